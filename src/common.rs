@@ -1,23 +1,253 @@
+//! Types and constants shared between the shaders and the host.
 use bytemuck::{Pod, Zeroable};
+use glam::{Quat, Vec3, Vec4};
 
 #[include_wgsl_oil::include_wgsl_oil("common.wgsl")]
 pub mod common {}
 
-pub use common::constants::GRID_SIZE::VALUE as GRID_SIZE;
-pub use common::constants::MAX_PARTICLES::VALUE as MAX_PARTICLES;
-pub use common::constants::MAX_PARTICLES_PER_GRID_CELL::VALUE as MAX_PARTICLES_PER_GRID_CELL;
+pub use common::constants::DEFAULT_PARTICLE_MASS::VALUE as DEFAULT_PARTICLE_MASS;
+pub use common::constants::DEFAULT_PARTICLE_RADIUS::VALUE as DEFAULT_PARTICLE_RADIUS;
+pub use common::constants::GRID_MARGIN::VALUE as GRID_MARGIN;
+pub use common::constants::MASS_GRID_TOP_SIZE::VALUE as MASS_GRID_TOP_SIZE;
+pub use common::constants::MAX_CONSTRAINTS_PER_PARTICLE::VALUE as MAX_CONSTRAINTS_PER_PARTICLE;
+pub use common::constants::MAX_MATERIALS::VALUE as MAX_MATERIALS;
+pub use common::constants::NO_CLUSTER::VALUE as NO_CLUSTER;
+pub use common::constants::PARTICLE_WORKGROUP_SIZE::VALUE as PARTICLE_WORKGROUP_SIZE;
+pub use common::constants::PHASE_FLUID::VALUE as PHASE_FLUID;
+pub use common::constants::PHASE_GRANULAR::VALUE as PHASE_GRANULAR;
 
+/// Runtime sizes shared by every shader, written from a [`crate::resources::Configuration`].
+pub use common::types::Parameters;
+unsafe impl Pod for Parameters {}
+unsafe impl Zeroable for Parameters {}
+impl Copy for Parameters {}
+
+/// A particle, stored as its current and previous position for Verlet integration, along with its
+/// radius and mass, which must be positive, and the index of its [`Material`].
+///
+/// Slots that hold no particle are zeroed, which makes them dead.
 pub use common::types::Particle;
 unsafe impl Pod for Particle {}
 unsafe impl Zeroable for Particle {}
 impl Copy for Particle {}
 
+impl Particle {
+    /// A particle at rest at `position`, made of the first material.
+    pub fn new(position: Vec3, radius: f32, mass: f32) -> Self {
+        Particle {
+            position,
+            radius,
+            old_position: position,
+            mass,
+            material: 0,
+            alive: 1,
+        }
+    }
+
+    /// Whether the particle is simulated, rather than filling a free slot.
+    pub fn is_alive(&self) -> bool {
+        self.alive != 0
+    }
+}
+
+/// How many particle slots are in use and how many particles are alive, as tracked on the GPU by
+/// [`crate::Emission`].
+pub use common::types::ParticleCounters;
+unsafe impl Pod for ParticleCounters {}
+unsafe impl Zeroable for ParticleCounters {}
+impl Copy for ParticleCounters {}
+
+impl ParticleCounters {
+    /// The counters for the first `particle_count` slots, of which those in `free_slots` are dead.
+    pub fn new(particle_count: u32, free_slots: usize) -> Self {
+        ParticleCounters {
+            workgroup_count_x: particle_count.div_ceil(PARTICLE_WORKGROUP_SIZE),
+            workgroup_count_y: 1,
+            workgroup_count_z: 1,
+            particle_count,
+            alive_count: particle_count - free_slots as u32,
+            free_count: free_slots as u32,
+            emitted_count: 0,
+        }
+    }
+}
+
+/// The physical properties and render colour shared by every particle with the same material index.
+///
+/// Materials are uploaded with [`crate::Resources::set_materials`].
+pub use common::types::Material;
+unsafe impl Pod for Material {}
+unsafe impl Zeroable for Material {}
+impl Copy for Material {}
+
+impl Default for Material {
+    /// Sand coloured and granular, with the density of a default particle.
+    fn default() -> Self {
+        Material {
+            colour: Vec3::new(1.0, 0.5, 0.3),
+            static_friction: 0.6,
+            kinetic_friction: 0.5,
+            drag: 0.0,
+            restitution: 0.0,
+            density: DEFAULT_PARTICLE_MASS / sphere_volume(DEFAULT_PARTICLE_RADIUS),
+            phase: PHASE_GRANULAR,
+            stiffness: 1000.0,
+            viscosity: 0.5,
+            surface_tension: 0.5,
+        }
+    }
+}
+
+impl Material {
+    /// A water coloured fluid, with the density of a default particle.
+    pub fn fluid() -> Self {
+        Material {
+            colour: Vec3::new(0.2, 0.4, 1.0),
+            static_friction: 0.01,
+            kinetic_friction: 0.01,
+            phase: PHASE_FLUID,
+            ..Default::default()
+        }
+    }
+
+    /// The mass of a particle of `radius` made of this material.
+    ///
+    /// Fluid particles settle a diameter apart, filling the cube around them rather than just their
+    /// sphere, so that a fluid at rest has the density of its material.
+    pub fn particle_mass(&self, radius: f32) -> f32 {
+        if self.phase == PHASE_FLUID {
+            self.density * (2.0 * radius).powi(3)
+        } else {
+            self.density * sphere_volume(radius)
+        }
+    }
+}
+
+fn sphere_volume(radius: f32) -> f32 {
+    4.0 / 3.0 * std::f32::consts::PI * radius.powi(3)
+}
+
+/// Keeps two particles a fixed distance apart, until it is stretched beyond its break strain.
+///
+/// Constraints are uploaded with [`crate::Resources::set_constraints`].
+pub use common::types::DistanceConstraint;
+unsafe impl Pod for DistanceConstraint {}
+unsafe impl Zeroable for DistanceConstraint {}
+impl Copy for DistanceConstraint {}
+
+impl DistanceConstraint {
+    /// An unbreakable constraint between the particles at indices `a` and `b`.
+    pub fn new(a: u32, b: u32, rest_length: f32, stiffness: f32) -> Self {
+        DistanceConstraint {
+            a,
+            b,
+            rest_length,
+            stiffness,
+            break_strain: 0.0,
+            broken: 0,
+        }
+    }
+}
+
+/// The indices of the [`DistanceConstraint`]s that a particle is part of, as written by
+/// [`crate::Resources::set_constraints`].
+pub use common::types::ParticleConstraints;
+unsafe impl Pod for ParticleConstraints {}
+unsafe impl Zeroable for ParticleConstraints {}
+impl Copy for ParticleConstraints {}
+
+/// A run of consecutive particles that move together as one rigid body, by being matched to their
+/// rest shape every substep.
+///
+/// Clusters are uploaded with [`crate::Resources::set_clusters`].
+pub use common::types::Cluster;
+unsafe impl Pod for Cluster {}
+unsafe impl Zeroable for Cluster {}
+impl Copy for Cluster {}
+
+impl Cluster {
+    /// A cluster of `members`, the particles from index `first_particle` onwards, that holds them
+    /// in their current arrangement.
+    pub fn new(members: &[Particle], first_particle: u32, stiffness: f32) -> Self {
+        assert!(!members.is_empty(), "a cluster needs at least one member");
+        let mass: f32 = members.iter().map(|particle| particle.mass).sum();
+        let centre = members
+            .iter()
+            .map(|particle| particle.position * particle.mass)
+            .sum::<Vec3>()
+            / mass;
+        Cluster {
+            centre,
+            first_particle,
+            rotation: Vec4::from(Quat::IDENTITY),
+            particle_count: members.len() as u32,
+            stiffness,
+        }
+    }
+
+    /// The offset of each of `members` from the centre of the cluster, which is the rest shape that
+    /// [`crate::Resources::set_clusters`] expects when `members` are the particles the cluster was
+    /// made of.
+    pub fn rest_offsets(&self, members: &[Particle]) -> Vec<Vec3> {
+        members
+            .iter()
+            .map(|particle| particle.position - self.centre)
+            .collect()
+    }
+}
+
+/// The cluster that a particle is a member of, as written by [`crate::Resources::set_clusters`].
+pub use common::types::ClusterParticle;
+unsafe impl Pod for ClusterParticle {}
+unsafe impl Zeroable for ClusterParticle {}
+impl Copy for ClusterParticle {}
+
+/// The box that the simulation keeps every particle inside of, walls and all, unlike the [`Bounds`]
+/// that the particles are partitioned within.
+///
+/// The container is set with [`crate::Resources::set_container`].
+pub use common::types::Container;
+unsafe impl Pod for Container {}
+unsafe impl Zeroable for Container {}
+impl Copy for Container {}
+
+impl Container {
+    /// The axis-aligned box from `min` to `max`.
+    pub fn aabb(min: Vec3, max: Vec3) -> Self {
+        Self::oriented((min + max) * 0.5, (max - min) * 0.5, Quat::IDENTITY)
+    }
+
+    /// The box centred on `centre` that extends `half_size` either side of it along each of its
+    /// axes, which are rotated by `rotation`.
+    pub fn oriented(centre: Vec3, half_size: Vec3, rotation: Quat) -> Self {
+        assert!(
+            half_size.cmpge(Vec3::ZERO).all(),
+            "a container cannot be inside out"
+        );
+        Container {
+            centre,
+            enabled: 1,
+            rotation: Vec4::from(rotation),
+            half_size,
+        }
+    }
+}
+
+/// Integer axis-aligned bounds that the particles are partitioned within.
 pub use common::types::Bounds;
 unsafe impl Pod for Bounds {}
 unsafe impl Zeroable for Bounds {}
 impl Copy for Bounds {}
 
+/// Number of particles that overlap a single cell of the grid partition.
 pub use common::types::GridCell;
 unsafe impl Pod for GridCell {}
 unsafe impl Zeroable for GridCell {}
 impl Copy for GridCell {}
+
+/// The total mass and centre of mass of the particles whose centres are within a cell of the mass
+/// grid, see [`crate::MassPartition`].
+pub use common::types::MassCell;
+unsafe impl Pod for MassCell {}
+unsafe impl Zeroable for MassCell {}
+impl Copy for MassCell {}
@@ -1,11 +1,143 @@
-const GRID_SIZE = 16u;
-const MAX_PARTICLES = 512u; 
-const MAX_PARTICLES_PER_GRID_CELL = MAX_PARTICLES / GRID_SIZE;
-const PARTICLE_RADIUS = 0.8;
+// Radius and mass of particles that are not given their own
+const DEFAULT_PARTICLE_RADIUS = 0.8;
+const DEFAULT_PARTICLE_MASS = 1.0;
 
+// How far particles can move within a step before the grid built at the start of the step stops
+// finding all of their contacts
+const GRID_MARGIN = 0.25;
+
+// Number of entries in the material table
+const MAX_MATERIALS = 16u;
+
+// How the particles of a material interact with each other
+const PHASE_GRANULAR = 0u;
+const PHASE_FLUID = 1u;
+
+@export struct Parameters {
+  // Number of particle slots in use at the start of the particle buffer, some of which may be dead
+  particle_count: u32,
+  max_particles_per_grid_cell: u32,
+  grid_size: vec3<u32>,
+}
+
+// `radius` and `mass` fill the padding after each position
 @export struct Particle {
   position: vec3<f32>,
+  radius: f32,
   old_position: vec3<f32>,
+  mass: f32,
+  // Index into the material table
+  material: u32,
+  // Non-zero while the particle is simulated, zero once it is deleted and its slot is free
+  alive: u32,
+}
+
+// Width of the workgroups of the kernels that run once per particle slot
+const PARTICLE_WORKGROUP_SIZE = 64u;
+
+// Tracks which particle slots are in use as particles are emitted and deleted on the GPU
+@export struct ParticleCounters {
+  // Arguments of the indirect dispatches over the slots in use, `PARTICLE_WORKGROUP_SIZE` slots per
+  // workgroup
+  workgroup_count_x: u32,
+  workgroup_count_y: u32,
+  workgroup_count_z: u32,
+  // Number of slots in use at the start of the particle buffer, live or not, which is copied into
+  // `Parameters::particle_count`
+  particle_count: u32,
+  // Number of live particles
+  alive_count: u32,
+  // Number of free slots below `particle_count`, listed in ascending order at the start of
+  // `free_slots`
+  free_count: u32,
+  // Number of particles emitted since the particles were populated, which seeds where the next one
+  // goes
+  emitted_count: u32,
+}
+
+@export struct Material {
+  colour: vec3<f32>,
+  // Coulomb friction coefficient below which contacts stick, as the ratio of the tangential motion
+  // at a contact to its penetration
+  static_friction: f32,
+  // Coulomb friction coefficient that resists contacts once they slide
+  kinetic_friction: f32,
+  // Coefficient of the air drag that opposes the particle's velocity, 0 for none
+  drag: f32,
+  // Coefficient of restitution (bounciness)
+  restitution: f32,
+  // Mass per unit volume, used to derive the mass of the particles spawned with this material, and
+  // the rest density of fluids
+  density: f32,
+  // One of the `PHASE_*` constants
+  phase: u32,
+  // Pressure per unit of density above the rest density, for fluids
+  stiffness: f32,
+  // Dynamic viscosity, for fluids
+  viscosity: f32,
+  // Strength of the cohesion between neighbouring particles, for fluids
+  surface_tension: f32,
+}
+
+// Number of distance constraints a single particle can be part of
+const MAX_CONSTRAINTS_PER_PARTICLE = 16u;
+
+// Keeps two particles `rest_length` apart
+@export struct DistanceConstraint {
+  // Indices of the constrained particles
+  a: u32,
+  b: u32,
+  rest_length: f32,
+  // Fraction of the error that is corrected each iteration, from 0 to 1
+  stiffness: f32,
+  // Stretch, as a fraction of `rest_length`, beyond which the constraint breaks, or 0 if it never
+  // breaks
+  break_strain: f32,
+  // Non-zero once broken, after which the constraint is ignored
+  broken: u32,
+}
+
+// The indices of the constraints that a particle is part of
+@export struct ParticleConstraints {
+  count: u32,
+  constraints: array<u32, MAX_CONSTRAINTS_PER_PARTICLE>,
+}
+
+// The cluster of a particle that is not part of one
+const NO_CLUSTER = 4294967295u;
+
+// Particles `first_particle` to `first_particle + particle_count` that move together as one rigid
+// body
+@export struct Cluster {
+  // Centre of mass of the members, as of the last time they were matched to the rigid shape
+  centre: vec3<f32>,
+  first_particle: u32,
+  // Rotation of the rigid shape from its rest orientation, as a quaternion, also used as the
+  // starting point for finding the next rotation
+  rotation: vec4<f32>,
+  particle_count: u32,
+  // Fraction of the way that members are moved onto the rigid shape each substep, from 0 to 1
+  stiffness: f32,
+}
+
+// The cluster that a particle is a member of, and where it sits within the rigid shape
+@export struct ClusterParticle {
+  // Offset from the centre of mass of the cluster at rest
+  rest_offset: vec3<f32>,
+  // Index of the cluster, or `NO_CLUSTER`
+  cluster: u32,
+}
+
+// The box that particles are kept inside of, as an axis-aligned box centred on the origin that is
+// rotated and then moved into place
+@export struct Container {
+  centre: vec3<f32>,
+  // Non-zero if particles are kept inside the container, otherwise they are free to go anywhere
+  enabled: u32,
+  // Rotation of the box away from being axis-aligned, as a quaternion
+  rotation: vec4<f32>,
+  // Distance from the centre to the walls along each axis of the box
+  half_size: vec3<f32>,
 }
 
 @export struct Bounds {
@@ -17,22 +149,91 @@ const PARTICLE_RADIUS = 0.8;
   max_z: i32,
 }
 
+// The indices of the particles within a cell are stored separately in `grid_particles`, starting at
+// `grid_index * max_particles_per_grid_cell`
 @export struct GridCell {
-  particles: array<u32, MAX_PARTICLES_PER_GRID_CELL>,
   particles_length: u32,
 }
 
-fn grid_position_to_grid_index(position: vec3<i32>) -> i32 {
-  let grid_size = i32(GRID_SIZE);
-  return position.x + position.y * grid_size + position.z * grid_size * grid_size;
+fn grid_position_to_grid_index(position: vec3<i32>, parameters: Parameters) -> i32 {
+  let grid_size = vec3<i32>(parameters.grid_size);
+  return position.x + position.y * grid_size.x + position.z * grid_size.x * grid_size.y;
+}
+
+fn grid_particle_index(grid_index: i32, cell_particle_index: u32, parameters: Parameters) -> u32 {
+  return u32(grid_index) * parameters.max_particles_per_grid_cell + cell_particle_index;
 }
 
-fn world_position_to_grid_position(position: vec3<f32>, bounds: Bounds) -> vec3<i32> {
+fn world_position_to_grid_position(position: vec3<f32>, bounds: Bounds, parameters: Parameters) -> vec3<i32> {
   let bounds_min = vec3<f32>(vec3<i32>(bounds.min_x, bounds.min_y, bounds.min_z));
   let bounds_max = vec3<f32>(vec3<i32>(bounds.max_x, bounds.max_y, bounds.max_z));
-  return vec3<i32>(round((position - bounds_min) / (bounds_max - bounds_min) * f32(GRID_SIZE - 1)));
+  return vec3<i32>(round((position - bounds_min) / (bounds_max - bounds_min) * vec3<f32>(parameters.grid_size - 1u)));
 }
 
-fn world_position_to_grid_index(position: vec3<f32>, bounds: Bounds) -> i32 {
-  return grid_position_to_grid_index(world_position_to_grid_position(position, bounds));
-}
\ No newline at end of file
+fn world_position_to_grid_index(position: vec3<f32>, bounds: Bounds, parameters: Parameters) -> i32 {
+  return grid_position_to_grid_index(world_position_to_grid_position(position, bounds, parameters), parameters);
+}
+
+// The cells from `min` to `max` inclusive
+struct GridRange {
+  min: vec3<i32>,
+  max: vec3<i32>,
+}
+
+// The cells overlapped by the bounding box of a sphere, clamped to the grid
+fn world_sphere_to_grid_range(position: vec3<f32>, radius: f32, bounds: Bounds, parameters: Parameters) -> GridRange {
+  let grid_min = vec3<i32>(0);
+  let grid_max = vec3<i32>(parameters.grid_size) - 1;
+  var range: GridRange;
+  range.min = clamp(world_position_to_grid_position(position - radius, bounds, parameters), grid_min, grid_max);
+  range.max = clamp(world_position_to_grid_position(position + radius, bounds, parameters), grid_min, grid_max);
+  return range;
+}
+
+// The coarsest level of the mass grid is the first with at most this many cells along every axis
+const MASS_GRID_TOP_SIZE = 4u;
+
+// The particles whose centres are within a cell of the mass grid, as a single point mass. The mass
+// grid is a pyramid of grids, the first of which matches the grid, and each of which after that
+// merges 2x2x2 cells of the first into one
+@export struct MassCell {
+  // Centre of mass, or the origin if the cell is empty
+  centre: vec3<f32>,
+  mass: f32,
+}
+
+// Number of cells along each axis of the `level`th grid of the mass grid
+fn mass_grid_size(level: u32, parameters: Parameters) -> vec3<u32> {
+  let scale = 1u << level;
+  return (parameters.grid_size + scale - 1u) / scale;
+}
+
+fn mass_grid_top_level(parameters: Parameters) -> u32 {
+  var level = 0u;
+  while (any(mass_grid_size(level, parameters) > vec3<u32>(MASS_GRID_TOP_SIZE))) {
+    level++;
+  }
+  return level;
+}
+
+// Index of the first cell of the `level`th grid of the mass grid
+fn mass_grid_offset(level: u32, parameters: Parameters) -> u32 {
+  var offset = 0u;
+  for (var i = 0u; i < level; i++) {
+    let size = mass_grid_size(i, parameters);
+    offset += size.x * size.y * size.z;
+  }
+  return offset;
+}
+
+fn mass_cell_index(position: vec3<i32>, level: u32, parameters: Parameters) -> u32 {
+  let size = vec3<i32>(mass_grid_size(level, parameters));
+  let index = position.x + position.y * size.x + position.z * size.x * size.y;
+  return mass_grid_offset(level, parameters) + u32(index);
+}
+
+// The cell of the grid that the centre of a particle at `position` is in, clamped to the grid
+fn world_position_to_clamped_grid_position(position: vec3<f32>, bounds: Bounds, parameters: Parameters) -> vec3<i32> {
+  let grid_position = world_position_to_grid_position(position, bounds, parameters);
+  return clamp(grid_position, vec3<i32>(0), vec3<i32>(parameters.grid_size) - 1);
+}
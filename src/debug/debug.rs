@@ -1,31 +1,45 @@
+use crate::error::SolError;
+use crate::wgpu_utilities::{submit, Device};
 use encase::{internal::CreateFrom, ShaderType, StorageBuffer};
-use wgpu::{Buffer, Device, Queue};
+use std::sync::mpsc;
+use wgpu::{Buffer, Queue};
 
-pub fn debug_buffer<T: ShaderType + CreateFrom>(
+/// Copies `buffer` into a staging buffer and decodes its contents as `T` once the GPU has finished.
+///
+/// `buffer` must have been created with `BufferUsages::COPY_SRC`.
+pub fn read_buffer<T: ShaderType + CreateFrom>(
     device: &Device,
     queue: &Queue,
     buffer: &Buffer,
-) -> T {
-    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        size: buffer.size(),
-        label: Some("Simulation::staging_buffer"),
-        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
+) -> Result<T, SolError> {
+    let (staging_buffer, command_buffer) = device.capture_errors(|| {
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            size: buffer.size(),
+            label: Some("read_buffer::staging_buffer"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
-    let mut command_encoder =
-        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-    command_encoder.copy_buffer_to_buffer(&buffer, 0, &staging_buffer, 0, buffer.size());
-    queue.submit(core::iter::once(command_encoder.finish()));
+        let mut command_encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        command_encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
+        (staging_buffer, command_encoder.finish())
+    })?;
+    submit(device, queue, command_buffer)?;
 
     let buffer_slice = staging_buffer.slice(..);
-    buffer_slice.map_async(wgpu::MapMode::Read, |_| {});
+    let (sender, receiver) = mpsc::channel();
+    buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
 
     device.poll(wgpu::Maintain::Wait);
 
+    // The callback is dropped without being called if the device is lost before the buffer is
+    // mapped
+    receiver.recv().map_err(|_| SolError::DeviceLost)??;
     let output = buffer_slice.get_mapped_range().to_vec();
     staging_buffer.unmap();
 
-    let result = StorageBuffer::new(output).create().unwrap();
-    return result;
+    Ok(StorageBuffer::new(output).create()?)
 }
@@ -1,2 +1,3 @@
+//! Utilities for inspecting GPU buffers from the CPU.
 mod debug;
-pub use debug::debug_buffer;
+pub use debug::read_buffer;
@@ -0,0 +1,496 @@
+use crate::common::{Material, ParticleCounters, DEFAULT_PARTICLE_MASS, DEFAULT_PARTICLE_RADIUS};
+use crate::error::SolError;
+use crate::resources::Resources;
+use crate::sdf::{SHAPE_BOX, SHAPE_CAPSULE, SHAPE_PLANE, SHAPE_SPHERE};
+use crate::wgpu_utilities::{submit, Device, QueueUtilities};
+use bytemuck::{Pod, Zeroable};
+use encase::{ShaderSize, ShaderType};
+use glam::{Quat, Vec3, Vec4};
+use std::borrow::Cow;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages,
+};
+
+#[include_wgsl_oil::include_wgsl_oil("emission.wgsl")]
+mod shader {}
+use shader::constants::EMITTER_BOX::VALUE as EMITTER_BOX;
+use shader::constants::EMITTER_DISC::VALUE as EMITTER_DISC;
+use shader::constants::EMITTER_POINT::VALUE as EMITTER_POINT;
+pub use shader::constants::MAX_EMITTERS::VALUE as MAX_EMITTERS;
+pub use shader::constants::MAX_SINKS::VALUE as MAX_SINKS;
+use shader::types::{EmissionUniforms, EmitterState};
+unsafe impl Pod for EmissionUniforms {}
+unsafe impl Zeroable for EmissionUniforms {}
+impl Copy for EmissionUniforms {}
+
+/// Adds particles at a steady rate, spread evenly over a point, disc or box.
+pub use shader::types::Emitter;
+unsafe impl Pod for Emitter {}
+unsafe impl Zeroable for Emitter {}
+impl Copy for Emitter {}
+
+impl Emitter {
+    /// Emits `rate` default particles per second at rest at `position`, made of the first material.
+    pub fn point(position: Vec3, rate: f32) -> Self {
+        Self::new(EMITTER_POINT, position, Vec3::ZERO, rate)
+    }
+
+    /// Emits `rate` particles per second across a horizontal disc of `radius` centred on
+    /// `position`.
+    pub fn disc(position: Vec3, radius: f32, rate: f32) -> Self {
+        Self::new(EMITTER_DISC, position, Vec3::new(radius, 0.0, 0.0), rate)
+    }
+
+    /// Emits `rate` particles per second within a box centred on `position` that extends
+    /// `half_size` either side of it along each axis.
+    pub fn cuboid(position: Vec3, half_size: Vec3, rate: f32) -> Self {
+        Self::new(EMITTER_BOX, position, half_size, rate)
+    }
+
+    fn new(shape: u32, position: Vec3, size: Vec3, rate: f32) -> Self {
+        assert!(rate >= 0.0, "emission rate cannot be negative");
+        Emitter {
+            position,
+            shape,
+            rotation: Vec4::from(Quat::IDENTITY),
+            size,
+            rate,
+            velocity: Vec3::ZERO,
+            material: 0,
+            radius: DEFAULT_PARTICLE_RADIUS,
+            mass: DEFAULT_PARTICLE_MASS,
+        }
+    }
+
+    /// The same emitter rotated by `rotation` about its centre.
+    pub fn rotated(self, rotation: Quat) -> Self {
+        Emitter {
+            rotation: Vec4::from(rotation * Quat::from_vec4(self.rotation)),
+            ..self
+        }
+    }
+
+    /// The same emitter, emitting particles of `material`, at `material_index` in the material
+    /// table, whose mass is derived from its density.
+    pub fn made_of(self, material_index: u32, material: &Material) -> Self {
+        Emitter {
+            material: material_index,
+            mass: material.particle_mass(self.radius),
+            ..self
+        }
+    }
+}
+
+/// Deletes the particles whose centres enter it, freeing their slots for the emitters.
+pub use shader::types::Sink;
+unsafe impl Pod for Sink {}
+unsafe impl Zeroable for Sink {}
+impl Copy for Sink {}
+
+impl Sink {
+    /// A sphere of `radius` centred on `position`.
+    pub fn sphere(position: Vec3, radius: f32) -> Self {
+        Self::new(SHAPE_SPHERE, position, Vec3::new(radius, 0.0, 0.0))
+    }
+
+    /// A box centred on `position` that extends `half_size` either side of it along each axis.
+    pub fn cuboid(position: Vec3, half_size: Vec3) -> Self {
+        Self::new(SHAPE_BOX, position, half_size)
+    }
+
+    /// An upright capsule of `radius` centred on `position`, with the centres of its caps
+    /// `half_length` above and below it.
+    pub fn capsule(position: Vec3, half_length: f32, radius: f32) -> Self {
+        Self::new(SHAPE_CAPSULE, position, Vec3::new(radius, half_length, 0.0))
+    }
+
+    /// Everything behind the plane through `position` that faces `normal`.
+    pub fn plane(position: Vec3, normal: Vec3) -> Self {
+        Sink {
+            rotation: Vec4::from(Quat::from_rotation_arc(Vec3::Y, normal.normalize())),
+            ..Self::new(SHAPE_PLANE, position, Vec3::ZERO)
+        }
+    }
+
+    fn new(shape: u32, position: Vec3, size: Vec3) -> Self {
+        Sink {
+            position,
+            shape,
+            rotation: Vec4::from(Quat::IDENTITY),
+            size,
+        }
+    }
+
+    /// The same sink rotated by `rotation` about its centre.
+    pub fn rotated(self, rotation: Quat) -> Self {
+        Sink {
+            rotation: Vec4::from(rotation * Quat::from_vec4(self.rotation)),
+            ..self
+        }
+    }
+}
+
+/// Deletes the particles inside the [`Sink`]s, then fills free particle slots with the particles
+/// that the [`Emitter`]s are due to emit, updating the particle counters of [`Resources`].
+///
+/// Particles are emitted on a single invocation, into the lowest free slots last, so that the same
+/// particles go into the same slots every time. Emitters keep the fraction of a particle they are
+/// yet to emit between steps, so that low rates still emit at a steady rate. A particle that would
+/// overlap one of the last particles its emitter emitted is dropped, so emitters crowded by their
+/// own particles emit fewer than their rate rather than pushing them apart.
+pub struct Emission {
+    bind_group_layout: BindGroupLayout,
+    bind_groups: [BindGroup; 2],
+    resources_generation: u64,
+    sink_particles_pipeline: ComputePipeline,
+    emit_particles_pipeline: ComputePipeline,
+    uniform_buffer: Buffer,
+    emitter_buffer: Buffer,
+    sink_buffer: Buffer,
+    state_buffer: Buffer,
+    // The dispatch arguments of the particle counters of `Resources`, which cannot be dispatched
+    // from while the sinks change them
+    indirect_buffer: Buffer,
+    emitters: Vec<Emitter>,
+    sinks: Vec<Sink>,
+    emitters_changed: bool,
+}
+
+impl Drop for Emission {
+    fn drop(&mut self) {
+        self.uniform_buffer.destroy();
+        self.emitter_buffer.destroy();
+        self.sink_buffer.destroy();
+        self.state_buffer.destroy();
+        self.indirect_buffer.destroy();
+    }
+}
+
+impl Emission {
+    /// Creates the compute pipelines and binds the particle, particle counter and free slot buffers
+    /// of `resources`. There are no emitters or sinks until they are set.
+    pub fn new(device: &Device, resources: &Resources) -> Result<Self, SolError> {
+        device.capture_errors(|| Self::create(device, resources))
+    }
+
+    fn create(device: &Device, resources: &Resources) -> Self {
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(Cow::Borrowed(shader::SOURCE)),
+        });
+
+        let storage = BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        let uniform = BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                (shader::globals::uniforms::binding::BINDING, uniform),
+                (shader::globals::particles::binding::BINDING, storage),
+                (shader::globals::counters::binding::BINDING, storage),
+                (shader::globals::free_slots::binding::BINDING, storage),
+                (shader::globals::emitters::binding::BINDING, uniform),
+                (shader::globals::sinks::binding::BINDING, uniform),
+                (shader::globals::emitter_states::binding::BINDING, storage),
+            ]
+            .map(|(binding, ty)| BindGroupLayoutEntry {
+                binding,
+                visibility: ShaderStages::COMPUTE,
+                ty,
+                count: None,
+            }),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let [sink_particles_pipeline, emit_particles_pipeline] = [
+            shader::entry_points::sink_particles::NAME,
+            shader::entry_points::emit_particles::NAME,
+        ]
+        .map(|entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point,
+            })
+        });
+
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: EmissionUniforms::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let emitter_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Emission::emitter_buffer"),
+            size: Emitter::SHADER_SIZE.get() * MAX_EMITTERS as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sink_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Emission::sink_buffer"),
+            size: Sink::SHADER_SIZE.get() * MAX_SINKS as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let state_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Emission::state_buffer"),
+            size: EmitterState::min_size().get() * MAX_EMITTERS as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let indirect_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: ParticleCounters::SHADER_SIZE.get(),
+            usage: BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            [
+                &uniform_buffer,
+                &emitter_buffer,
+                &sink_buffer,
+                &state_buffer,
+            ],
+            resources,
+        );
+
+        Emission {
+            bind_group_layout,
+            bind_groups,
+            resources_generation: resources.generation(),
+            sink_particles_pipeline,
+            emit_particles_pipeline,
+            uniform_buffer,
+            emitter_buffer,
+            sink_buffer,
+            state_buffer,
+            indirect_buffer,
+            emitters: Vec::new(),
+            sinks: Vec::new(),
+            emitters_changed: false,
+        }
+    }
+
+    /// One bind group for each of the particle buffers of `resources`, binding the uniform,
+    /// emitter, sink and state buffers of the stage.
+    fn create_bind_groups(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        [uniform_buffer, emitter_buffer, sink_buffer, state_buffer]: [&Buffer; 4],
+        resources: &Resources,
+    ) -> [BindGroup; 2] {
+        resources
+            .particle_buffers()
+            .each_ref()
+            .map(|particle_buffer| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: None,
+                    layout: bind_group_layout,
+                    entries: &[
+                        (shader::globals::uniforms::binding::BINDING, uniform_buffer),
+                        (
+                            shader::globals::particles::binding::BINDING,
+                            particle_buffer,
+                        ),
+                        (
+                            shader::globals::counters::binding::BINDING,
+                            resources.particle_counters_buffer(),
+                        ),
+                        (
+                            shader::globals::free_slots::binding::BINDING,
+                            resources.free_slots_buffer(),
+                        ),
+                        (shader::globals::emitters::binding::BINDING, emitter_buffer),
+                        (shader::globals::sinks::binding::BINDING, sink_buffer),
+                        (
+                            shader::globals::emitter_states::binding::BINDING,
+                            state_buffer,
+                        ),
+                    ]
+                    .map(|(binding, buffer)| BindGroupEntry {
+                        binding,
+                        resource: buffer.as_entire_binding(),
+                    }),
+                })
+            })
+    }
+
+    /// Rebuilds the bind groups if the buffers of `resources` have been reallocated since they were
+    /// created.
+    fn update_bind_groups(&mut self, device: &Device, resources: &Resources) {
+        if self.resources_generation != resources.generation() {
+            self.bind_groups = Self::create_bind_groups(
+                device,
+                &self.bind_group_layout,
+                [
+                    &self.uniform_buffer,
+                    &self.emitter_buffer,
+                    &self.sink_buffer,
+                    &self.state_buffer,
+                ],
+                resources,
+            );
+            self.resources_generation = resources.generation();
+        }
+    }
+
+    /// Replaces the emitters, which start emitting from the next step without any fraction of a
+    /// particle carried over.
+    ///
+    /// There can be at most [`MAX_EMITTERS`] emitters, otherwise
+    /// [`SolError::InvalidConfiguration`] is returned.
+    pub fn set_emitters(&mut self, emitters: &[Emitter]) -> Result<(), SolError> {
+        if emitters.len() > MAX_EMITTERS as usize {
+            return Err(SolError::InvalidConfiguration(format!(
+                "{} emitters exceed the maximum of {MAX_EMITTERS}",
+                emitters.len()
+            )));
+        }
+        self.emitters = emitters.to_vec();
+        self.emitters_changed = true;
+        Ok(())
+    }
+
+    pub fn emitters(&self) -> &[Emitter] {
+        &self.emitters
+    }
+
+    /// Replaces the sinks, which delete the particles inside them from the next step.
+    ///
+    /// There can be at most [`MAX_SINKS`] sinks, otherwise [`SolError::InvalidConfiguration`] is
+    /// returned.
+    pub fn set_sinks(&mut self, sinks: &[Sink]) -> Result<(), SolError> {
+        if sinks.len() > MAX_SINKS as usize {
+            return Err(SolError::InvalidConfiguration(format!(
+                "{} sinks exceed the maximum of {MAX_SINKS}",
+                sinks.len()
+            )));
+        }
+        self.sinks = sinks.to_vec();
+        Ok(())
+    }
+
+    pub fn sinks(&self) -> &[Sink] {
+        &self.sinks
+    }
+
+    /// Records deleting the particles inside the sinks and emitting the particles due over
+    /// `delta_time` into `command_encoder`. Records nothing if there are no emitters or sinks.
+    ///
+    /// Emitted particles move at the velocity of their emitter over each of the `substeps` of the
+    /// simulation. The particle count of the parameters of `resources` is updated on the GPU, so
+    /// [`crate::resources::Configuration::particle_count`] no longer reflects it. The uniforms,
+    /// emitters and sinks are written through `queue`, so they take effect when `command_encoder`
+    /// is next submitted.
+    pub fn emit_with_encoder(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_encoder: &mut CommandEncoder,
+        resources: &Resources,
+        delta_time: f32,
+        substeps: u32,
+    ) {
+        if self.emitters.is_empty() && self.sinks.is_empty() {
+            return;
+        }
+        self.update_bind_groups(device, resources);
+
+        let uniforms = EmissionUniforms {
+            delta_time,
+            substep_delta_time: delta_time / substeps as f32,
+            emitter_count: self.emitters.len() as u32,
+            sink_count: self.sinks.len() as u32,
+            particle_capacity: resources.configuration().particle_capacity,
+        };
+        queue.write_encased_uniform_buffer(&self.uniform_buffer, uniforms);
+        let mut emitter_table = [Emitter::zeroed(); MAX_EMITTERS as usize];
+        emitter_table[..self.emitters.len()].copy_from_slice(&self.emitters);
+        queue.write_encased_uniform_buffer(&self.emitter_buffer, emitter_table);
+        let mut sink_table = [Sink::zeroed(); MAX_SINKS as usize];
+        sink_table[..self.sinks.len()].copy_from_slice(&self.sinks);
+        queue.write_encased_uniform_buffer(&self.sink_buffer, sink_table);
+
+        if std::mem::take(&mut self.emitters_changed) {
+            command_encoder.clear_buffer(&self.state_buffer, 0, None);
+        }
+        command_encoder.copy_buffer_to_buffer(
+            resources.particle_counters_buffer(),
+            0,
+            &self.indirect_buffer,
+            0,
+            ParticleCounters::SHADER_SIZE.get(),
+        );
+
+        {
+            let mut compute_pass =
+                command_encoder.begin_compute_pass(&ComputePassDescriptor { label: None });
+            compute_pass.set_bind_group(
+                0,
+                &self.bind_groups[resources.particle_buffer_index()],
+                &[],
+            );
+            if !self.sinks.is_empty() {
+                compute_pass.set_pipeline(&self.sink_particles_pipeline);
+                compute_pass.dispatch_workgroups_indirect(&self.indirect_buffer, 0);
+            }
+            compute_pass.set_pipeline(&self.emit_particles_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        // The particle count follows the three dispatch arguments of the counters, and is the first
+        // member of the parameters
+        command_encoder.copy_buffer_to_buffer(
+            resources.particle_counters_buffer(),
+            3 * std::mem::size_of::<u32>() as u64,
+            resources.parameters_buffer(),
+            0,
+            std::mem::size_of::<u32>() as u64,
+        );
+    }
+
+    /// Deletes and emits particles and submits the work to `queue`.
+    pub fn emit(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        resources: &Resources,
+        delta_time: f32,
+        substeps: u32,
+    ) -> Result<(), SolError> {
+        let command_buffer = device.capture_errors(|| {
+            let mut command_encoder =
+                device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            self.emit_with_encoder(
+                device,
+                queue,
+                &mut command_encoder,
+                resources,
+                delta_time,
+                substeps,
+            );
+            command_encoder.finish()
+        })?;
+        submit(device, queue, command_buffer)
+    }
+}
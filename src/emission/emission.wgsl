@@ -0,0 +1,293 @@
+#import ../common.wgsl as Common
+#import ../sdf.wgsl as Sdf
+
+// Number of entries in the emitter and sink tables
+const MAX_EMITTERS = 8u;
+const MAX_SINKS = 8u;
+
+// Number of the last particles of each emitter that a new particle is not emitted on top of
+const RECENT_PARTICLES = 16u;
+// Marks an entry of `EmitterState::recent_slots` whose particle has been deleted
+const NO_SLOT = 4294967295u;
+
+// The region that an emitter places its particles in, in its local space
+const EMITTER_POINT = 0u;
+// Across the local xz plane
+const EMITTER_DISC = 1u;
+const EMITTER_BOX = 2u;
+
+@export struct Emitter {
+  position: vec3<f32>,
+  // One of the `EMITTER_*` constants
+  shape: u32,
+  // Rotation from local to world space, as a quaternion
+  rotation: vec4<f32>,
+  // The radius of a disc in `x`, the half extents of a box, and unused for a point
+  size: vec3<f32>,
+  // Particles emitted per second
+  rate: f32,
+  // World space velocity of the emitted particles
+  velocity: vec3<f32>,
+  // Index into the material table
+  material: u32,
+  radius: f32,
+  mass: f32,
+}
+
+// Deletes the particles whose centres are inside it
+@export struct Sink {
+  position: vec3<f32>,
+  // One of the `Sdf::SHAPE_*` constants
+  shape: u32,
+  // Rotation from local to world space, as a quaternion
+  rotation: vec4<f32>,
+  // As `Sdf::Collider::size`
+  size: vec3<f32>,
+}
+
+@export struct EmissionUniforms {
+  // Length of the step in seconds
+  delta_time: f32,
+  // Length of each substep of the simulation, over which the initial velocity is set
+  substep_delta_time: f32,
+  // Number of entries in `emitters`
+  emitter_count: u32,
+  // Number of entries in `sinks`
+  sink_count: u32,
+  // Number of particle slots in the particle buffers
+  particle_capacity: u32,
+}
+
+// `Common::ParticleCounters`, updated atomically
+struct AtomicParticleCounters {
+  workgroup_count_x: atomic<u32>,
+  workgroup_count_y: atomic<u32>,
+  workgroup_count_z: atomic<u32>,
+  particle_count: atomic<u32>,
+  alive_count: atomic<u32>,
+  free_count: atomic<u32>,
+  emitted_count: atomic<u32>,
+}
+
+@group(0)
+@binding(0)
+var<uniform> uniforms: EmissionUniforms;
+
+// The current particle buffer, which is changed in place
+@group(0)
+@binding(1)
+var<storage, read_write> particles: array<Common::Particle>;
+
+@group(0)
+@binding(2)
+var<storage, read_write> counters: AtomicParticleCounters;
+
+@group(0)
+@binding(3)
+var<storage, read_write> free_slots: array<u32>;
+
+@group(0)
+@binding(4)
+var<uniform> emitters: array<Emitter, MAX_EMITTERS>;
+
+@group(0)
+@binding(5)
+var<uniform> sinks: array<Sink, MAX_SINKS>;
+
+// What each emitter carries over between steps
+@export struct EmitterState {
+  // The fraction of a particle that the emitter has yet to emit
+  accumulator: f32,
+  // Number of places the emitter has tried to emit a particle at, which is the index of the next
+  // place in its sequence
+  sequence_index: u32,
+  // The slots of the last particles emitted, in a ring indexed by the sequence index they were
+  // emitted at, or `NO_SLOT` once a sink has deleted them
+  recent_slots: array<u32, RECENT_PARTICLES>,
+  // Number of entries in `recent_slots`
+  recent_count: u32,
+}
+
+@group(0)
+@binding(6)
+var<storage, read_write> emitter_states: array<EmitterState, MAX_EMITTERS>;
+
+const TAU = 6.28318530718;
+
+// Deletes every live particle inside a sink, freeing its slot
+@compute
+@workgroup_size(64)
+fn sink_particles(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+  let particle_index = global_invocation_id.x;
+  if (particle_index >= atomicLoad(&counters.particle_count)) {
+    return;
+  }
+  var particle = particles[particle_index];
+  if (particle.alive == 0u || !inside_sinks(particle.position)) {
+    return;
+  }
+  particle.alive = 0u;
+  particles[particle_index] = particle;
+  // Listed in whatever order the atomics resolve, which `emit_particles` sorts
+  free_slots[atomicAdd(&counters.free_count, 1u)] = particle_index;
+  atomicSub(&counters.alive_count, 1u);
+  forget_recent_particle(particle_index);
+}
+
+// Removes a deleted particle from the recent particles of its emitter, so that the particle that is
+// emitted into its slot next is not mistaken for it
+fn forget_recent_particle(slot: u32) {
+  for (var emitter_index = 0u; emitter_index < uniforms.emitter_count; emitter_index++) {
+    for (var i = 0u; i < RECENT_PARTICLES; i++) {
+      if (emitter_states[emitter_index].recent_slots[i] == slot) {
+        emitter_states[emitter_index].recent_slots[i] = NO_SLOT;
+      }
+    }
+  }
+}
+
+fn inside_sinks(position: vec3<f32>) -> bool {
+  for (var i = 0u; i < uniforms.sink_count; i++) {
+    let sink = sinks[i];
+    let collider = Sdf::Collider(
+      sink.position,
+      sink.shape,
+      sink.rotation,
+      sink.size,
+      Sdf::OPERATION_UNION,
+      vec3<f32>(0.0),
+      0.0,
+    );
+    if (Sdf::collider_distance(collider, position) < 0.0) {
+      return true;
+    }
+  }
+  return false;
+}
+
+// Fills free slots, and then the slots past the end, with the particles that each emitter is due to
+// emit. Runs on a single invocation, so that the particles are emitted into the same slots every
+// time. A particle that would overlap one of the last particles of its emitter is dropped rather
+// than pushed apart from it, so crowded emitters emit less than their rate
+@compute
+@workgroup_size(1)
+fn emit_particles() {
+  var particle_count = atomicLoad(&counters.particle_count);
+  var free_count = atomicLoad(&counters.free_count);
+  var alive_count = atomicLoad(&counters.alive_count);
+  var emitted_count = atomicLoad(&counters.emitted_count);
+
+  // Insertion sort, as all but the slots freed this step are already in order
+  for (var i = 1u; i < free_count; i++) {
+    let slot = free_slots[i];
+    var j = i;
+    for (; j > 0u && free_slots[j - 1u] > slot; j--) {
+      free_slots[j] = free_slots[j - 1u];
+    }
+    free_slots[j] = slot;
+  }
+
+  // Free slots at the end are no longer in use at all, so that dispatches stop covering them
+  for (; free_count > 0u && free_slots[free_count - 1u] == particle_count - 1u; free_count--) {
+    particle_count--;
+  }
+
+  for (var emitter_index = 0u; emitter_index < uniforms.emitter_count; emitter_index++) {
+    let emitter = emitters[emitter_index];
+    let accumulator = emitter_states[emitter_index].accumulator + emitter.rate * uniforms.delta_time;
+    let count = u32(floor(accumulator));
+    // Particles that find no slot are dropped rather than emitted later
+    emitter_states[emitter_index].accumulator = accumulator - f32(count);
+    for (var i = 0u; i < count; i++) {
+      let sequence_index = emitter_states[emitter_index].sequence_index;
+      let particle = emit(emitter, sequence_index);
+      emitter_states[emitter_index].sequence_index = sequence_index + 1u;
+      if (overlaps_recent_particles(emitter_index, particle)) {
+        continue;
+      }
+      var slot: u32;
+      if (free_count > 0u) {
+        free_count--;
+        slot = free_slots[free_count];
+      } else if (particle_count < uniforms.particle_capacity) {
+        slot = particle_count;
+        particle_count++;
+      } else {
+        break;
+      }
+      particles[slot] = particle;
+      emitter_states[emitter_index].recent_slots[sequence_index % RECENT_PARTICLES] = slot;
+      let recent_count = emitter_states[emitter_index].recent_count;
+      emitter_states[emitter_index].recent_count = min(recent_count + 1u, RECENT_PARTICLES);
+      emitted_count++;
+      alive_count++;
+    }
+  }
+
+  atomicStore(&counters.workgroup_count_x, (particle_count + Common::PARTICLE_WORKGROUP_SIZE - 1u) / Common::PARTICLE_WORKGROUP_SIZE);
+  atomicStore(&counters.particle_count, particle_count);
+  atomicStore(&counters.free_count, free_count);
+  atomicStore(&counters.alive_count, alive_count);
+  atomicStore(&counters.emitted_count, emitted_count);
+}
+
+fn overlaps_recent_particles(emitter_index: u32, particle: Common::Particle) -> bool {
+  for (var i = 0u; i < emitter_states[emitter_index].recent_count; i++) {
+    let recent_slot = emitter_states[emitter_index].recent_slots[i];
+    if (recent_slot == NO_SLOT) {
+      continue;
+    }
+    let recent_particle = particles[recent_slot];
+    let touching_distance = particle.radius + recent_particle.radius;
+    if (recent_particle.alive != 0u && distance(particle.position, recent_particle.position) < touching_distance) {
+      return true;
+    }
+  }
+  return false;
+}
+
+// The particle at the `index`th place that an emitter emits at, placed within the emitter by a
+// low-discrepancy sequence, which spreads the particles emitted one after another evenly
+fn emit(emitter: Emitter, index: u32) -> Common::Particle {
+  var offset = vec3<f32>(0.0);
+  if (emitter.shape == EMITTER_DISC) {
+    let point = r2(index);
+    let radius = emitter.size.x * sqrt(point.x);
+    let angle = TAU * point.y;
+    offset = vec3<f32>(radius * cos(angle), 0.0, radius * sin(angle));
+  } else if (emitter.shape == EMITTER_BOX) {
+    offset = (r3(index) * 2.0 - 1.0) * emitter.size;
+  }
+  let position = emitter.position + Sdf::rotate(emitter.rotation, offset);
+
+  var particle: Common::Particle;
+  particle.position = position;
+  particle.radius = emitter.radius;
+  // Verlet integration holds the velocity as the distance moved over the last substep
+  particle.old_position = position - emitter.velocity * uniforms.substep_delta_time;
+  particle.mass = emitter.mass;
+  particle.material = emitter.material;
+  particle.alive = 1u;
+  return particle;
+}
+
+// The `index`th point of the R2 sequence in the unit square, from Roberts 2018, "The Unreasonable
+// Effectiveness of Quasirandom Sequences". Accumulated in 32-bit fixed point, which wraps exactly
+// rather than losing precision as the index grows
+fn r2(index: u32) -> vec2<f32> {
+  return vec2<f32>(fixed_fraction(index * 3242174889u), fixed_fraction(index * 2447445413u));
+}
+
+// As `r2`, in the unit cube
+fn r3(index: u32) -> vec3<f32> {
+  return vec3<f32>(
+    fixed_fraction(index * 3518319154u),
+    fixed_fraction(index * 2882110344u),
+    fixed_fraction(index * 2360945575u),
+  );
+}
+
+// `fraction` / 2^32 offset by a half, in [0, 1)
+fn fixed_fraction(fraction: u32) -> f32 {
+  return f32((fraction + 2147483648u) >> 8u) / 16777216.0;
+}
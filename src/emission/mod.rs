@@ -0,0 +1,3 @@
+//! Adding particles from emitters and deleting them in sinks as the simulation runs.
+mod emission;
+pub use emission::{Emission, Emitter, Sink, MAX_EMITTERS, MAX_SINKS};
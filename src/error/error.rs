@@ -0,0 +1,127 @@
+use std::fmt;
+use std::io;
+use wgpu::{BufferAsyncError, CreateSurfaceError, Features, RequestDeviceError, SurfaceError};
+use winit::error::OsError;
+
+/// Everything that can go wrong while creating, stepping or reading back a simulation.
+#[derive(Debug)]
+pub enum SolError {
+    /// No adapter satisfies the request
+    AdapterUnavailable,
+    /// The adapter could not provide a device
+    RequestDevice(RequestDeviceError),
+    /// The window could not be created
+    CreateWindow(OsError),
+    /// A surface could not be created for the window
+    CreateSurface(CreateSurfaceError),
+    /// The next texture of a surface could not be acquired
+    Surface(SurfaceError),
+    /// The device does not support the features an operation needs
+    MissingFeatures(Features),
+    /// Sizes or indices passed to the resources do not fit their configuration
+    InvalidConfiguration(String),
+    /// The device was lost, and it and everything created from it have to be recreated
+    DeviceLost,
+    /// The device ran out of memory
+    OutOfMemory,
+    /// `wgpu` rejected a call, which usually indicates a bug
+    Validation(String),
+    /// A buffer could not be mapped for reading
+    BufferMap(BufferAsyncError),
+    /// The contents of a buffer did not fit the type they were decoded as
+    Decode(encase::internal::Error),
+    Io(io::Error),
+    /// The command line arguments could not be parsed
+    Usage(String),
+}
+
+impl fmt::Display for SolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolError::AdapterUnavailable => f.write_str("No suitable adapter is available"),
+            SolError::RequestDevice(error) => write!(f, "Failed to request device: {error}"),
+            SolError::CreateWindow(error) => write!(f, "Failed to create window: {error}"),
+            SolError::CreateSurface(error) => write!(f, "Failed to create surface: {error}"),
+            SolError::Surface(error) => write!(f, "Failed to get current texture: {error}"),
+            SolError::MissingFeatures(features) => {
+                write!(f, "Device is missing features {features:?}")
+            }
+            SolError::InvalidConfiguration(description) => {
+                write!(f, "Invalid configuration: {description}")
+            }
+            SolError::DeviceLost => f.write_str("Device was lost"),
+            SolError::OutOfMemory => f.write_str("Device ran out of memory"),
+            SolError::Validation(description) => f.write_str(description),
+            SolError::BufferMap(error) => write!(f, "Failed to map buffer: {error}"),
+            SolError::Decode(error) => write!(f, "Failed to decode buffer: {error}"),
+            SolError::Io(error) => error.fmt(f),
+            SolError::Usage(description) => write!(f, "Invalid arguments: {description}"),
+        }
+    }
+}
+
+impl std::error::Error for SolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SolError::RequestDevice(error) => Some(error),
+            SolError::CreateWindow(error) => Some(error),
+            SolError::CreateSurface(error) => Some(error),
+            SolError::Surface(error) => Some(error),
+            SolError::BufferMap(error) => Some(error),
+            SolError::Decode(error) => Some(error),
+            SolError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<wgpu::Error> for SolError {
+    fn from(error: wgpu::Error) -> Self {
+        match error {
+            wgpu::Error::OutOfMemory { .. } => SolError::OutOfMemory,
+            wgpu::Error::Validation { description, .. } => SolError::Validation(description),
+        }
+    }
+}
+
+impl From<RequestDeviceError> for SolError {
+    fn from(error: RequestDeviceError) -> Self {
+        SolError::RequestDevice(error)
+    }
+}
+
+impl From<OsError> for SolError {
+    fn from(error: OsError) -> Self {
+        SolError::CreateWindow(error)
+    }
+}
+
+impl From<CreateSurfaceError> for SolError {
+    fn from(error: CreateSurfaceError) -> Self {
+        SolError::CreateSurface(error)
+    }
+}
+
+impl From<SurfaceError> for SolError {
+    fn from(error: SurfaceError) -> Self {
+        SolError::Surface(error)
+    }
+}
+
+impl From<BufferAsyncError> for SolError {
+    fn from(error: BufferAsyncError) -> Self {
+        SolError::BufferMap(error)
+    }
+}
+
+impl From<encase::internal::Error> for SolError {
+    fn from(error: encase::internal::Error) -> Self {
+        SolError::Decode(error)
+    }
+}
+
+impl From<io::Error> for SolError {
+    fn from(error: io::Error) -> Self {
+        SolError::Io(error)
+    }
+}
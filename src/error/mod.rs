@@ -0,0 +1,3 @@
+//! The error returned by every fallible operation.
+mod error;
+pub use error::SolError;
@@ -0,0 +1,253 @@
+use crate::emission::Emission;
+use crate::error::SolError;
+use crate::partition::{BoundsPartition, GridPartition, MassPartition};
+use crate::resources::Resources;
+use crate::simulation::Simulation;
+use crate::visualisation::{Camera, Visualisation};
+use crate::wgpu_utilities::{submit, Device};
+use glam::Vec3;
+use wgpu::{ColorTargetState, CommandEncoder, CommandEncoderDescriptor, Queue, TextureView};
+
+/// A stage that can be recorded by a [`FramePipeline`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// Deletes the particles inside the sinks and adds those that the emitters are due to emit over
+    /// the frame's `delta_time`
+    Emit,
+    /// Moves the bounds to follow the current particles, according to the [`crate::BoundsMode`]
+    CalculateBounds,
+    /// Clears and rebuilds the grid within the current bounds
+    BuildGrid,
+    /// Sums the mass grid from the particles within the current bounds, if the simulation has
+    /// [`crate::SelfGravity`]
+    SumMasses,
+    /// Advances the particles by the frame's `delta_time`, finding neighbours through the grid
+    /// unless the simulation uses [`crate::simulation::NeighbourSearch::BruteForce`]
+    Simulate,
+    /// Renders the particles, if the frame has a [`Target`] and the pipeline has a
+    /// [`Visualisation`]
+    ///
+    /// Recorded once per frame, after every step.
+    Visualise,
+}
+
+impl Pass {
+    /// Keeps the bounds and grid up to date with the particles before every simulation step.
+    pub const DEFAULT_ORDER: [Pass; 6] = [
+        Pass::Emit,
+        Pass::CalculateBounds,
+        Pass::BuildGrid,
+        Pass::SumMasses,
+        Pass::Simulate,
+        Pass::Visualise,
+    ];
+}
+
+/// Where and from which point of view a frame is rendered.
+pub struct Target<'a> {
+    pub view: &'a TextureView,
+    pub camera: &'a Camera,
+}
+
+/// The inputs to a single frame.
+pub struct Frame<'a> {
+    /// Length of each step in seconds
+    pub delta_time: f32,
+    /// Number of times the passes other than [`Pass::Visualise`] are recorded, see
+    /// [`FixedTimestep`]
+    ///
+    /// [`FixedTimestep`]: super::FixedTimestep
+    pub steps: u32,
+    /// Uniform gravity, which [`crate::GravityModel::Radial`] ignores
+    pub gravity: Vec3,
+    /// Frames without a target skip [`Pass::Visualise`]
+    pub target: Option<Target<'a>>,
+}
+
+/// Owns every stage and records them into one command encoder in a configurable order.
+pub struct FramePipeline {
+    pub emission: Emission,
+    pub bounds_partition: BoundsPartition,
+    pub grid_partition: GridPartition,
+    pub mass_partition: MassPartition,
+    pub simulation: Simulation,
+    /// Only present for pipelines created with a render target format
+    pub visualisation: Option<Visualisation>,
+    target: Option<ColorTargetState>,
+    passes: Vec<Pass>,
+}
+
+impl FramePipeline {
+    /// Creates every stage for `resources`, including a [`Visualisation`] when `target` is given.
+    pub fn new(
+        device: &Device,
+        resources: &Resources,
+        target: Option<ColorTargetState>,
+    ) -> Result<Self, SolError> {
+        Ok(FramePipeline {
+            emission: Emission::new(device, resources)?,
+            bounds_partition: BoundsPartition::new(device, resources)?,
+            grid_partition: GridPartition::new(device, resources)?,
+            mass_partition: MassPartition::new(device, resources)?,
+            simulation: Simulation::new(device, resources)?,
+            visualisation: target
+                .clone()
+                .map(|target| Visualisation::new(device, target, resources))
+                .transpose()?,
+            target,
+            passes: Pass::DEFAULT_ORDER.to_vec(),
+        })
+    }
+
+    /// Creates the same stages, with the same settings, on another device, e.g. after
+    /// [`SolError::DeviceLost`].
+    pub fn recreate(&self, device: &Device, resources: &Resources) -> Result<Self, SolError> {
+        let mut pipeline = FramePipeline::new(device, resources, self.target.clone())?;
+        pipeline.emission.set_emitters(self.emission.emitters())?;
+        pipeline.emission.set_sinks(self.emission.sinks())?;
+        pipeline
+            .bounds_partition
+            .set_mode(self.bounds_partition.mode());
+        pipeline.simulation.set_substeps(self.simulation.substeps());
+        pipeline
+            .simulation
+            .set_iterations(self.simulation.iterations());
+        pipeline
+            .simulation
+            .set_relaxation(self.simulation.relaxation());
+        pipeline
+            .simulation
+            .set_neighbour_search(self.simulation.neighbour_search());
+        pipeline.simulation.set_phase(self.simulation.phase());
+        pipeline
+            .simulation
+            .set_smoothing_length(self.simulation.smoothing_length());
+        pipeline
+            .simulation
+            .set_force_fields(self.simulation.force_fields())?;
+        pipeline
+            .simulation
+            .set_self_gravity(self.simulation.self_gravity());
+        pipeline
+            .simulation
+            .set_gravity_model(self.simulation.gravity_model());
+        pipeline.passes = self.passes.clone();
+        Ok(pipeline)
+    }
+
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    /// Replaces the order in which passes are recorded.
+    ///
+    /// Passes may be repeated or omitted, e.g. `[Simulate, Visualise]` keeps the bounds and grid
+    /// from the previous frame. Every [`Pass::Simulate`] within a frame shares the same uniforms.
+    pub fn set_passes(&mut self, passes: impl Into<Vec<Pass>>) {
+        self.passes = passes.into();
+    }
+
+    /// Records every pass into `command_encoder`, in order.
+    ///
+    /// The passes other than [`Pass::Visualise`] are recorded `frame.steps` times, followed by a
+    /// single [`Pass::Visualise`] so that frames which do not step are still rendered.
+    ///
+    /// Errors are raised through `device` rather than returned, see [`Self::run`].
+    pub fn record(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_encoder: &mut CommandEncoder,
+        resources: &mut Resources,
+        frame: &Frame,
+    ) {
+        for _ in 0..frame.steps {
+            for pass in self.passes.iter() {
+                match pass {
+                    Pass::Emit => {
+                        self.emission.emit_with_encoder(
+                            device,
+                            queue,
+                            command_encoder,
+                            resources,
+                            frame.delta_time,
+                            self.simulation.substeps(),
+                        );
+                    }
+                    Pass::CalculateBounds => {
+                        self.bounds_partition.calculate_bounds_with_encoder(
+                            device,
+                            queue,
+                            command_encoder,
+                            resources,
+                        );
+                    }
+                    Pass::BuildGrid => {
+                        self.grid_partition.build_grid_with_encoder(
+                            device,
+                            command_encoder,
+                            resources,
+                        );
+                    }
+                    Pass::SumMasses => {
+                        if self.simulation.self_gravity().is_some() {
+                            self.mass_partition.sum_masses_with_encoder(
+                                device,
+                                command_encoder,
+                                resources,
+                            );
+                        }
+                    }
+                    Pass::Simulate => {
+                        self.simulation.simulate_with_encoder(
+                            device,
+                            queue,
+                            command_encoder,
+                            resources,
+                            frame.delta_time,
+                            frame.gravity,
+                        );
+                    }
+                    // Recorded once after every step
+                    Pass::Visualise => {}
+                }
+            }
+        }
+
+        if !self.passes.contains(&Pass::Visualise) {
+            return;
+        }
+        if let (Some(visualisation), Some(target)) =
+            (self.visualisation.as_mut(), frame.target.as_ref())
+        {
+            visualisation.visualise_with_encoder(
+                device,
+                queue,
+                command_encoder,
+                target.view,
+                resources,
+                target.camera,
+            );
+        }
+    }
+
+    /// Records every pass into a single command encoder and submits it to `queue`.
+    ///
+    /// After [`SolError::DeviceLost`], the device has to be recreated along with the [`Resources`]
+    /// and the pipeline, see [`Self::recreate`].
+    pub fn run(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        resources: &mut Resources,
+        frame: &Frame,
+    ) -> Result<(), SolError> {
+        let command_buffer = device.capture_errors(|| {
+            let mut command_encoder =
+                device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            self.record(device, queue, &mut command_encoder, resources, frame);
+            command_encoder.finish()
+        })?;
+        submit(device, queue, command_buffer)
+    }
+}
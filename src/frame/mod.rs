@@ -0,0 +1,7 @@
+//! Scheduling every stage of a frame into a single submission.
+mod frame;
+pub use frame::{Frame, FramePipeline, Pass, Target};
+mod timestep;
+pub use timestep::FixedTimestep;
+mod transport;
+pub use transport::Transport;
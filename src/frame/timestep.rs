@@ -0,0 +1,46 @@
+/// Turns elapsed real time into a whole number of fixed-size simulation steps.
+///
+/// Time that does not make up a whole step is carried over to the next call to [`Self::advance`].
+/// After a hitch, at most `max_steps` steps are run and the rest of the backlog is dropped, so that
+/// a slow frame cannot make the next frame even slower.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedTimestep {
+    /// Length of a single step in seconds
+    pub step: f32,
+    /// Most steps that are run to catch up with real time
+    pub max_steps: u32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    pub fn new(step: f32, max_steps: u32) -> Self {
+        assert!(step > 0.0, "a step must have a positive length");
+        FixedTimestep {
+            step,
+            max_steps,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Accumulates `elapsed` seconds and returns how many steps to run now.
+    pub fn advance(&mut self, elapsed: f32) -> u32 {
+        self.accumulator += elapsed.max(0.0);
+        let steps = (self.accumulator / self.step).floor() as u32;
+        if steps > self.max_steps {
+            self.accumulator = 0.0;
+            return self.max_steps;
+        }
+        self.accumulator -= steps as f32 * self.step;
+        steps
+    }
+
+    /// How far real time is into the next step, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).clamp(0.0, 1.0)
+    }
+
+    /// Discards any accumulated time.
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+    }
+}
@@ -0,0 +1,62 @@
+use super::FixedTimestep;
+
+/// Pause, single-step and time-scale controls that sit between real time and a [`FixedTimestep`].
+///
+/// Only the number of steps changes, every step keeps the same length, so slow motion runs the same
+/// simulation less often rather than a different one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transport {
+    paused: bool,
+    time_scale: f32,
+    pending_steps: u32,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport {
+            paused: false,
+            time_scale: 1.0,
+            pending_steps: 0,
+        }
+    }
+}
+
+impl Transport {
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn toggle_paused(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Runs exactly one more step on the next call to [`Self::advance`], even while paused.
+    pub fn step_once(&mut self) {
+        self.pending_steps += 1;
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Multiplies the real time that is passed to [`Self::advance`], e.g. 0.5 for half speed.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        assert!(time_scale >= 0.0, "time scale must not be negative");
+        self.time_scale = time_scale;
+    }
+
+    /// Returns how many steps to run after `elapsed` seconds of real time.
+    ///
+    /// No time is accumulated while paused, so resuming does not try to catch up.
+    pub fn advance(&mut self, timestep: &mut FixedTimestep, elapsed: f32) -> u32 {
+        let pending_steps = std::mem::take(&mut self.pending_steps);
+        if self.paused {
+            return pending_steps;
+        }
+        timestep.advance(elapsed * self.time_scale) + pending_steps
+    }
+}
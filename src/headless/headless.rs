@@ -0,0 +1,198 @@
+use crate::common::{Particle, ParticleCounters};
+use crate::debug::read_buffer;
+use crate::error::SolError;
+use crate::frame::{Frame, FramePipeline};
+use crate::resources::{Configuration, Resources};
+use crate::wgpu_utilities::{required_limits, Device};
+use glam::Vec3;
+use std::io::{self, Write};
+use wgpu::{
+    Adapter, DeviceDescriptor, Features, Instance, PowerPreference, Queue, RequestAdapterOptions,
+};
+
+/// Requests an adapter and device that are not tied to any surface.
+///
+/// A hardware adapter is preferred, but the fallback (software) adapter is used when none is
+/// available so that the simulation can run on machines without a GPU or display. Passing
+/// `force_fallback_adapter` skips straight to the fallback adapter, which gives consistent results
+/// across machines. The device is requested with [`request_device`].
+pub async fn request_headless_device(
+    force_fallback_adapter: bool,
+) -> Result<(Adapter, Device, Queue), SolError> {
+    let instance = Instance::default();
+    let mut adapter = None;
+    if !force_fallback_adapter {
+        adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            })
+            .await;
+    }
+    if adapter.is_none() {
+        adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::HighPerformance,
+                force_fallback_adapter: true,
+                compatible_surface: None,
+            })
+            .await;
+    }
+    let adapter = adapter.ok_or(SolError::AdapterUnavailable)?;
+    let (device, queue) = request_device(&adapter).await?;
+    Ok((adapter, device, queue))
+}
+
+/// Requests a device from `adapter` with the limits every stage needs, and timestamp queries for
+/// [`crate::profiling::profile`] when the adapter supports them.
+///
+/// Errors raised outside of a [`SolError`] returning call are printed rather than panicking.
+pub async fn request_device(adapter: &Adapter) -> Result<(Device, Queue), SolError> {
+    let (device, queue) = adapter
+        .request_device(
+            &DeviceDescriptor {
+                label: None,
+                // Software adapters rarely support timestamp queries, so only ask for them when
+                // available
+                features: adapter.features() & Features::TIMESTAMP_QUERY,
+                limits: required_limits(adapter),
+            },
+            None,
+        )
+        .await?;
+    Ok((Device::new(device), queue))
+}
+
+/// Owns a device and every simulation stage, and steps them without rendering.
+pub struct HeadlessRunner {
+    pub adapter: Adapter,
+    pub device: Device,
+    pub queue: Queue,
+    pub resources: Resources,
+    pub pipeline: FramePipeline,
+    particles: Vec<Particle>,
+}
+
+impl HeadlessRunner {
+    /// See [`request_headless_device`] for how the adapter is chosen.
+    pub async fn new(
+        configuration: Configuration,
+        force_fallback_adapter: bool,
+    ) -> Result<Self, SolError> {
+        let (adapter, device, queue) = request_headless_device(force_fallback_adapter).await?;
+        let resources = Resources::new(&device, &queue, configuration)?;
+        let pipeline = FramePipeline::new(&device, &resources, None)?;
+        Ok(HeadlessRunner {
+            adapter,
+            device,
+            queue,
+            resources,
+            pipeline,
+            particles: Vec::new(),
+        })
+    }
+
+    /// Uploads the initial particle state.
+    pub fn populate(&mut self, particles: &[Particle]) -> Result<(), SolError> {
+        self.resources.populate(&self.queue, particles)?;
+        self.particles = particles.to_vec();
+        Ok(())
+    }
+
+    /// Requests a new device from the same adapter after [`SolError::DeviceLost`] and recreates the
+    /// resources and pipeline on it, keeping the pipeline's settings.
+    ///
+    /// The state of the particles is lost with the device, so they are restored to the particles
+    /// last passed to [`Self::populate`]. The material table, the constraints, unbroken, and the
+    /// clusters, as they were set, are carried over.
+    pub async fn recover(&mut self) -> Result<(), SolError> {
+        let (device, queue) = request_device(&self.adapter).await?;
+        let mut resources = Resources::new(&device, &queue, *self.resources.configuration())?;
+        resources.set_materials(&queue, self.resources.materials())?;
+        resources.set_colliders(&queue, self.resources.colliders())?;
+        resources.set_container(&queue, self.resources.container());
+        resources.set_constraints(&device, &queue, self.resources.constraints())?;
+        resources.set_clusters(
+            &device,
+            &queue,
+            self.resources.clusters(),
+            self.resources.rest_offsets(),
+        )?;
+        let pipeline = self.pipeline.recreate(&device, &resources)?;
+        resources.populate(&queue, &self.particles)?;
+        // Drop the stages before the resources and the device they were created from
+        self.pipeline = pipeline;
+        self.resources = resources;
+        self.device = device;
+        self.queue = queue;
+        Ok(())
+    }
+
+    /// Records a single step of every pass of [`Self::pipeline`] into a single submission,
+    /// advancing the particles by `delta_time`.
+    pub fn step(&mut self, delta_time: f32, gravity: Vec3) -> Result<(), SolError> {
+        self.pipeline.run(
+            &self.device,
+            &self.queue,
+            &mut self.resources,
+            &Frame {
+                delta_time,
+                steps: 1,
+                gravity,
+                target: None,
+            },
+        )
+    }
+
+    /// Steps the simulation `frames` times with a fixed `delta_time`.
+    pub fn run(&mut self, frames: u32, delta_time: f32, gravity: Vec3) -> Result<(), SolError> {
+        for _ in 0..frames {
+            self.step(delta_time, gravity)?;
+        }
+        self.device.poll(wgpu::Maintain::Wait);
+        Ok(())
+    }
+
+    /// Reads the current particle state back from the GPU, including the slots that are not in use,
+    /// which hold dead particles.
+    pub fn particles(&self) -> Result<Vec<Particle>, SolError> {
+        read_buffer::<Vec<Particle>>(&self.device, &self.queue, self.resources.particle_buffer())
+    }
+
+    /// Reads back how many particle slots are in use and how many particles are alive.
+    pub fn particle_counters(&self) -> Result<ParticleCounters, SolError> {
+        read_buffer::<ParticleCounters>(
+            &self.device,
+            &self.queue,
+            self.resources.particle_counters_buffer(),
+        )
+    }
+}
+
+/// Writes `particles` as CSV, one particle per row, including the dead particles in free slots,
+/// whose `alive` column is 0.
+pub fn write_particles<W: Write>(writer: &mut W, particles: &[Particle]) -> io::Result<()> {
+    writeln!(
+        writer,
+        "position_x,position_y,position_z,old_position_x,old_position_y,old_position_z,radius,mass,\
+         material,alive"
+    )?;
+    for particle in particles {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{}",
+            particle.position.x,
+            particle.position.y,
+            particle.position.z,
+            particle.old_position.x,
+            particle.old_position.y,
+            particle.old_position.z,
+            particle.radius,
+            particle.mass,
+            particle.material,
+            particle.alive,
+        )?;
+    }
+    Ok(())
+}
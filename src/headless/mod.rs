@@ -0,0 +1,3 @@
+//! Running the simulation without a window or surface.
+mod headless;
+pub use headless::{request_device, request_headless_device, write_particles, HeadlessRunner};
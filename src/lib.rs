@@ -0,0 +1,75 @@
+//! GPU particle simulation built on `wgpu`.
+//!
+//! A frame is made up of a handful of stages that all operate on the buffers owned by
+//! [`Resources`]:
+//!
+//! - [`Emission`] adds particles from [`Emitter`]s and deletes those that enter [`Sink`]s, keeping
+//!   count of the particle slots in use so that the other stages only dispatch over those
+//! - [`BoundsPartition`] keeps an axis-aligned [`Bounds`] around the particles, see [`BoundsMode`]
+//! - [`GridPartition`] bins the particles into [`GridCell`]s within those bounds
+//! - [`MassPartition`] sums the mass in each cell of that grid and of coarser grids above it, for
+//!   [`SelfGravity`]
+//! - [`Simulation`] integrates the particles under uniform or radial gravity, see [`GravityModel`],
+//!   resolves their collisions with each other and with the [`Collider`]s, applies fluid forces,
+//!   [`ForceField`]s and the gravity between the particles and holds rigid clusters together
+//! - [`Visualisation`] ray marches the particles and the colliders from the point of view of a
+//!   [`Camera`]
+//!
+//! [`FramePipeline`] records these stages into a single submission per frame, in a configurable
+//! order, stepping the simulation as many times as [`FixedTimestep`] says real time calls for.
+//! [`Transport`] pauses, single-steps or slows down that real time.
+//!
+//! [`headless::HeadlessRunner`] steps these stages without a window, for batch runs and tests.
+//!
+//! [`reference`](mod@reference) reimplements the stages on the CPU so that the shaders can be
+//! tested against it.
+//!
+//! [`spawn`] generates initial particles from a seed. As [`Simulation`] double buffers the
+//! particles in [`Resources`], stepping them with a fixed time step makes runs reproducible.
+//!
+//! Buffers can be read back to the CPU with [`debug::read_buffer`] and passes can be timed with
+//! [`profiling::profile`].
+//!
+//! Fallible operations return a [`SolError`] rather than panicking.
+
+// Modules are split into a `mod.rs` and a file of the same name, which is intentional
+#![allow(clippy::module_inception)]
+
+pub mod error;
+pub use error::SolError;
+
+pub mod common;
+pub use common::{Bounds, Cluster, Container, DistanceConstraint, GridCell, Material, Particle};
+
+pub mod sdf;
+pub use sdf::Collider;
+
+pub mod resources;
+pub use resources::{Configuration, Resources};
+
+pub mod emission;
+pub use emission::{Emission, Emitter, Sink};
+
+pub mod partition;
+pub use partition::{BoundsMode, BoundsPartition, GridPartition, MassPartition};
+
+pub mod simulation;
+pub use simulation::{ForceField, GravityModel, RadialGravity, SelfGravity, Simulation};
+
+pub mod visualisation;
+pub use visualisation::{Camera, Visualisation};
+
+pub mod frame;
+pub use frame::{FixedTimestep, FramePipeline, Transport};
+
+pub mod debug;
+
+pub mod headless;
+
+pub mod profiling;
+
+pub mod reference;
+
+pub mod spawn;
+
+pub mod wgpu_utilities;
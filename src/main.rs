@@ -1,55 +1,662 @@
 use futures::executor::block_on;
-use glam::{IVec3, Quat, Vec3};
+use glam::{Quat, UVec3, Vec3};
+use rand::Rng;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::time::{Duration, Instant};
 use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
-    BufferUsages, DeviceDescriptor, Features, Instance, Limits, PowerPreference, PresentMode,
-    RequestAdapterOptions, SurfaceConfiguration, TextureUsages, TextureViewDescriptor,
+    Adapter, Instance, PowerPreference, PresentMode, Queue, RequestAdapterOptions, Surface,
+    SurfaceConfiguration, TextureUsages, TextureViewDescriptor,
 };
 use winit::{
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
-use encase::StorageBuffer;
+use sol::common::{DEFAULT_PARTICLE_MASS, DEFAULT_PARTICLE_RADIUS};
+use sol::debug::read_buffer;
+use sol::frame::{Frame, Target};
+use sol::headless::{request_device, write_particles, HeadlessRunner};
+use sol::profiling::profile;
+use sol::sdf::OPERATION_SUBTRACTION;
+use sol::simulation::{NeighbourSearch, DEFAULT_ITERATIONS, DEFAULT_SUBSTEPS};
+use sol::spawn::{assign_material, mix_coarse_grains, random_particles, seeded_rng, Lattice};
+use sol::wgpu_utilities::{current_texture, Device};
+use sol::{
+    Bounds, BoundsMode, Camera, Collider, Configuration, Container, Emitter, FixedTimestep,
+    ForceField, FramePipeline, GravityModel, Material, Particle, RadialGravity, Resources,
+    SelfGravity, Sink, SolError, Transport,
+};
 
-pub mod common;
-use crate::common::{Bounds, GridCell, Particle, MAX_PARTICLES};
+const GRAVITY: Vec3 = Vec3::new(0.0, -9.8, 0.0);
+/// Most steps the viewer runs in one frame to catch up after a slow frame
+const MAX_CATCH_UP_STEPS: u32 = 4;
+/// Index of [`pebble`] in [`materials`]
+const PEBBLE: u32 = 1;
+/// Index of water in [`materials`]
+const WATER: u32 = 2;
+
+/// Sand, which every particle is made of unless it is a coarse grain or `--fluid` is passed,
+/// [`pebble`] and water.
+fn materials() -> [Material; 3] {
+    [Material::default(), pebble(), Material::fluid()]
+}
 
-mod partition;
-use crate::partition::{BoundsPartition, GridPartition};
+/// Grey, slightly bouncy stone that is denser than sand.
+fn pebble() -> Material {
+    let sand = Material::default();
+    Material {
+        colour: Vec3::new(0.5, 0.5, 0.55),
+        restitution: 0.1,
+        density: sand.density * 1.5,
+        ..sand
+    }
+}
 
-mod visualisation;
-use crate::visualisation::{Camera, Visualisation};
+/// A ball with a tunnel through it, in the middle of the particles.
+fn colliders() -> [Collider; 2] {
+    [
+        Collider {
+            colour: Vec3::new(0.3, 0.6, 0.3),
+            ..Collider::sphere(Vec3::ZERO, 6.0)
+        },
+        Collider {
+            operation: OPERATION_SUBTRACTION,
+            smoothing: 1.0,
+            ..Collider::capsule(Vec3::ZERO, 8.0, 2.5)
+                .rotated(Quat::from_rotation_x(std::f32::consts::FRAC_PI_2))
+        },
+    ]
+}
 
-mod simulation;
-use crate::simulation::Simulation;
+/// Radius of the rocky core that `--core` pulls the sand onto
+const CORE_RADIUS: f32 = 6.0;
 
-pub mod debug;
-use debug::debug_buffer;
+/// A rocky ball in the middle of the particles.
+fn core() -> Collider {
+    Collider {
+        colour: Vec3::new(0.45, 0.3, 0.25),
+        ..Collider::sphere(Vec3::ZERO, CORE_RADIUS)
+    }
+}
 
-pub mod profiling;
-use crate::profiling::profile;
+/// Pulls towards the middle of [`core`], as hard at its surface as gravity pulls on Earth.
+fn core_gravity() -> RadialGravity {
+    RadialGravity::inverse_square(Vec3::ZERO, CORE_RADIUS, GRAVITY.length())
+}
 
-pub mod wgpu_utilities;
+/// Height of the bottom of the bowl of the hopper
+const HOPPER_BOTTOM: f32 = 4.0;
+
+/// A bowl carved into a slab across the container, with a round hole at the bottom that the sand
+/// poured into it drains through. The sides of the bowl are steeper than sand can rest on, apart
+/// from around the hole.
+fn hopper() -> [Collider; 3] {
+    [
+        Collider {
+            colour: Vec3::new(0.45, 0.35, 0.3),
+            ..Collider::cuboid(
+                Vec3::new(0.0, HOPPER_BOTTOM + 2.0, 0.0),
+                Vec3::new(17.0, 4.0, 17.0),
+            )
+        },
+        Collider {
+            operation: OPERATION_SUBTRACTION,
+            ..Collider::sphere(Vec3::new(0.0, HOPPER_BOTTOM + 14.0, 0.0), 14.0)
+        },
+        Collider {
+            operation: OPERATION_SUBTRACTION,
+            smoothing: 1.0,
+            ..Collider::capsule(Vec3::new(0.0, HOPPER_BOTTOM, 0.0), 2.0, 5.0)
+        },
+    ]
+}
 
-use rand::Rng;
+/// Pours sand into the hopper from above.
+fn emitters() -> [Emitter; 1] {
+    [Emitter {
+        velocity: Vec3::new(0.0, -10.0, 0.0),
+        ..Emitter::disc(Vec3::new(0.0, 14.0, 0.0), 4.0, 40.0)
+    }]
+}
+
+/// Deletes the sand that has drained through the hopper, once it reaches the bottom of the
+/// container.
+fn sinks() -> [Sink; 1] {
+    [Sink::cuboid(
+        Vec3::new(0.0, -CONTAINER_HALF_EXTENT as f32, 0.0),
+        Vec3::new(
+            CONTAINER_HALF_EXTENT as f32,
+            2.0,
+            CONTAINER_HALF_EXTENT as f32,
+        ),
+    )]
+}
+
+/// A vortex through the middle of the container and an attractor that circles it, `time` seconds
+/// in.
+fn force_fields(time: f32) -> [ForceField; 2] {
+    let orbit_rate = std::f32::consts::PI / 8.0;
+    let attractor_position = Quat::from_rotation_y(orbit_rate * time) * Vec3::new(10.0, 0.0, 0.0);
+    [
+        ForceField::vortex(Vec3::ZERO, Vec3::Y, 16.0, 12.0),
+        ForceField::attractor(attractor_position, 8.0, 24.0),
+    ]
+}
+
+/// Half the size of the box that the particles are scattered within
+const HALF_EXTENT: f32 = 16.0;
+/// Half the size of the box that the particles are kept inside of, and partitioned within
+const CONTAINER_HALF_EXTENT: i32 = 17;
+
+/// The box that the particles are kept inside of.
+fn container() -> Container {
+    Container::aabb(
+        Vec3::splat(-CONTAINER_HALF_EXTENT as f32),
+        Vec3::splat(CONTAINER_HALF_EXTENT as f32),
+    )
+}
+
+/// Partitions the particles within the container, which they never leave, so that the grid does not
+/// have to follow them.
+fn bounds_mode() -> BoundsMode {
+    BoundsMode::Fixed(Bounds {
+        min_x: -CONTAINER_HALF_EXTENT,
+        min_y: -CONTAINER_HALF_EXTENT,
+        min_z: -CONTAINER_HALF_EXTENT,
+        max_x: CONTAINER_HALF_EXTENT,
+        max_y: CONTAINER_HALF_EXTENT,
+        max_z: CONTAINER_HALF_EXTENT,
+    })
+}
+
+/// `count` particles scattered about the points of a lattice that fills the box they are usually
+/// scattered within, so that none of them overlap. Overlapping particles are pushed apart fast
+/// enough to escape the gravity of a planet, which has no container to hold them in.
+fn dust_cloud(rng: &mut impl Rng, count: u32) -> Vec<Particle> {
+    let size = (count as f32).cbrt().ceil() as u32;
+    let spacing = HALF_EXTENT * 2.0 / size as f32;
+    let lattice = Lattice {
+        origin: Vec3::splat(spacing * 0.5 - HALF_EXTENT),
+        size: UVec3::splat(size),
+        spacing,
+    };
+    let jitter = (spacing * 0.5 - DEFAULT_PARTICLE_RADIUS).max(0.0);
+    let mut particles = lattice.particles(DEFAULT_PARTICLE_RADIUS, DEFAULT_PARTICLE_MASS);
+    particles.truncate(count as usize);
+    for particle in &mut particles {
+        particle.position += Vec3::new(
+            rng.gen_range(-jitter..=jitter),
+            rng.gen_range(-jitter..=jitter),
+            rng.gen_range(-jitter..=jitter),
+        );
+        particle.old_position = particle.position;
+    }
+    particles
+}
+
+/// How hard the particles at the edge of the cloud are pulled in by `--planet`, in metres per
+/// second squared
+const PLANET_PULL: f32 = 4.0;
+
+/// Range of time scales the viewer steps through
+const MIN_TIME_SCALE: f32 = 1.0 / 16.0;
+const MAX_TIME_SCALE: f32 = 4.0;
+
+fn main() -> ExitCode {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let result = Options::parse(&arguments).and_then(|options| {
+        if options.headless {
+            block_on(headless_main(options))
+        } else {
+            block_on(async_main(options))
+        }
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The error for an argument that `Options::parse` could not make sense of.
+fn usage(description: &str) -> SolError {
+    SolError::Usage(description.to_owned())
+}
+
+struct Options {
+    headless: bool,
+    fallback_adapter: bool,
+    deterministic: bool,
+    brute_force: bool,
+    fluid: bool,
+    colliders: bool,
+    pour: bool,
+    force_fields: bool,
+    planet: bool,
+    core: bool,
+    seed: Option<u64>,
+    particles: u32,
+    coarse_fraction: f32,
+    coarse_radius: f32,
+    grid_size: u32,
+    frames: u32,
+    delta_time: f32,
+    substeps: u32,
+    iterations: u32,
+    output: Option<PathBuf>,
+}
+
+impl Options {
+    /// Parses `--particles <count>`, `--coarse-fraction <fraction>` of which are coarse grains of
+    /// `--coarse-radius <radius>`, `--grid-size <cells>`, `--seed <seed>`, `--delta-time <seconds>`
+    /// which is the length of each step, `--substeps <count>` which each step is split into and
+    /// `--iterations <count>` of the contact solver per substep, plus `--deterministic` which steps
+    /// once per frame, `--brute-force` which tests every pair of particles for contact instead of
+    /// searching the grid, `--fluid` which makes the fine particles water, `--colliders` which adds
+    /// a ball with a tunnel through it, `--pour` which starts without particles and pours sand
+    /// through a hopper, the particle count being the most there can be at once, `--force-fields`
+    /// which stirs the particles with a vortex and a circling attractor instead of spinning gravity
+    /// around them, `--planet` which lets the particles fall together under their own gravity
+    /// instead, with nothing around them, `--core` which pulls the particles onto a rocky ball in
+    /// the middle instead of spinning gravity around them, and `--headless` which runs without a
+    /// window and accepts `--fallback-adapter`, `--frames <count>` and `--output <path>`
+    fn parse(arguments: &[String]) -> Result<Self, SolError> {
+        let default_configuration = Configuration::default();
+        let mut options = Options {
+            headless: false,
+            fallback_adapter: false,
+            deterministic: false,
+            brute_force: false,
+            fluid: false,
+            colliders: false,
+            pour: false,
+            force_fields: false,
+            planet: false,
+            core: false,
+            seed: None,
+            particles: default_configuration.particle_count,
+            coarse_fraction: 0.0,
+            coarse_radius: 2.0,
+            grid_size: default_configuration.grid_size.x,
+            frames: 600,
+            delta_time: 1.0 / 60.0,
+            substeps: DEFAULT_SUBSTEPS,
+            iterations: DEFAULT_ITERATIONS,
+            output: None,
+        };
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            match argument.as_str() {
+                "--headless" => options.headless = true,
+                "--fallback-adapter" => options.fallback_adapter = true,
+                "--deterministic" => options.deterministic = true,
+                "--brute-force" => options.brute_force = true,
+                "--fluid" => options.fluid = true,
+                "--colliders" => options.colliders = true,
+                "--pour" => options.pour = true,
+                "--force-fields" => options.force_fields = true,
+                "--planet" => options.planet = true,
+                "--core" => options.core = true,
+                "--seed" => {
+                    options.seed = Some(
+                        arguments
+                            .next()
+                            .and_then(|value| value.parse().ok())
+                            .ok_or_else(|| usage("--seed expects an unsigned integer"))?,
+                    );
+                }
+                "--particles" => {
+                    options.particles = arguments
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| usage("--particles expects a particle count"))?;
+                }
+                "--coarse-fraction" => {
+                    options.coarse_fraction = arguments
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| {
+                            usage("--coarse-fraction expects a fraction between 0 and 1")
+                        })?;
+                }
+                "--coarse-radius" => {
+                    options.coarse_radius = arguments
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .filter(|&radius| radius > 0.0)
+                        .ok_or_else(|| usage("--coarse-radius expects a positive radius"))?;
+                }
+                "--grid-size" => {
+                    options.grid_size = arguments
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| usage("--grid-size expects a cell count"))?;
+                }
+                "--frames" => {
+                    options.frames = arguments
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| usage("--frames expects a frame count"))?;
+                }
+                "--delta-time" => {
+                    options.delta_time = arguments
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| usage("--delta-time expects a duration in seconds"))?;
+                }
+                "--substeps" => {
+                    options.substeps = arguments
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .filter(|&substeps| substeps > 0)
+                        .ok_or_else(|| usage("--substeps expects a positive count"))?;
+                }
+                "--iterations" => {
+                    options.iterations = arguments
+                        .next()
+                        .and_then(|value| value.parse().ok())
+                        .filter(|&iterations| iterations > 0)
+                        .ok_or_else(|| usage("--iterations expects a positive count"))?;
+                }
+                "--output" => {
+                    options.output = Some(
+                        arguments
+                            .next()
+                            .map(PathBuf::from)
+                            .ok_or_else(|| usage("--output expects a path"))?,
+                    );
+                }
+                _ => {}
+            }
+        }
+        Ok(options)
+    }
+
+    fn configuration(&self) -> Configuration {
+        Configuration {
+            grid_size: UVec3::splat(self.grid_size),
+            ..Configuration::with_particles(self.particles)
+        }
+    }
+
+    fn colliders(&self) -> Vec<Collider> {
+        if self.pour {
+            hopper().to_vec()
+        } else if self.core {
+            vec![core()]
+        } else if self.colliders {
+            colliders().to_vec()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// The force fields `time` seconds in.
+    fn force_fields(&self, time: f32) -> Vec<ForceField> {
+        if self.force_fields {
+            force_fields(time).to_vec()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Gravity `time` seconds in, which spins around the particles unless force fields stir them
+    /// instead, or they are a planet.
+    fn gravity(&self, time: f32) -> Vec3 {
+        if self.planet {
+            return Vec3::ZERO;
+        }
+        if self.force_fields {
+            return GRAVITY;
+        }
+        let spin_rate = std::f32::consts::PI / 32.0;
+        let gravity_rotation = Quat::from_euler(
+            glam::EulerRot::XYZ,
+            spin_rate * time,
+            spin_rate * time,
+            spin_rate * time,
+        );
+        gravity_rotation * GRAVITY
+    }
+
+    /// Radial gravity towards the core, which replaces the gravity vector of each step, if there is
+    /// one.
+    fn gravity_model(&self) -> GravityModel {
+        if self.core {
+            GravityModel::Radial(core_gravity())
+        } else {
+            GravityModel::Uniform
+        }
+    }
+
+    /// The gravity between the particles of a planet, strong enough to pull those at the edge of
+    /// the cloud in at [`PLANET_PULL`] whatever their number.
+    fn self_gravity(&self) -> Option<SelfGravity> {
+        self.planet.then(|| {
+            let mass = self.particles as f32 * DEFAULT_PARTICLE_MASS;
+            SelfGravity::new(PLANET_PULL * HALF_EXTENT * HALF_EXTENT / mass)
+        })
+    }
+
+    /// The container, unless the particles are a planet, which nothing holds in.
+    fn container(&self) -> Option<Container> {
+        (!self.planet).then(container)
+    }
 
-fn main() {
-    block_on(async_main());
+    /// Planets are partitioned wherever their particles are, as they have no container to stay
+    /// inside of.
+    fn bounds_mode(&self) -> BoundsMode {
+        if self.planet {
+            BoundsMode::Dynamic
+        } else {
+            bounds_mode()
+        }
+    }
+
+    fn emitters(&self) -> Vec<Emitter> {
+        if self.pour {
+            emitters().to_vec()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn sinks(&self) -> Vec<Sink> {
+        if self.pour {
+            sinks().to_vec()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn neighbour_search(&self) -> NeighbourSearch {
+        if self.brute_force {
+            NeighbourSearch::BruteForce
+        } else {
+            NeighbourSearch::Grid
+        }
+    }
+
+    /// Scatters the particles using `--seed`, or a random seed which is printed so that the run can
+    /// be reproduced. There are none to begin with when pouring
+    fn initial_particles(&self) -> Vec<Particle> {
+        if self.pour {
+            return Vec::new();
+        }
+        let seed = self.seed.unwrap_or_else(rand::random);
+        println!("Seed: {}", seed);
+        let mut rng = seeded_rng(seed);
+        let mut particles = if self.planet {
+            dust_cloud(&mut rng, self.particles)
+        } else {
+            random_particles(&mut rng, self.particles, HALF_EXTENT)
+        };
+        if self.fluid {
+            assign_material(&mut particles, WATER, &Material::fluid());
+        }
+        mix_coarse_grains(
+            &mut rng,
+            &mut particles,
+            self.coarse_fraction,
+            self.coarse_radius,
+            PEBBLE,
+            &pebble(),
+        );
+        particles
+    }
 }
 
-async fn async_main() {
+async fn headless_main(options: Options) -> Result<(), SolError> {
+    let mut runner = HeadlessRunner::new(options.configuration(), options.fallback_adapter).await?;
+    println!("Adapter: {:?}", runner.adapter.get_info());
+
+    runner
+        .resources
+        .set_materials(&runner.queue, &materials())?;
+    runner
+        .resources
+        .set_colliders(&runner.queue, &options.colliders())?;
+    runner
+        .resources
+        .set_container(&runner.queue, options.container());
+    runner.populate(&options.initial_particles())?;
+    runner
+        .pipeline
+        .bounds_partition
+        .set_mode(options.bounds_mode());
+    runner.pipeline.emission.set_emitters(&options.emitters())?;
+    runner.pipeline.emission.set_sinks(&options.sinks())?;
+    // Headless runs are not animated, so the force fields stay where they start
+    runner
+        .pipeline
+        .simulation
+        .set_force_fields(&options.force_fields(0.0))?;
+    runner
+        .pipeline
+        .simulation
+        .set_self_gravity(options.self_gravity());
+    runner
+        .pipeline
+        .simulation
+        .set_gravity_model(options.gravity_model());
+    runner.pipeline.simulation.set_substeps(options.substeps);
+    runner
+        .pipeline
+        .simulation
+        .set_iterations(options.iterations);
+    runner
+        .pipeline
+        .simulation
+        .set_neighbour_search(options.neighbour_search());
+
+    let start_instant = Instant::now();
+    runner.run(options.frames, options.delta_time, options.gravity(0.0))?;
+    println!(
+        "Simulated {} frames in {:.2}s",
+        options.frames,
+        start_instant.elapsed().as_secs_f32()
+    );
+
+    let mut particles = runner.particles()?;
+    particles.retain(Particle::is_alive);
+    match options.output {
+        Some(path) => write_particles(&mut BufWriter::new(File::create(path)?), &particles)?,
+        None => write_particles(&mut std::io::stdout().lock(), &particles)?,
+    }
+    Ok(())
+}
+
+/// Calculates the bounds that the particles are partitioned within and builds the grid within them,
+/// timing both if the device supports timestamp queries
+async fn partition(
+    device: &Device,
+    queue: &Queue,
+    resources: &Resources,
+    pipeline: &mut FramePipeline,
+) -> Result<(), SolError> {
+    let timing = profile(device, queue, |command_encoder| {
+        pipeline.bounds_partition.calculate_bounds_with_encoder(
+            device,
+            queue,
+            command_encoder,
+            resources,
+        );
+    })
+    .await;
+    match timing {
+        Ok(timing) => println!("Calculate bounds duration: {}ms", timing.duration()),
+        Err(SolError::MissingFeatures(_)) => {
+            pipeline
+                .bounds_partition
+                .calculate_bounds(device, queue, resources)?;
+        }
+        Err(error) => return Err(error),
+    }
+    let data = read_buffer::<Bounds>(device, queue, resources.bounds_buffer())?;
+    println!("Bounds: {:?}", data);
+
+    let timing = profile(device, queue, |command_encoder| {
+        pipeline
+            .grid_partition
+            .build_grid_with_encoder(device, command_encoder, resources);
+    })
+    .await;
+    match timing {
+        Ok(timing) => println!("Build grid duration: {}ms", timing.duration()),
+        Err(SolError::MissingFeatures(_)) => {
+            pipeline
+                .grid_partition
+                .build_grid(device, queue, resources)?;
+        }
+        Err(error) => return Err(error),
+    }
+    // let data = read_buffer::<Vec<GridCell>>(device, queue, resources.grid_buffer())?;
+    // println!("Grid: {:?}", data);
+    // let total_grid_particles: u32 = data.iter().map(|element| element.particles_length).sum();
+    // println!(
+    //     "Particles {}, Grid Particles {}",
+    //     resources.configuration().particle_count, total_grid_particles
+    // );
+    Ok(())
+}
+
+/// Replaces a lost device with a new one from `adapter` and recreates everything that was created
+/// from the lost device, restarting the simulation from `particles`
+async fn recreate_device(
+    adapter: &Adapter,
+    surface: &Surface,
+    surface_configuration: &SurfaceConfiguration,
+    resources: &Resources,
+    pipeline: &FramePipeline,
+    particles: &[Particle],
+) -> Result<(Device, Queue, Resources, FramePipeline), SolError> {
+    let (device, queue) = request_device(adapter).await?;
+    surface.configure(&device, surface_configuration);
+    let mut new_resources = Resources::new(&device, &queue, *resources.configuration())?;
+    new_resources.set_materials(&queue, resources.materials())?;
+    new_resources.set_colliders(&queue, resources.colliders())?;
+    new_resources.set_container(&queue, resources.container());
+    new_resources.populate(&queue, particles)?;
+    let mut new_pipeline = pipeline.recreate(&device, &new_resources)?;
+    partition(&device, &queue, &new_resources, &mut new_pipeline).await?;
+    Ok((device, queue, new_resources, new_pipeline))
+}
+
+async fn async_main(options: Options) -> Result<(), SolError> {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("🌎")
         // .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)))
-        .build(&event_loop)
-        .unwrap();
+        .build(&event_loop)?;
 
     let instance = Instance::default();
-    let surface = unsafe { instance.create_surface(&window) }.unwrap();
+    let surface = unsafe { instance.create_surface(&window) }?;
     let adapter = instance
         .request_adapter(&RequestAdapterOptions {
             power_preference: PowerPreference::HighPerformance,
@@ -57,18 +664,8 @@ async fn async_main() {
             compatible_surface: Some(&surface),
         })
         .await
-        .expect("Failed to request adapter");
-    let (device, queue) = adapter
-        .request_device(
-            &DeviceDescriptor {
-                label: None,
-                features: Features::TIMESTAMP_QUERY,
-                limits: Limits::downlevel_defaults().using_resolution(adapter.limits()),
-            },
-            None,
-        )
-        .await
-        .expect("Failed to request device");
+        .ok_or(SolError::AdapterUnavailable)?;
+    let (mut device, mut queue) = request_device(&adapter).await?;
     let surface_capabilities = surface.get_capabilities(&adapter);
     let surface_formats = surface_capabilities.formats[0];
     let mut surface_configuration = SurfaceConfiguration {
@@ -81,84 +678,45 @@ async fn async_main() {
         view_formats: vec![],
     };
 
-    let simulation = Simulation::new(&device);
-
-    let mut rng = rand::thread_rng();
-    let mut particles = vec![
-        Particle {
-            position: Vec3::new(0.0, 0.0, 0.0),
-            old_position: Vec3::new(0.0, 0.0, 0.0)
-        };
-        MAX_PARTICLES as usize
-    ];
-
-    for particle in particles.iter_mut() {
-        let position = Vec3::new(
-            rng.gen_range(-16.0..16.0),
-            rng.gen_range(-16.0..16.0),
-            rng.gen_range(-16.0..16.0),
-        );
-        particle.position = position;
-        particle.old_position = position;
-    }
-
-    let mut encased_particle_buffer = StorageBuffer::new(Vec::<u8>::new());
-    encased_particle_buffer.write(&particles).unwrap();
-    let data = encased_particle_buffer.into_inner();
-    queue.write_buffer(&simulation.particle_buffer, 0, &data);
-
-    // let data = debug_buffer::<Vec<Particle>>(&device, &queue, &particle_buffer);
+    let particles = options.initial_particles();
+    let mut resources = Resources::new(&device, &queue, options.configuration())?;
+    resources.set_materials(&queue, &materials())?;
+    resources.set_colliders(&queue, &options.colliders())?;
+    resources.set_container(&queue, options.container());
+    resources.populate(&queue, &particles)?;
+
+    let mut pipeline = FramePipeline::new(&device, &resources, Some(surface_formats.into()))?;
+    pipeline.bounds_partition.set_mode(options.bounds_mode());
+    pipeline.emission.set_emitters(&options.emitters())?;
+    pipeline.emission.set_sinks(&options.sinks())?;
+    pipeline.simulation.set_self_gravity(options.self_gravity());
+    pipeline
+        .simulation
+        .set_gravity_model(options.gravity_model());
+    pipeline.simulation.set_substeps(options.substeps);
+    pipeline.simulation.set_iterations(options.iterations);
+    pipeline
+        .simulation
+        .set_neighbour_search(options.neighbour_search());
+
+    // let data = read_buffer::<Vec<Particle>>(&device, &queue, resources.particle_buffer())?;
     // println!("Particles {:?}", data);
 
-    let bounds_partition = BoundsPartition::new(&device);
-    let timing = profile(&device, &queue, |command_encoder| {
-        bounds_partition.calculate_bounds_with_encoder(
-            &device,
-            &queue,
-            command_encoder,
-            &simulation.particle_buffer,
-        );
-    })
-    .await;
-    // TODO: We should just rename this to some read buffer utility and then print it on the consumer side
-    let data = debug_buffer::<Bounds>(&device, &queue, &bounds_partition.bounds_buffer);
-    println!("Bounds: {:?}", data);
-    println!("Calculate bounds duration: {}ms", timing.duration());
-
-    let grid_partition = GridPartition::new(&device);
-    let timing = profile(&device, &queue, |command_encoder| {
-        grid_partition.build_grid_with_encoder(
-            &device,
-            command_encoder,
-            &simulation.particle_buffer,
-            &bounds_partition.bounds_buffer,
-        );
-    })
-    .await;
-    // TODO: We should just rename this to some read buffer utility and then print it on the consumer side
-    // let data = debug_buffer::<Vec<GridCell>>(&device, &queue, &grid_partition.grid_buffer);
-    // println!("Grid: {:?}", data);
-    // let total_grid_particles: u32 = data.iter().map(|element| element.particles_length).sum();
-    // println!(
-    //     "Max Particles {}, Grid Particles {}",
-    //     MAX_PARTICLES, total_grid_particles
-    // );
-    println!("Build grid duration: {}ms", timing.duration());
+    partition(&device, &queue, &resources, &mut pipeline).await?;
 
-    // let mut simulation = Simulation::new(8, 8, 8, &device);
-    // simulation.populate(&device, &queue);
     let mut distance = 64.;
     let mut camera = Camera::new();
     camera.position = camera.rotation * Vec3::new(0., 0., -distance);
 
-    let visualisation = Visualisation::new(&device, surface_formats.into());
-
     let mut is_focused = true;
     let mut frame_count = 0;
 
     let start_instant = Instant::now();
     let mut last_frame_time = start_instant;
     let mut previous_instant = start_instant;
+    let mut timestep = FixedTimestep::new(options.delta_time, MAX_CATCH_UP_STEPS);
+    let mut transport = Transport::default();
+    let mut time = 0.0;
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
@@ -168,6 +726,37 @@ async fn async_main() {
             } => {
                 is_focused = focused;
             }
+            // Space pauses and resumes, period steps once, minus and equals halve and double the
+            // time scale and zero resets it
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => match key {
+                VirtualKeyCode::Space => {
+                    transport.toggle_paused();
+                    println!("Paused: {}", transport.paused());
+                }
+                VirtualKeyCode::Period => transport.step_once(),
+                VirtualKeyCode::Minus | VirtualKeyCode::Equals | VirtualKeyCode::Key0 => {
+                    let time_scale = match key {
+                        VirtualKeyCode::Minus => transport.time_scale() * 0.5,
+                        VirtualKeyCode::Equals => transport.time_scale() * 2.0,
+                        _ => 1.0,
+                    };
+                    transport.set_time_scale(time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE));
+                    println!("Time scale: {}", transport.time_scale());
+                }
+                _ => {}
+            },
             Event::WindowEvent {
                 event: WindowEvent::CursorMoved { position, .. },
                 ..
@@ -210,7 +799,6 @@ async fn async_main() {
             }
             Event::MainEventsCleared => {
                 let instant = Instant::now();
-                let time = start_instant.elapsed().as_secs_f32();
                 let delta_time = instant.duration_since(previous_instant).as_secs_f32();
                 frame_count += 1;
 
@@ -226,54 +814,82 @@ async fn async_main() {
 
                 previous_instant = instant;
 
-                if (!is_focused) {
-                    return;
+                // Losing focus pauses the simulation, but it is still rendered. Deterministic runs
+                // advance by a single step of time per frame, regardless of how long it took, so
+                // that gravity only ever changes between steps at the same times
+                let steps = if !is_focused {
+                    0
+                } else if options.deterministic {
+                    transport.advance(&mut timestep, options.delta_time)
+                } else {
+                    transport.advance(&mut timestep, delta_time)
+                };
+                time += steps as f32 * timestep.step;
+
+                let gravity = options.gravity(time);
+
+                // A lost or outdated surface is reconfigured and the frame skipped
+                let result = pipeline
+                    .simulation
+                    .set_force_fields(&options.force_fields(time))
+                    .and_then(|()| current_texture(&surface, &device, &surface_configuration))
+                    .and_then(|current_texture| {
+                        let Some(current_texture) = current_texture else {
+                            return Ok(None);
+                        };
+                        let view = current_texture
+                            .texture
+                            .create_view(&TextureViewDescriptor::default());
+
+                        pipeline.run(
+                            &device,
+                            &queue,
+                            &mut resources,
+                            &Frame {
+                                delta_time: timestep.step,
+                                steps,
+                                gravity,
+                                target: Some(Target {
+                                    view: &view,
+                                    camera: &camera,
+                                }),
+                            },
+                        )?;
+                        Ok(Some(current_texture))
+                    });
+                match result {
+                    Ok(Some(current_texture)) => current_texture.present(),
+                    Ok(None) => {}
+                    Err(SolError::DeviceLost) => {
+                        eprintln!("Device lost, recreating it");
+                        match block_on(recreate_device(
+                            &adapter,
+                            &surface,
+                            &surface_configuration,
+                            &resources,
+                            &pipeline,
+                            &particles,
+                        )) {
+                            Ok((new_device, new_queue, new_resources, new_pipeline)) => {
+                                pipeline = new_pipeline;
+                                resources = new_resources;
+                                queue = new_queue;
+                                device = new_device;
+                            }
+                            Err(error) => {
+                                eprintln!("Failed to recreate device: {}", error);
+                                *control_flow = ControlFlow::ExitWithCode(1);
+                                return;
+                            }
+                        }
+                    }
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        *control_flow = ControlFlow::ExitWithCode(1);
+                        return;
+                    }
                 }
 
-                let gravity: Vec3 = Vec3::new(0.0, -9.8, 0.0);
-                let spin_rate = std::f32::consts::PI / 32.0;
-                let gravity_rotation = Quat::from_euler(
-                    glam::EulerRot::XYZ,
-                    spin_rate * time,
-                    spin_rate * time,
-                    spin_rate * time,
-                );
-                let rotated_gravity = gravity_rotation * gravity;
-
-                simulation.simulate(
-                    &device,
-                    &queue,
-                    &bounds_partition.bounds_buffer,
-                    &grid_partition.grid_buffer,
-                    delta_time,
-                    rotated_gravity,
-                );
-
-                // // TODO: `build_grid` is not stable and seems to produce different data even with the same input
-                grid_partition.build_grid(
-                    &device,
-                    &queue,
-                    &simulation.particle_buffer,
-                    &bounds_partition.bounds_buffer,
-                );
-
-                let current_texture = surface
-                    .get_current_texture()
-                    .expect("Failed to get current texture");
-                let view = current_texture
-                    .texture
-                    .create_view(&TextureViewDescriptor::default());
-                visualisation.visualise(
-                    &device,
-                    &queue,
-                    &view,
-                    &simulation.particle_buffer,
-                    &bounds_partition.bounds_buffer,
-                    &grid_partition.grid_buffer,
-                    &camera,
-                );
-                current_texture.present();
-
                 window.request_redraw();
             }
             // TODO: explicity destroy GPU resources
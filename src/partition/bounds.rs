@@ -1,32 +1,92 @@
-use crate::common::{Bounds, MAX_PARTICLES};
-use bytemuck::Zeroable;
-use encase::{ShaderSize, StorageBuffer};
+use crate::common::Bounds;
+use crate::error::SolError;
+use crate::resources::Resources;
+use crate::wgpu_utilities::{submit, Device, QueueUtilities};
+use bytemuck::{Pod, Zeroable};
+use encase::ShaderSize;
+use glam::IVec3;
 use std::borrow::Cow;
 use wgpu::{
-    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
     CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
-    ComputePipelineDescriptor, Device, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor,
+    ComputePipelineDescriptor, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor,
     ShaderSource, ShaderStages,
 };
 
 #[include_wgsl_oil::include_wgsl_oil("bounds.wgsl")]
 mod shader {}
+use shader::constants::MODE_DYNAMIC::VALUE as MODE_DYNAMIC;
+use shader::constants::MODE_FIXED::VALUE as MODE_FIXED;
+use shader::types::Uniforms;
+unsafe impl Pod for Uniforms {}
+unsafe impl Zeroable for Uniforms {}
+impl Copy for Uniforms {}
 
+/// How [`BoundsPartition`] moves the bounds as the particles move.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BoundsMode {
+    /// Always the given bounds, which particles outside of are binned into the nearest edge of the
+    /// grid
+    Fixed(Bounds),
+    /// The bounds that tightly enclose the particles, recalculated every time
+    #[default]
+    Dynamic,
+    /// The bounds that enclose the particles with `margin` to spare on every side. A side only
+    /// moves once a particle crosses it, or once every particle has moved more than `hysteresis`
+    /// further away from it than the margin, so that the grid does not change with every small
+    /// movement of the particles
+    DynamicWithMargin { margin: u32, hysteresis: u32 },
+}
+
+impl BoundsMode {
+    fn uniforms(self) -> Uniforms {
+        let (mode, margin, hysteresis, fixed_bounds) = match self {
+            BoundsMode::Fixed(bounds) => (MODE_FIXED, 0, 0, bounds),
+            BoundsMode::Dynamic => (MODE_DYNAMIC, 0, 0, Bounds::zeroed()),
+            BoundsMode::DynamicWithMargin { margin, hysteresis } => {
+                (MODE_DYNAMIC, margin, hysteresis, Bounds::zeroed())
+            }
+        };
+        Uniforms {
+            mode,
+            margin: margin as i32,
+            hysteresis: hysteresis as i32,
+            fixed_min: IVec3::new(fixed_bounds.min_x, fixed_bounds.min_y, fixed_bounds.min_z),
+            fixed_max: IVec3::new(fixed_bounds.max_x, fixed_bounds.max_y, fixed_bounds.max_z),
+        }
+    }
+}
+
+/// Keeps the integer [`crate::Bounds`] of [`Resources`] around the particles, according to its
+/// [`BoundsMode`]. The bounds are always at least 1 wide along each axis.
 pub struct BoundsPartition {
     bind_group_layout: BindGroupLayout,
+    bind_groups: [BindGroup; 2],
+    resources_generation: u64,
+    reset_bounds_pipeline: ComputePipeline,
     calculate_bounds_pipeline: ComputePipeline,
-    pub bounds_buffer: Buffer,
+    update_bounds_pipeline: ComputePipeline,
+    uniform_buffer: Buffer,
+    particle_bounds_buffer: Buffer,
+    mode: BoundsMode,
 }
 
 impl Drop for BoundsPartition {
     fn drop(&mut self) {
-        self.bounds_buffer.destroy();
+        self.uniform_buffer.destroy();
+        self.particle_bounds_buffer.destroy();
     }
 }
 
 impl BoundsPartition {
-    pub fn new(device: &Device) -> Self {
+    /// Creates the compute pipelines and binds the parameters, particle and bounds buffers of
+    /// `resources`.
+    pub fn new(device: &Device, resources: &Resources) -> Result<Self, SolError> {
+        device.capture_errors(|| Self::create(device, resources))
+    }
+
+    fn create(device: &Device, resources: &Resources) -> Self {
         let shader_module = device.create_shader_module(ShaderModuleDescriptor {
             label: None,
             source: ShaderSource::Wgsl(Cow::Borrowed(shader::SOURCE)),
@@ -45,6 +105,36 @@ impl BoundsPartition {
                     },
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: shader::globals::particle_bounds::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: shader::globals::uniforms::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
                 BindGroupLayoutEntry {
                     binding: shader::globals::bounds::binding::BINDING,
                     visibility: ShaderStages::COMPUTE,
@@ -64,88 +154,193 @@ impl BoundsPartition {
             push_constant_ranges: &[],
         });
 
-        let calculate_bounds_pipeline =
+        let [reset_bounds_pipeline, calculate_bounds_pipeline, update_bounds_pipeline] = [
+            shader::entry_points::reset_bounds::NAME,
+            shader::entry_points::calculate_bounds::NAME,
+            shader::entry_points::update_bounds::NAME,
+        ]
+        .map(|entry_point| {
             device.create_compute_pipeline(&ComputePipelineDescriptor {
                 label: None,
                 layout: Some(&pipeline_layout),
                 module: &shader_module,
-                entry_point: shader::entry_points::calculate_bounds::NAME,
-            });
+                entry_point,
+            })
+        });
 
-        let bounds_buffer = device.create_buffer(&BufferDescriptor {
-            size: Bounds::SHADER_SIZE.get(),
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
             label: None,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            size: Uniforms::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let particle_bounds_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("BoundsPartition::particle_bounds_buffer"),
+            size: Bounds::SHADER_SIZE.get(),
+            usage: BufferUsages::STORAGE,
             mapped_at_creation: false,
         });
 
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            &particle_bounds_buffer,
+            resources,
+        );
+
         BoundsPartition {
             bind_group_layout,
+            bind_groups,
+            resources_generation: resources.generation(),
+            reset_bounds_pipeline,
             calculate_bounds_pipeline,
-            bounds_buffer,
+            update_bounds_pipeline,
+            uniform_buffer,
+            particle_bounds_buffer,
+            mode: BoundsMode::default(),
         }
     }
 
-    pub fn calculate_bounds_with_encoder(
-        &self,
+    /// One bind group for each of the particle buffers of `resources`.
+    fn create_bind_groups(
         device: &Device,
-        queue: &Queue,
-        command_encoder: &mut CommandEncoder,
+        bind_group_layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        particle_bounds_buffer: &Buffer,
+        resources: &Resources,
+    ) -> [BindGroup; 2] {
+        resources
+            .particle_buffers()
+            .each_ref()
+            .map(|particle_buffer| {
+                Self::create_bind_group(
+                    device,
+                    bind_group_layout,
+                    uniform_buffer,
+                    particle_bounds_buffer,
+                    particle_buffer,
+                    resources,
+                )
+            })
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        particle_bounds_buffer: &Buffer,
         particle_buffer: &Buffer,
-    ) {
-        let mut encased_bounds_buffer = StorageBuffer::new(Vec::<u8>::new());
-        encased_bounds_buffer.write(&Bounds::zeroed()).unwrap();
-        queue.write_buffer(&self.bounds_buffer, 0, &encased_bounds_buffer.into_inner());
-
-        // TODO: We create a new bind group for every compute just as a way to dependency inject
-        // the particle buffer outside of the constructor,(which can get complicated due to bi-directional dependencies)
-        //
-        // See:
-        // let simulation = Simulation:new(bounds.bounds_buffer, ...);
-        // let bounds = Bounds:new(simulation.particle_buffer, ...);
-        //
-        // We could do something like:
-        // let simulation_resources = create_simulation_resources();
-        // let bounds_resources = create_bounds_resources();
-        // let simulation = Simulation:new(bounds_resources.bounds_buffer, ...);
-        // let bounds = Bounds:new(simulation_resources.particle_buffer, ...);
-        //
-        // Otherwise we just keep the following:
-        // let simulation = Simulation:new(...);
-        // let bounds = Bounds:new(...);
-        // bounds.calculate(&simulation.particle_buffer)
-        // simulation.simulate(&bounds.bounds_buffer)
-
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        resources: &Resources,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
             label: None,
-            layout: &self.bind_group_layout,
+            layout: bind_group_layout,
             entries: &[
                 BindGroupEntry {
                     binding: shader::globals::particles::binding::BINDING,
                     resource: particle_buffer.as_entire_binding(),
                 },
+                BindGroupEntry {
+                    binding: shader::globals::particle_bounds::binding::BINDING,
+                    resource: particle_bounds_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    resource: resources.parameters_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::uniforms::binding::BINDING,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
                 BindGroupEntry {
                     binding: shader::globals::bounds::binding::BINDING,
-                    resource: self.bounds_buffer.as_entire_binding(),
+                    resource: resources.bounds_buffer().as_entire_binding(),
                 },
             ],
-        });
+        })
+    }
+
+    /// Rebuilds the bind groups if the buffers of `resources` have been reallocated since they were
+    /// created.
+    fn update_bind_groups(&mut self, device: &Device, resources: &Resources) {
+        if self.resources_generation != resources.generation() {
+            self.bind_groups = Self::create_bind_groups(
+                device,
+                &self.bind_group_layout,
+                &self.uniform_buffer,
+                &self.particle_bounds_buffer,
+                resources,
+            );
+            self.resources_generation = resources.generation();
+        }
+    }
+
+    pub fn mode(&self) -> BoundsMode {
+        self.mode
+    }
+
+    /// Changes how the bounds follow the particles from the next time they are calculated.
+    ///
+    /// Fixed bounds are not recalculated from the particles at all. Dynamic bounds are rebuilt from
+    /// scratch each time, and so change with every movement of the particles, which margin and
+    /// hysteresis avoid.
+    pub fn set_mode(&mut self, mode: BoundsMode) {
+        if let BoundsMode::Fixed(bounds) = mode {
+            assert!(
+                bounds.min_x <= bounds.max_x
+                    && bounds.min_y <= bounds.max_y
+                    && bounds.min_z <= bounds.max_z,
+                "bounds cannot be inside out"
+            );
+        }
+        self.mode = mode;
+    }
+
+    /// Records the bounds calculation into `command_encoder`.
+    ///
+    /// The uniforms are written through `queue`, so they take effect when `command_encoder` is next
+    /// submitted.
+    pub fn calculate_bounds_with_encoder(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_encoder: &mut CommandEncoder,
+        resources: &Resources,
+    ) {
+        self.update_bind_groups(device, resources);
+        queue.write_encased_uniform_buffer(&self.uniform_buffer, self.mode.uniforms());
+
         let mut compute_pass =
             command_encoder.begin_compute_pass(&ComputePassDescriptor { label: None });
-        compute_pass.set_pipeline(&self.calculate_bounds_pipeline);
-        compute_pass.set_bind_group(0, &bind_group, &[]);
-        let workgroup_size = shader::entry_points::calculate_bounds::WORKGROUP_SIZE;
-        compute_pass.dispatch_workgroups(
-            (MAX_PARTICLES as f32 / workgroup_size[0] as f32).ceil() as u32,
-            workgroup_size[1],
-            workgroup_size[2],
-        );
+        compute_pass.set_bind_group(0, &self.bind_groups[resources.particle_buffer_index()], &[]);
+        if !matches!(self.mode, BoundsMode::Fixed(_)) {
+            // Reset within the encoder, rather than through the queue, so that the bounds can be
+            // recalculated more than once per submission
+            compute_pass.set_pipeline(&self.reset_bounds_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+
+            compute_pass.set_pipeline(&self.calculate_bounds_pipeline);
+            compute_pass.dispatch_workgroups_indirect(resources.particle_counters_buffer(), 0);
+        }
+        compute_pass.set_pipeline(&self.update_bounds_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
     }
 
-    pub fn calculate_bounds(&self, device: &Device, queue: &Queue, particle_buffer: &Buffer) {
-        let mut command_encoder =
-            device.create_command_encoder(&CommandEncoderDescriptor { label: None });
-        self.calculate_bounds_with_encoder(device, queue, &mut command_encoder, particle_buffer);
-        queue.submit(Some(command_encoder.finish()));
+    /// Calculates the bounds and submits the work to `queue`.
+    pub fn calculate_bounds(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        resources: &Resources,
+    ) -> Result<(), SolError> {
+        let command_buffer = device.capture_errors(|| {
+            let mut command_encoder =
+                device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            self.calculate_bounds_with_encoder(device, queue, &mut command_encoder, resources);
+            command_encoder.finish()
+        })?;
+        submit(device, queue, command_buffer)
     }
 }
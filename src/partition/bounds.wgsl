@@ -1,5 +1,22 @@
 #import ../common.wgsl as Common
 
+// How the bounds follow the particles
+const MODE_FIXED = 0u;
+const MODE_DYNAMIC = 1u;
+
+@export struct Uniforms {
+  // One of the `MODE_*` constants
+  mode: u32,
+  // Distance the dynamic bounds are grown by beyond the particles
+  margin: i32,
+  // Distance the particles can move away from a side of the dynamic bounds before that side shrinks
+  // back towards them
+  hysteresis: i32,
+  // The bounds in `MODE_FIXED`
+  fixed_min: vec3<i32>,
+  fixed_max: vec3<i32>,
+}
+
 struct AtomicBounds {
   min_x: atomic<i32>,
   min_y: atomic<i32>,
@@ -13,20 +30,99 @@ struct AtomicBounds {
 @binding(0)
 var<storage, read> particles: array<Common::Particle>;
 
-
+// The bounds that tightly enclose the particles this frame
 @group(0)
 @binding(1)
-var<storage, read_write> bounds: AtomicBounds;
+var<storage, read_write> particle_bounds: AtomicBounds;
+
+@group(0)
+@binding(2)
+var<uniform> parameters: Common::Parameters;
+
+@group(0)
+@binding(3)
+var<uniform> uniforms: Uniforms;
+
+// The bounds the particles are partitioned within, which persist between frames
+@group(0)
+@binding(4)
+var<storage, read_write> bounds: Common::Bounds;
 
+// Further than any particle can be, in either direction
+const FAR = 2147483647;
+
+// Empties the particle bounds, so that any particle grows them
 @compute
 @workgroup_size(1)
+fn reset_bounds() {
+  atomicStore(&particle_bounds.min_x, FAR);
+  atomicStore(&particle_bounds.min_y, FAR);
+  atomicStore(&particle_bounds.min_z, FAR);
+  atomicStore(&particle_bounds.max_x, -FAR);
+  atomicStore(&particle_bounds.max_y, -FAR);
+  atomicStore(&particle_bounds.max_z, -FAR);
+}
+
+@compute
+@workgroup_size(64)
 fn calculate_bounds(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
   let particle_index = global_invocation_id.x;
+  if (particle_index >= parameters.particle_count) {
+    return;
+  }
   let particle = particles[particle_index];
-  atomicMin(&bounds.min_x, i32(floor(particle.position.x)));
-  atomicMin(&bounds.min_y, i32(floor(particle.position.y)));
-  atomicMin(&bounds.min_z, i32(floor(particle.position.z)));
-  atomicMax(&bounds.max_x, i32(ceil(particle.position.x)));
-  atomicMax(&bounds.max_y, i32(ceil(particle.position.y)));
-  atomicMax(&bounds.max_z, i32(ceil(particle.position.z)));
+  if (particle.alive == 0u) {
+    return;
+  }
+  atomicMin(&particle_bounds.min_x, i32(floor(particle.position.x)));
+  atomicMin(&particle_bounds.min_y, i32(floor(particle.position.y)));
+  atomicMin(&particle_bounds.min_z, i32(floor(particle.position.z)));
+  atomicMax(&particle_bounds.max_x, i32(ceil(particle.position.x)));
+  atomicMax(&particle_bounds.max_y, i32(ceil(particle.position.y)));
+  atomicMax(&particle_bounds.max_z, i32(ceil(particle.position.z)));
+}
+
+// Moves the bounds according to the mode
+@compute
+@workgroup_size(1)
+fn update_bounds() {
+  var bounds_min = vec3<i32>(bounds.min_x, bounds.min_y, bounds.min_z);
+  var bounds_max = vec3<i32>(bounds.max_x, bounds.max_y, bounds.max_z);
+  if (uniforms.mode == MODE_FIXED) {
+    bounds_min = uniforms.fixed_min;
+    bounds_max = uniforms.fixed_max;
+  } else {
+    let particles_min = vec3<i32>(
+      atomicLoad(&particle_bounds.min_x),
+      atomicLoad(&particle_bounds.min_y),
+      atomicLoad(&particle_bounds.min_z),
+    );
+    let particles_max = vec3<i32>(
+      atomicLoad(&particle_bounds.max_x),
+      atomicLoad(&particle_bounds.max_y),
+      atomicLoad(&particle_bounds.max_z),
+    );
+    // Without any particles there is nothing to follow, so keep the previous bounds
+    if (all(particles_min <= particles_max)) {
+      bounds_min = follow_min(bounds_min, particles_min);
+      bounds_max = -follow_min(-bounds_max, -particles_max);
+    }
+  }
+  // The grid divides by the size of the bounds, so keep them at least 1 wide
+  bounds_max = max(bounds_max, bounds_min + 1);
+  bounds.min_x = bounds_min.x;
+  bounds.min_y = bounds_min.y;
+  bounds.min_z = bounds_min.z;
+  bounds.max_x = bounds_max.x;
+  bounds.max_y = bounds_max.y;
+  bounds.max_z = bounds_max.z;
+}
+
+// The minimum side of the bounds, moved to `margin` beyond the particles once they cross it or move
+// more than `hysteresis` further away from it
+fn follow_min(bounds_min: vec3<i32>, particles_min: vec3<i32>) -> vec3<i32> {
+  let target_min = particles_min - uniforms.margin;
+  let crossed = particles_min < bounds_min;
+  let left = bounds_min < target_min - uniforms.hysteresis;
+  return select(bounds_min, target_min, crossed | left);
 }
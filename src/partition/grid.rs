@@ -1,32 +1,38 @@
-use crate::common::{GridCell, GRID_SIZE, MAX_PARTICLES};
-use encase::ShaderSize;
+use crate::error::SolError;
+use crate::resources::Resources;
+use crate::wgpu_utilities::{submit, Device};
 use std::borrow::Cow;
 use wgpu::{
-    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
-    CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
-    ComputePipelineDescriptor, Device, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor,
-    ShaderSource, ShaderStages,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, CommandEncoder,
+    CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+    PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor, ShaderSource, ShaderStages,
 };
 
 #[include_wgsl_oil::include_wgsl_oil("grid.wgsl")]
 mod shader {}
 
+/// Bins the particles into a uniform grid of [`crate::GridCell`]s spanning the current bounds.
+///
+/// Every particle is binned into each cell that its bounding box overlaps, and the particles within
+/// each cell are kept in order of index. A cell that overflows keeps the particles with the lowest
+/// indices.
 pub struct GridPartition {
     bind_group_layout: BindGroupLayout,
+    bind_groups: [BindGroup; 2],
+    resources_generation: u64,
     clear_grid_pipeline: ComputePipeline,
     build_grid_pipeline: ComputePipeline,
-    pub grid_buffer: Buffer,
 }
 
-impl Drop for GridPartition {
-    fn drop(&mut self) {
-        self.grid_buffer.destroy();
+impl GridPartition {
+    /// Creates the compute pipelines and binds the parameters, particle, bounds and grid buffers of
+    /// `resources`.
+    pub fn new(device: &Device, resources: &Resources) -> Result<Self, SolError> {
+        device.capture_errors(|| Self::create(device, resources))
     }
-}
 
-impl GridPartition {
-    pub fn new(device: &Device) -> Self {
+    fn create(device: &Device, resources: &Resources) -> Self {
         let shader_module = device.create_shader_module(ShaderModuleDescriptor {
             label: None,
             source: ShaderSource::Wgsl(Cow::Borrowed(shader::SOURCE)),
@@ -65,6 +71,26 @@ impl GridPartition {
                     },
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: shader::globals::grid_particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -88,31 +114,40 @@ impl GridPartition {
             entry_point: shader::entry_points::build_grid::NAME,
         });
 
-        let grid_buffer = device.create_buffer(&BufferDescriptor {
-            size: GridCell::SHADER_SIZE.get() * (GRID_SIZE * GRID_SIZE * GRID_SIZE) as u64,
-            label: None,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
+        let bind_groups = Self::create_bind_groups(device, &bind_group_layout, resources);
 
         GridPartition {
             bind_group_layout,
+            bind_groups,
+            resources_generation: resources.generation(),
             clear_grid_pipeline,
             build_grid_pipeline,
-            grid_buffer,
         }
     }
 
-    pub fn build_grid_with_encoder(
-        &self,
+    /// One bind group for each of the particle buffers of `resources`.
+    fn create_bind_groups(
         device: &Device,
-        command_encoder: &mut CommandEncoder,
+        bind_group_layout: &BindGroupLayout,
+        resources: &Resources,
+    ) -> [BindGroup; 2] {
+        resources
+            .particle_buffers()
+            .each_ref()
+            .map(|particle_buffer| {
+                Self::create_bind_group(device, bind_group_layout, particle_buffer, resources)
+            })
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
         particle_buffer: &Buffer,
-        bounds_buffer: &Buffer,
-    ) {
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        resources: &Resources,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
             label: None,
-            layout: &self.bind_group_layout,
+            layout: bind_group_layout,
             entries: &[
                 BindGroupEntry {
                     binding: shader::globals::particles::binding::BINDING,
@@ -120,46 +155,74 @@ impl GridPartition {
                 },
                 BindGroupEntry {
                     binding: shader::globals::bounds::binding::BINDING,
-                    resource: bounds_buffer.as_entire_binding(),
+                    resource: resources.bounds_buffer().as_entire_binding(),
                 },
                 BindGroupEntry {
                     binding: shader::globals::grid::binding::BINDING,
-                    resource: self.grid_buffer.as_entire_binding(),
+                    resource: resources.grid_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::grid_particles::binding::BINDING,
+                    resource: resources.grid_particles_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    resource: resources.parameters_buffer().as_entire_binding(),
                 },
             ],
-        });
+        })
+    }
+
+    /// Rebuilds the bind groups if the buffers of `resources` have been reallocated since they were
+    /// created.
+    fn update_bind_groups(&mut self, device: &Device, resources: &Resources) {
+        if self.resources_generation != resources.generation() {
+            self.bind_groups = Self::create_bind_groups(device, &self.bind_group_layout, resources);
+            self.resources_generation = resources.generation();
+        }
+    }
+
+    /// Records clearing and rebuilding the grid into `command_encoder`.
+    pub fn build_grid_with_encoder(
+        &mut self,
+        device: &Device,
+        command_encoder: &mut CommandEncoder,
+        resources: &Resources,
+    ) {
+        self.update_bind_groups(device, resources);
 
         let mut compute_pass =
             command_encoder.begin_compute_pass(&ComputePassDescriptor { label: None });
-        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.set_bind_group(0, &self.bind_groups[resources.particle_buffer_index()], &[]);
 
+        let configuration = resources.configuration();
+
+        let grid_size = configuration.grid_size;
         let workgroup_size = shader::entry_points::clear_grid::WORKGROUP_SIZE;
         compute_pass.set_pipeline(&self.clear_grid_pipeline);
         compute_pass.dispatch_workgroups(
-            (GRID_SIZE as f32 / workgroup_size[0] as f32).ceil() as u32,
-            (GRID_SIZE as f32 / workgroup_size[1] as f32).ceil() as u32,
-            (GRID_SIZE as f32 / workgroup_size[2] as f32).ceil() as u32,
+            (grid_size.x as f32 / workgroup_size[0] as f32).ceil() as u32,
+            (grid_size.y as f32 / workgroup_size[1] as f32).ceil() as u32,
+            (grid_size.z as f32 / workgroup_size[2] as f32).ceil() as u32,
         );
 
-        let workgroup_size = shader::entry_points::build_grid::WORKGROUP_SIZE;
         compute_pass.set_pipeline(&self.build_grid_pipeline);
-        compute_pass.dispatch_workgroups(
-            (MAX_PARTICLES as f32 / workgroup_size[0] as f32).ceil() as u32,
-            workgroup_size[1],
-            workgroup_size[2],
-        );
+        compute_pass.dispatch_workgroups_indirect(resources.particle_counters_buffer(), 0);
     }
 
+    /// Rebuilds the grid and submits the work to `queue`.
     pub fn build_grid(
-        &self,
+        &mut self,
         device: &Device,
         queue: &Queue,
-        particle_buffer: &Buffer,
-        bounds_buffer: &Buffer,
-    ) {
-        let mut command_encoder =
-            device.create_command_encoder(&CommandEncoderDescriptor { label: None });
-        self.build_grid_with_encoder(device, &mut command_encoder, particle_buffer, bounds_buffer);
-        queue.submit(Some(command_encoder.finish()));
+        resources: &Resources,
+    ) -> Result<(), SolError> {
+        let command_buffer = device.capture_errors(|| {
+            let mut command_encoder =
+                device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            self.build_grid_with_encoder(device, &mut command_encoder, resources);
+            command_encoder.finish()
+        })?;
+        submit(device, queue, command_buffer)
     }
 }
@@ -1,7 +1,10 @@
 #import ../common.wgsl as Common
 
+// Marks the free slots of a cell, above every particle index so that `insert_particle` fills the
+// slots in order
+const EMPTY_SLOT = 4294967295u;
+
 @export struct AtomicGridCell {
-  particles: array<u32, Common::MAX_PARTICLES_PER_GRID_CELL>,
   particles_length: atomic<u32>,
 }
 
@@ -17,35 +20,66 @@ var<storage, read> bounds: Common::Bounds;
 @binding(2)
 var<storage, read_write> grid: array<AtomicGridCell>;
 
+@group(0)
+@binding(3)
+var<storage, read_write> grid_particles: array<atomic<u32>>;
+
+@group(0)
+@binding(4)
+var<uniform> parameters: Common::Parameters;
+
 @compute
-@workgroup_size(1, 1, 1)
+@workgroup_size(4, 4, 4)
 fn clear_grid(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+  if (any(global_invocation_id >= parameters.grid_size)) {
+    return;
+  }
   let grid_position = vec3<i32>(global_invocation_id);
-  let grid_index = Common::grid_position_to_grid_index(grid_position);
-  grid[grid_index].particles_length = 0u;
+  let grid_index = Common::grid_position_to_grid_index(grid_position, parameters);
+  atomicStore(&grid[grid_index].particles_length, 0u);
+  for (var i = 0u; i < parameters.max_particles_per_grid_cell; i++) {
+    atomicStore(&grid_particles[Common::grid_particle_index(grid_index, i, parameters)], EMPTY_SLOT);
+  }
 }
 
 @compute
-@workgroup_size(1)
+@workgroup_size(64)
 fn build_grid(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
   let particle_index = global_invocation_id.x;
+  if (particle_index >= parameters.particle_count) {
+    return;
+  }
   let particle = particles[particle_index];
-  let bounds_min = vec3<i32>(bounds.min_x, bounds.min_y, bounds.min_z);
-  let bounds_max = vec3<i32>(bounds.max_x, bounds.max_y, bounds.max_z);
-  // TODO: Replace this with actual particle radius rather than constant
-  // TODO: Not sure why we need the `* 4.0` here, but it seems to ensure that a particle is populated in all influenced cells
-  let min_grid_position = clamp(Common::world_position_to_grid_position(particle.position - vec3<f32>(Common::PARTICLE_RADIUS * 4.0), bounds), bounds_min, bounds_max);
-  let max_grid_position = clamp(Common::world_position_to_grid_position(particle.position + vec3<f32>(Common::PARTICLE_RADIUS * 4.0), bounds), bounds_min, bounds_max);
+  if (particle.alive == 0u) {
+    return;
+  }
+  // Each particle is binned into every cell that its bounding box overlaps, so particles of any
+  // size are found by searching the cells that the searching particle overlaps. The margin keeps
+  // finding them as they move during the step
+  let range = Common::world_sphere_to_grid_range(particle.position, particle.radius + Common::GRID_MARGIN, bounds, parameters);
   var grid_position = vec3<i32>();
-  for (grid_position.x = min_grid_position.x; grid_position.x <= max_grid_position.x; grid_position.x++) {
-    for (grid_position.y = min_grid_position.y; grid_position.y <= max_grid_position.y; grid_position.y++) {
-      for (grid_position.z = min_grid_position.z; grid_position.z <= max_grid_position.z; grid_position.z++) {
-        let grid_index = Common::grid_position_to_grid_index(grid_position);
-        let particles_length = atomicAdd(&grid[grid_index].particles_length, 1u);
-        if (particles_length < Common::MAX_PARTICLES_PER_GRID_CELL) {
-          grid[grid_index].particles[particles_length] = particle_index;
-        }
+  for (grid_position.x = range.min.x; grid_position.x <= range.max.x; grid_position.x++) {
+    for (grid_position.y = range.min.y; grid_position.y <= range.max.y; grid_position.y++) {
+      for (grid_position.z = range.min.z; grid_position.z <= range.max.z; grid_position.z++) {
+        let grid_index = Common::grid_position_to_grid_index(grid_position, parameters);
+        atomicAdd(&grid[grid_index].particles_length, 1u);
+        insert_particle(grid_index, particle_index);
       }
     }
   }
-}
\ No newline at end of file
+}
+
+// Inserts `particle_index` into the slots of the cell at `grid_index`, which are kept sorted by
+// index. Each slot keeps the lower of its index and the one being inserted, and passes the higher
+// one on to the next slot, so however the atomics resolve, every cell ends up with the particles
+// with the lowest indices in order, and drops the rest if it overflows
+fn insert_particle(grid_index: i32, particle_index: u32) {
+  var carried_particle_index = particle_index;
+  for (var i = 0u; i < parameters.max_particles_per_grid_cell; i++) {
+    let slot = atomicMin(&grid_particles[Common::grid_particle_index(grid_index, i, parameters)], carried_particle_index);
+    if (slot == EMPTY_SLOT) {
+      return;
+    }
+    carried_particle_index = max(slot, carried_particle_index);
+  }
+}
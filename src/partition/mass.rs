@@ -0,0 +1,298 @@
+use crate::error::SolError;
+use crate::resources::Resources;
+use crate::wgpu_utilities::{submit, Device};
+use encase::ShaderSize;
+use std::borrow::Cow;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages,
+};
+
+#[include_wgsl_oil::include_wgsl_oil("mass.wgsl")]
+mod shader {}
+
+pub use shader::constants::MASS_FIXED_POINT_SCALE::VALUE as MASS_FIXED_POINT_SCALE;
+use shader::types::AtomicCellMassSum;
+
+/// Sums the particles in each cell of the grid into a [`crate::common::MassCell`], and each block
+/// of 2x2x2 cells into a cell of a coarser grid above it, up to a grid of a handful of cells, so
+/// that the gravity between distant particles can be approximated by that between the cells they
+/// are in.
+///
+/// Each particle is counted in the cell its centre is in, however full that cell of the grid is, so
+/// only the bounds must have been calculated from the current particles.
+pub struct MassPartition {
+    bind_group_layout: BindGroupLayout,
+    bind_groups: [BindGroup; 2],
+    resources_generation: u64,
+    // The fixed point sums of the particles in each cell of the grid, which is as large as the grid
+    cell_mass_sums_buffer: Buffer,
+    add_particle_masses_pipeline: ComputePipeline,
+    sum_cell_masses_pipeline: ComputePipeline,
+    sum_coarse_cell_masses_pipeline: ComputePipeline,
+}
+
+impl Drop for MassPartition {
+    fn drop(&mut self) {
+        self.cell_mass_sums_buffer.destroy();
+    }
+}
+
+impl MassPartition {
+    /// Creates the compute pipelines and binds the parameters, particle, bounds and mass grid
+    /// buffers of `resources`.
+    pub fn new(device: &Device, resources: &Resources) -> Result<Self, SolError> {
+        device.capture_errors(|| Self::create(device, resources))
+    }
+
+    fn create(device: &Device, resources: &Resources) -> Self {
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(Cow::Borrowed(shader::SOURCE)),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: shader::globals::particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: shader::globals::bounds::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: shader::globals::cell_mass_sums::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: shader::globals::mass_grid::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let add_particle_masses_pipeline =
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: shader::entry_points::add_particle_masses::NAME,
+            });
+
+        let sum_cell_masses_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: shader::entry_points::sum_cell_masses::NAME,
+        });
+
+        let sum_coarse_cell_masses_pipeline =
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: shader::entry_points::sum_coarse_cell_masses::NAME,
+            });
+
+        let cell_mass_sums_buffer = Self::create_cell_mass_sums_buffer(device, resources);
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &cell_mass_sums_buffer,
+            resources,
+        );
+
+        MassPartition {
+            bind_group_layout,
+            bind_groups,
+            resources_generation: resources.generation(),
+            cell_mass_sums_buffer,
+            add_particle_masses_pipeline,
+            sum_cell_masses_pipeline,
+            sum_coarse_cell_masses_pipeline,
+        }
+    }
+
+    fn create_cell_mass_sums_buffer(device: &Device, resources: &Resources) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("MassPartition::cell_mass_sums_buffer"),
+            size: AtomicCellMassSum::SHADER_SIZE.get()
+                * resources.configuration().grid_cell_count() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// One bind group for each of the particle buffers of `resources`.
+    fn create_bind_groups(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        cell_mass_sums_buffer: &Buffer,
+        resources: &Resources,
+    ) -> [BindGroup; 2] {
+        resources
+            .particle_buffers()
+            .each_ref()
+            .map(|particle_buffer| {
+                Self::create_bind_group(
+                    device,
+                    bind_group_layout,
+                    particle_buffer,
+                    cell_mass_sums_buffer,
+                    resources,
+                )
+            })
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        particle_buffer: &Buffer,
+        cell_mass_sums_buffer: &Buffer,
+        resources: &Resources,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: shader::globals::particles::binding::BINDING,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::bounds::binding::BINDING,
+                    resource: resources.bounds_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    resource: resources.parameters_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::cell_mass_sums::binding::BINDING,
+                    resource: cell_mass_sums_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::mass_grid::binding::BINDING,
+                    resource: resources.mass_grid_buffer().as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Rebuilds the bind groups if the buffers of `resources` have been reallocated since they were
+    /// created, reallocating the cell mass sums along with the grid.
+    fn update_bind_groups(&mut self, device: &Device, resources: &Resources) {
+        if self.resources_generation != resources.generation() {
+            let cell_mass_sums_buffer = Self::create_cell_mass_sums_buffer(device, resources);
+            std::mem::replace(&mut self.cell_mass_sums_buffer, cell_mass_sums_buffer).destroy();
+            self.bind_groups = Self::create_bind_groups(
+                device,
+                &self.bind_group_layout,
+                &self.cell_mass_sums_buffer,
+                resources,
+            );
+            self.resources_generation = resources.generation();
+        }
+    }
+
+    /// Records summing the mass grid from the particles into `command_encoder`.
+    pub fn sum_masses_with_encoder(
+        &mut self,
+        device: &Device,
+        command_encoder: &mut CommandEncoder,
+        resources: &Resources,
+    ) {
+        self.update_bind_groups(device, resources);
+        command_encoder.clear_buffer(&self.cell_mass_sums_buffer, 0, None);
+
+        let mut compute_pass =
+            command_encoder.begin_compute_pass(&ComputePassDescriptor { label: None });
+        compute_pass.set_bind_group(0, &self.bind_groups[resources.particle_buffer_index()], &[]);
+
+        compute_pass.set_pipeline(&self.add_particle_masses_pipeline);
+        compute_pass.dispatch_workgroups_indirect(resources.particle_counters_buffer(), 0);
+
+        let configuration = resources.configuration();
+
+        let grid_size = configuration.grid_size;
+        let workgroup_size = shader::entry_points::sum_cell_masses::WORKGROUP_SIZE;
+        compute_pass.set_pipeline(&self.sum_cell_masses_pipeline);
+        compute_pass.dispatch_workgroups(
+            (grid_size.x as f32 / workgroup_size[0] as f32).ceil() as u32,
+            (grid_size.y as f32 / workgroup_size[1] as f32).ceil() as u32,
+            (grid_size.z as f32 / workgroup_size[2] as f32).ceil() as u32,
+        );
+
+        let coarse_cell_count = configuration.mass_cell_count() - configuration.grid_cell_count();
+        if coarse_cell_count > 0 {
+            let workgroup_size = shader::entry_points::sum_coarse_cell_masses::WORKGROUP_SIZE;
+            compute_pass.set_pipeline(&self.sum_coarse_cell_masses_pipeline);
+            compute_pass.dispatch_workgroups(
+                (coarse_cell_count as f32 / workgroup_size[0] as f32).ceil() as u32,
+                workgroup_size[1],
+                workgroup_size[2],
+            );
+        }
+    }
+
+    /// Sums the mass grid and submits the work to `queue`.
+    pub fn sum_masses(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        resources: &Resources,
+    ) -> Result<(), SolError> {
+        let command_buffer = device.capture_errors(|| {
+            let mut command_encoder =
+                device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            self.sum_masses_with_encoder(device, &mut command_encoder, resources);
+            command_encoder.finish()
+        })?;
+        submit(device, queue, command_buffer)
+    }
+}
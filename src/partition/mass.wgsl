@@ -0,0 +1,140 @@
+#import ../common.wgsl as Common
+
+@group(0)
+@binding(0)
+var<storage, read> particles: array<Common::Particle>;
+
+@group(0)
+@binding(1)
+var<storage, read> bounds: Common::Bounds;
+
+@group(0)
+@binding(2)
+var<uniform> parameters: Common::Parameters;
+
+@group(0)
+@binding(3)
+var<storage, read_write> cell_mass_sums: array<AtomicCellMassSum>;
+
+@group(0)
+@binding(4)
+var<storage, read_write> mass_grid: array<Common::MassCell>;
+
+// Masses are summed in fixed point, in units of `1 / MASS_FIXED_POINT_SCALE`, so that the sums are
+// the same whatever order the particles are added in. A cell can hold up to about a million units
+// of mass
+const MASS_FIXED_POINT_SCALE = 4096.0;
+
+// The mass of the particles whose centres are within a cell of the grid, and their moment about the
+// centre of the cell in cell widths, both in fixed point
+@export struct AtomicCellMassSum {
+  moment_x: atomic<i32>,
+  moment_y: atomic<i32>,
+  moment_z: atomic<i32>,
+  mass: atomic<u32>,
+}
+
+// Distance between the centres of neighbouring cells of the grid along each axis
+fn cell_width() -> vec3<f32> {
+  let bounds_min = vec3<f32>(vec3<i32>(bounds.min_x, bounds.min_y, bounds.min_z));
+  let bounds_max = vec3<f32>(vec3<i32>(bounds.max_x, bounds.max_y, bounds.max_z));
+  return (bounds_max - bounds_min) / vec3<f32>(max(parameters.grid_size, vec3<u32>(2u)) - 1u);
+}
+
+fn cell_centre(grid_position: vec3<i32>) -> vec3<f32> {
+  let bounds_min = vec3<f32>(vec3<i32>(bounds.min_x, bounds.min_y, bounds.min_z));
+  return bounds_min + vec3<f32>(grid_position) * cell_width();
+}
+
+// Adds every live particle to the cell its centre is in, however many particles the cell holds
+@compute
+@workgroup_size(64)
+fn add_particle_masses(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+  let particle_index = global_invocation_id.x;
+  if (particle_index >= parameters.particle_count) {
+    return;
+  }
+  let particle = particles[particle_index];
+  if (particle.alive == 0u) {
+    return;
+  }
+  let grid_position = Common::world_position_to_clamped_grid_position(particle.position, bounds, parameters);
+  let grid_index = Common::grid_position_to_grid_index(grid_position, parameters);
+  let mass = u32(round(particle.mass * MASS_FIXED_POINT_SCALE));
+  let offset = (particle.position - cell_centre(grid_position)) / cell_width();
+  let moment = vec3<i32>(round(offset * f32(mass)));
+  atomicAdd(&cell_mass_sums[grid_index].moment_x, moment.x);
+  atomicAdd(&cell_mass_sums[grid_index].moment_y, moment.y);
+  atomicAdd(&cell_mass_sums[grid_index].moment_z, moment.z);
+  atomicAdd(&cell_mass_sums[grid_index].mass, mass);
+}
+
+// Converts the fixed point sums of each cell of the grid into the first level of the mass grid
+@compute
+@workgroup_size(4, 4, 4)
+fn sum_cell_masses(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+  if (any(global_invocation_id >= parameters.grid_size)) {
+    return;
+  }
+  let grid_position = vec3<i32>(global_invocation_id);
+  let grid_index = Common::grid_position_to_grid_index(grid_position, parameters);
+  let mass = atomicLoad(&cell_mass_sums[grid_index].mass);
+  var cell = Common::MassCell(vec3<f32>(0.0), 0.0);
+  if (mass > 0u) {
+    let moment = vec3<i32>(
+      atomicLoad(&cell_mass_sums[grid_index].moment_x),
+      atomicLoad(&cell_mass_sums[grid_index].moment_y),
+      atomicLoad(&cell_mass_sums[grid_index].moment_z),
+    );
+    let centre = cell_centre(grid_position) + vec3<f32>(moment) / f32(mass) * cell_width();
+    cell = Common::MassCell(centre, f32(mass) / MASS_FIXED_POINT_SCALE);
+  }
+  mass_grid[Common::mass_cell_index(grid_position, 0u, parameters)] = cell;
+}
+
+// Sums each cell of the coarser levels of the mass grid from the cells of the grid within it,
+// rather than from the level below, so that every level is summed by one dispatch
+@compute
+@workgroup_size(64)
+fn sum_coarse_cell_masses(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+  let top_level = Common::mass_grid_top_level(parameters);
+  var mass_cell_index = global_invocation_id.x + Common::mass_grid_offset(1u, parameters);
+  var level = 1u;
+  for (; level <= top_level; level++) {
+    let size = Common::mass_grid_size(level, parameters);
+    let level_cell_count = size.x * size.y * size.z;
+    if (mass_cell_index < Common::mass_grid_offset(level, parameters) + level_cell_count) {
+      break;
+    }
+  }
+  if (level > top_level) {
+    return;
+  }
+
+  let size = Common::mass_grid_size(level, parameters);
+  let index = mass_cell_index - Common::mass_grid_offset(level, parameters);
+  let position = vec3<u32>(index % size.x, index / size.x % size.y, index / (size.x * size.y));
+  let scale = 1u << level;
+  let grid_min = position * scale;
+  let grid_max = min(grid_min + scale, parameters.grid_size);
+  var mass = 0.0;
+  var moment = vec3<f32>(0.0);
+  var grid_position = vec3<u32>();
+  for (grid_position.z = grid_min.z; grid_position.z < grid_max.z; grid_position.z++) {
+    for (grid_position.y = grid_min.y; grid_position.y < grid_max.y; grid_position.y++) {
+      for (grid_position.x = grid_min.x; grid_position.x < grid_max.x; grid_position.x++) {
+        let cell = mass_grid[Common::mass_cell_index(vec3<i32>(grid_position), 0u, parameters)];
+        mass += cell.mass;
+        moment += cell.centre * cell.mass;
+      }
+    }
+  }
+  mass_grid[mass_cell_index] = mass_cell(mass, moment);
+}
+
+fn mass_cell(mass: f32, moment: vec3<f32>) -> Common::MassCell {
+  if (mass <= 0.0) {
+    return Common::MassCell(vec3<f32>(0.0), 0.0);
+  }
+  return Common::MassCell(moment / mass, mass);
+}
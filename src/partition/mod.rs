@@ -1,4 +1,8 @@
+//! Spatial partitioning of the particles, used to accelerate neighbour queries and to approximate
+//! the gravity between distant particles.
 mod bounds;
-pub use bounds::BoundsPartition;
+pub use bounds::{BoundsMode, BoundsPartition};
 mod grid;
 pub use grid::GridPartition;
+mod mass;
+pub use mass::{MassPartition, MASS_FIXED_POINT_SCALE};
@@ -1,2 +1,3 @@
+//! Utilities for timing GPU work with timestamp queries.
 mod profiling;
-pub use profiling::profile;
+pub use profiling::{profile, Timing};
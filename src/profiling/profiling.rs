@@ -1,11 +1,12 @@
-use core::panic;
-
+use crate::error::SolError;
+use crate::wgpu_utilities::{submit, Device};
 use futures::channel::oneshot;
 use wgpu::{
-    BufferDescriptor, BufferUsages, CommandEncoder, CommandEncoderDescriptor, Device,
+    BufferDescriptor, BufferUsages, CommandEncoder, CommandEncoderDescriptor, Features,
     QuerySetDescriptor, QueryType, Queue,
 };
 
+/// Start and end GPU timestamps of a profiled block of work, in nanoseconds.
 pub struct Timing {
     pub start: u64,
     pub end: u64,
@@ -14,60 +15,75 @@ pub struct Timing {
 impl Timing {
     // Duration in ms
     pub fn duration(&self) -> f32 {
-        return (self.end - self.start) as f32 / 1000000.0;
+        (self.end - self.start) as f32 / 1000000.0
     }
 }
 
-pub async fn profile<F>(device: &Device, queue: &Queue, f: F) -> Timing
+/// Records the commands encoded by `f` between two timestamp queries, submits them and waits for
+/// the resulting [`Timing`].
+///
+/// Returns [`SolError::MissingFeatures`] unless the device was created with
+/// `Features::TIMESTAMP_QUERY`.
+pub async fn profile<F>(device: &Device, queue: &Queue, f: F) -> Result<Timing, SolError>
 where
-    F: Fn(&mut CommandEncoder) -> (),
+    F: FnOnce(&mut CommandEncoder),
 {
-    let query_set = device.create_query_set(&QuerySetDescriptor {
-        label: None,
-        ty: QueryType::Timestamp,
-        count: 2,
-    });
+    if !device.features().contains(Features::TIMESTAMP_QUERY) {
+        return Err(SolError::MissingFeatures(Features::TIMESTAMP_QUERY));
+    }
 
-    let query_buffer = device.create_buffer(&BufferDescriptor {
-        label: None,
-        size: 16,
-        usage: BufferUsages::QUERY_RESOLVE
-            | BufferUsages::STORAGE
-            | BufferUsages::COPY_SRC
-            | BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
+    let (query_buffer, staging_buffer, command_buffer) = device.capture_errors(|| {
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: None,
+            ty: QueryType::Timestamp,
+            count: 2,
+        });
 
-    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: 16,
-        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
+        let query_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: 16,
+            usage: BufferUsages::QUERY_RESOLVE
+                | BufferUsages::STORAGE
+                | BufferUsages::COPY_SRC
+                | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 16,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
-    let mut command_encoder =
-        device.create_command_encoder(&CommandEncoderDescriptor { label: None });
-    command_encoder.write_timestamp(&query_set, 0);
-    f(&mut command_encoder);
-    command_encoder.write_timestamp(&query_set, 1);
-    command_encoder.resolve_query_set(&query_set, 0..2, &query_buffer, 0);
-    command_encoder.copy_buffer_to_buffer(&query_buffer, 0, &staging_buffer, 0, 16);
-    queue.submit(Some(command_encoder.finish()));
+        let mut command_encoder =
+            device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        command_encoder.write_timestamp(&query_set, 0);
+        f(&mut command_encoder);
+        command_encoder.write_timestamp(&query_set, 1);
+        command_encoder.resolve_query_set(&query_set, 0..2, &query_buffer, 0);
+        command_encoder.copy_buffer_to_buffer(&query_buffer, 0, &staging_buffer, 0, 16);
+        (query_buffer, staging_buffer, command_encoder.finish())
+    })?;
+    submit(device, queue, command_buffer)?;
 
     let buffer_slice = staging_buffer.slice(..);
     let (sender, receiver) = oneshot::channel();
-    buffer_slice.map_async(wgpu::MapMode::Read, |v| sender.send(v).unwrap());
+    buffer_slice.map_async(wgpu::MapMode::Read, |result| {
+        let _ = sender.send(result);
+    });
     device.poll(wgpu::Maintain::Wait);
-    if let Ok(Ok(())) = receiver.await {
-        let data = buffer_slice.get_mapped_range();
-        let result = bytemuck::cast_slice::<u8, u64>(&data);
-        query_buffer.destroy();
-        staging_buffer.destroy();
-        return Timing {
-            start: result[0],
-            end: result[1],
-        };
-    } else {
-        panic!("Failed to profile");
-    }
+    // The callback is dropped without being called if the device is lost before the buffer is
+    // mapped
+    receiver.await.map_err(|_| SolError::DeviceLost)??;
+    let data = buffer_slice.get_mapped_range();
+    let result = bytemuck::cast_slice::<u8, u64>(&data);
+    let timing = Timing {
+        start: result[0],
+        end: result[1],
+    };
+    drop(data);
+    query_buffer.destroy();
+    staging_buffer.destroy();
+    Ok(timing)
 }
@@ -0,0 +1,6 @@
+//! A CPU implementation of the simulation stages, used to validate the shaders.
+mod reference;
+pub use reference::{
+    approximate_self_gravity, build_grid, build_mass_grid, calculate_bounds, direct_self_gravity,
+    divergence, simulate, update_bounds, Divergence, Grid, Scene, Settings,
+};
@@ -0,0 +1,1324 @@
+use crate::common::{
+    Bounds, Cluster, Container, DistanceConstraint, GridCell, MassCell, Material, Particle,
+    GRID_MARGIN, MAX_MATERIALS, PHASE_FLUID,
+};
+use crate::partition::{BoundsMode, MASS_FIXED_POINT_SCALE};
+use crate::resources::Configuration;
+use crate::sdf::{
+    Collider, EMPTY_DISTANCE, OPERATION_INTERSECTION, OPERATION_SUBTRACTION, SHAPE_BOX,
+    SHAPE_CAPSULE, SHAPE_SPHERE,
+};
+use crate::simulation::{
+    ForceField, GravityModel, NeighbourSearch, Phase, RadialFalloff, RadialGravity, SelfGravity,
+    COLLISION_EPSILON, CONTACT_SLOP, DEFAULT_ITERATIONS, DEFAULT_RELAXATION,
+    DEFAULT_SMOOTHING_LENGTH, DEFAULT_SUBSTEPS, FORCE_FIELD_ATTRACTOR, FORCE_FIELD_VORTEX,
+    GRADIENT_STEP,
+};
+use bytemuck::Zeroable;
+use glam::{IVec3, Mat3, Quat, UVec3, Vec3, Vec4};
+
+/// The bounds that [`crate::BoundsPartition`] calculates from scratch in [`BoundsMode::Dynamic`].
+pub fn calculate_bounds(particles: &[Particle]) -> Bounds {
+    update_bounds(&Bounds::zeroed(), particles, BoundsMode::Dynamic)
+}
+
+/// Mirrors `bounds.wgsl::reset_bounds`, `calculate_bounds` and `update_bounds`, moving `bounds` to
+/// follow `particles` according to `mode`.
+pub fn update_bounds(bounds: &Bounds, particles: &[Particle], mode: BoundsMode) -> Bounds {
+    let (bounds_min, bounds_max) = integer_bounds_min_max(bounds);
+    let (bounds_min, bounds_max) = match mode {
+        BoundsMode::Fixed(bounds) => integer_bounds_min_max(&bounds),
+        BoundsMode::Dynamic => follow_particles(bounds_min, bounds_max, particles, 0, 0),
+        BoundsMode::DynamicWithMargin { margin, hysteresis } => follow_particles(
+            bounds_min,
+            bounds_max,
+            particles,
+            margin as i32,
+            hysteresis as i32,
+        ),
+    };
+    let bounds_max = bounds_max.max(bounds_min + 1);
+    Bounds {
+        min_x: bounds_min.x,
+        min_y: bounds_min.y,
+        min_z: bounds_min.z,
+        max_x: bounds_max.x,
+        max_y: bounds_max.y,
+        max_z: bounds_max.z,
+    }
+}
+
+fn integer_bounds_min_max(bounds: &Bounds) -> (IVec3, IVec3) {
+    (
+        IVec3::new(bounds.min_x, bounds.min_y, bounds.min_z),
+        IVec3::new(bounds.max_x, bounds.max_y, bounds.max_z),
+    )
+}
+
+/// Mirrors the dynamic branch of `bounds.wgsl::update_bounds`.
+fn follow_particles(
+    bounds_min: IVec3,
+    bounds_max: IVec3,
+    particles: &[Particle],
+    margin: i32,
+    hysteresis: i32,
+) -> (IVec3, IVec3) {
+    if !particles.iter().any(Particle::is_alive) {
+        return (bounds_min, bounds_max);
+    }
+    let mut particles_min = IVec3::MAX;
+    let mut particles_max = IVec3::MIN;
+    for particle in particles.iter().filter(|particle| particle.is_alive()) {
+        particles_min = particles_min.min(particle.position.floor().as_ivec3());
+        particles_max = particles_max.max(particle.position.ceil().as_ivec3());
+    }
+    (
+        follow_min(bounds_min, particles_min, margin, hysteresis),
+        -follow_min(-bounds_max, -particles_max, margin, hysteresis),
+    )
+}
+
+/// Mirrors `bounds.wgsl::follow_min`.
+fn follow_min(bounds_min: IVec3, particles_min: IVec3, margin: i32, hysteresis: i32) -> IVec3 {
+    let target_min = particles_min - margin;
+    let crossed = particles_min.cmplt(bounds_min);
+    let left = bounds_min.cmplt(target_min - hysteresis);
+    IVec3::select(crossed | left, target_min, bounds_min)
+}
+
+/// The contents of the grid buffers, as produced by [`build_grid`].
+pub struct Grid {
+    pub cells: Vec<GridCell>,
+    /// `max_particles_per_grid_cell` slots per cell, of which the first `particles_length` are used
+    pub particles: Vec<u32>,
+}
+
+impl Grid {
+    /// The particles binned into the cell at `grid_index`, excluding those that overflowed it.
+    pub fn cell_particles(&self, grid_index: usize, configuration: &Configuration) -> &[u32] {
+        let max_particles_per_grid_cell = configuration.max_particles_per_grid_cell as usize;
+        let length =
+            (self.cells[grid_index].particles_length as usize).min(max_particles_per_grid_cell);
+        let start = grid_index * max_particles_per_grid_cell;
+        &self.particles[start..start + length]
+    }
+}
+
+fn bounds_min_max(bounds: &Bounds) -> (Vec3, Vec3) {
+    let (bounds_min, bounds_max) = integer_bounds_min_max(bounds);
+    (bounds_min.as_vec3(), bounds_max.as_vec3())
+}
+
+/// Mirrors `common.wgsl::world_position_to_grid_position`.
+fn world_position_to_grid_position(position: Vec3, bounds: &Bounds, grid_size: UVec3) -> IVec3 {
+    let (bounds_min, bounds_max) = bounds_min_max(bounds);
+    let grid_position =
+        (position - bounds_min) / (bounds_max - bounds_min) * (grid_size - 1).as_vec3();
+    // WGSL rounds half way cases to even
+    Vec3::new(
+        grid_position.x.round_ties_even(),
+        grid_position.y.round_ties_even(),
+        grid_position.z.round_ties_even(),
+    )
+    .as_ivec3()
+}
+
+/// Mirrors `common.wgsl::world_sphere_to_grid_range`, returning the first and last cells inclusive.
+fn world_sphere_to_grid_range(
+    position: Vec3,
+    radius: f32,
+    bounds: &Bounds,
+    grid_size: UVec3,
+) -> (IVec3, IVec3) {
+    let grid_max = grid_size.as_ivec3() - 1;
+    (
+        world_position_to_grid_position(position - radius, bounds, grid_size)
+            .clamp(IVec3::ZERO, grid_max),
+        world_position_to_grid_position(position + radius, bounds, grid_size)
+            .clamp(IVec3::ZERO, grid_max),
+    )
+}
+
+fn grid_position_to_grid_index(position: IVec3, grid_size: UVec3) -> usize {
+    let grid_size = grid_size.as_ivec3();
+    (position.x + position.y * grid_size.x + position.z * grid_size.x * grid_size.y) as usize
+}
+
+/// Mirrors `grid.wgsl::clear_grid` and `grid.wgsl::build_grid`.
+///
+/// Particles are binned in index order, so when a cell overflows it keeps the particles with the
+/// lowest indices, as the GPU does.
+pub fn build_grid(particles: &[Particle], bounds: &Bounds, configuration: &Configuration) -> Grid {
+    let grid_cell_count = configuration.grid_cell_count() as usize;
+    let max_particles_per_grid_cell = configuration.max_particles_per_grid_cell;
+    let mut grid = Grid {
+        cells: vec![GridCell::zeroed(); grid_cell_count],
+        particles: vec![0; grid_cell_count * max_particles_per_grid_cell as usize],
+    };
+
+    let grid_size = configuration.grid_size;
+    for (particle_index, particle) in particles.iter().enumerate() {
+        if !particle.is_alive() {
+            continue;
+        }
+        let (min_grid_position, max_grid_position) = world_sphere_to_grid_range(
+            particle.position,
+            particle.radius + GRID_MARGIN,
+            bounds,
+            grid_size,
+        );
+        for x in min_grid_position.x..=max_grid_position.x {
+            for y in min_grid_position.y..=max_grid_position.y {
+                for z in min_grid_position.z..=max_grid_position.z {
+                    let grid_index = grid_position_to_grid_index(IVec3::new(x, y, z), grid_size);
+                    let particles_length = grid.cells[grid_index].particles_length;
+                    grid.cells[grid_index].particles_length += 1;
+                    if particles_length < max_particles_per_grid_cell {
+                        grid.particles[grid_index * max_particles_per_grid_cell as usize
+                            + particles_length as usize] = particle_index as u32;
+                    }
+                }
+            }
+        }
+    }
+    grid
+}
+
+/// Mirrors `common.wgsl::world_position_to_clamped_grid_position`.
+fn world_position_to_clamped_grid_position(
+    position: Vec3,
+    bounds: &Bounds,
+    grid_size: UVec3,
+) -> IVec3 {
+    world_position_to_grid_position(position, bounds, grid_size)
+        .clamp(IVec3::ZERO, grid_size.as_ivec3() - 1)
+}
+
+fn mass_cell_index(position: IVec3, level: usize, mass_grid_sizes: &[UVec3]) -> usize {
+    let offset: u32 = mass_grid_sizes[..level]
+        .iter()
+        .map(|size| size.x * size.y * size.z)
+        .sum();
+    offset as usize + grid_position_to_grid_index(position, mass_grid_sizes[level])
+}
+
+/// Mirrors `mass.wgsl::mass_cell`.
+fn mass_cell(mass: f32, moment: Vec3) -> MassCell {
+    if mass <= 0.0 {
+        return MassCell {
+            centre: Vec3::ZERO,
+            mass: 0.0,
+        };
+    }
+    MassCell {
+        centre: moment / mass,
+        mass,
+    }
+}
+
+/// Mirrors `mass.wgsl::cell_width`.
+fn cell_width(bounds: &Bounds, grid_size: UVec3) -> Vec3 {
+    let (bounds_min, bounds_max) = bounds_min_max(bounds);
+    (bounds_max - bounds_min) / (grid_size.max(UVec3::splat(2)) - 1).as_vec3()
+}
+
+/// Mirrors `mass.wgsl::cell_centre`.
+fn cell_centre(grid_position: IVec3, bounds: &Bounds, grid_size: UVec3) -> Vec3 {
+    let (bounds_min, _) = bounds_min_max(bounds);
+    bounds_min + grid_position.as_vec3() * cell_width(bounds, grid_size)
+}
+
+/// Mirrors `mass.wgsl::add_particle_masses`, `mass.wgsl::sum_cell_masses` and
+/// `mass.wgsl::sum_coarse_cell_masses`, summing the live `particles` into the cells their centres
+/// are in.
+pub fn build_mass_grid(
+    particles: &[Particle],
+    bounds: &Bounds,
+    configuration: &Configuration,
+) -> Vec<MassCell> {
+    let grid_size = configuration.grid_size;
+    let mass_grid_sizes = configuration.mass_grid_sizes();
+    let mut mass_grid = vec![MassCell::zeroed(); configuration.mass_cell_count() as usize];
+
+    // The fixed point moment and mass of each cell of the grid
+    let mut cell_mass_sums = vec![(IVec3::ZERO, 0u32); configuration.grid_cell_count() as usize];
+    for particle in particles.iter().filter(|particle| particle.is_alive()) {
+        let grid_position =
+            world_position_to_clamped_grid_position(particle.position, bounds, grid_size);
+        let (moment_sum, mass_sum) =
+            &mut cell_mass_sums[grid_position_to_grid_index(grid_position, grid_size)];
+        let mass = (particle.mass * MASS_FIXED_POINT_SCALE).round_ties_even() as u32;
+        let offset = (particle.position - cell_centre(grid_position, bounds, grid_size))
+            / cell_width(bounds, grid_size);
+        let moment = offset * mass as f32;
+        let moment = IVec3::new(
+            moment.x.round_ties_even() as i32,
+            moment.y.round_ties_even() as i32,
+            moment.z.round_ties_even() as i32,
+        );
+        *moment_sum = IVec3::new(
+            moment_sum.x.wrapping_add(moment.x),
+            moment_sum.y.wrapping_add(moment.y),
+            moment_sum.z.wrapping_add(moment.z),
+        );
+        *mass_sum = mass_sum.wrapping_add(mass);
+    }
+    for z in 0..grid_size.z as i32 {
+        for y in 0..grid_size.y as i32 {
+            for x in 0..grid_size.x as i32 {
+                let grid_position = IVec3::new(x, y, z);
+                let grid_index = grid_position_to_grid_index(grid_position, grid_size);
+                let (moment, mass) = cell_mass_sums[grid_index];
+                if mass > 0 {
+                    mass_grid[grid_index] = MassCell {
+                        centre: cell_centre(grid_position, bounds, grid_size)
+                            + moment.as_vec3() / mass as f32 * cell_width(bounds, grid_size),
+                        mass: mass as f32 / MASS_FIXED_POINT_SCALE,
+                    };
+                }
+            }
+        }
+    }
+
+    for (level, size) in mass_grid_sizes.iter().enumerate().skip(1) {
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let position = UVec3::new(x, y, z);
+                    let grid_min = position << level as u32;
+                    let grid_max = (grid_min + (1 << level)).min(grid_size);
+                    let mut mass = 0.0;
+                    let mut moment = Vec3::ZERO;
+                    for z in grid_min.z..grid_max.z {
+                        for y in grid_min.y..grid_max.y {
+                            for x in grid_min.x..grid_max.x {
+                                let grid_position = UVec3::new(x, y, z).as_ivec3();
+                                let cell = &mass_grid
+                                    [grid_position_to_grid_index(grid_position, grid_size)];
+                                mass += cell.mass;
+                                moment += cell.centre * cell.mass;
+                            }
+                        }
+                    }
+                    mass_grid[mass_cell_index(position.as_ivec3(), level, &mass_grid_sizes)] =
+                        mass_cell(mass, moment);
+                }
+            }
+        }
+    }
+    mass_grid
+}
+
+/// Mirrors `attraction.wgsl::point_mass_pull`.
+fn point_mass_pull(offset: Vec3, mass: f32, softening_length: f32) -> Vec3 {
+    let distance_squared = offset.length_squared() + softening_length * softening_length;
+    offset * mass / (distance_squared * distance_squared.sqrt())
+}
+
+/// Mirrors `attraction.wgsl::self_gravity`, pulling the particle at `particle_index` of `snapshot`
+/// towards the cells of `mass_grid` that are far enough away to take as a whole at each level, and
+/// towards each particle in the cells around its own.
+fn self_gravity(
+    self_gravity: &SelfGravity,
+    particle_index: usize,
+    snapshot: &[Particle],
+    bounds: &Bounds,
+    grid: &Grid,
+    mass_grid: &[MassCell],
+    configuration: &Configuration,
+) -> Vec3 {
+    let particle = &snapshot[particle_index];
+    let grid_size = configuration.grid_size;
+    let grid_position =
+        world_position_to_clamped_grid_position(particle.position, bounds, grid_size);
+    let mass_grid_sizes = configuration.mass_grid_sizes();
+    let top_level = mass_grid_sizes.len() - 1;
+    let mut pull = Vec3::ZERO;
+
+    for (level, size) in mass_grid_sizes.iter().enumerate() {
+        let position = grid_position >> level as i32;
+        let (mut range_min, mut range_max) = (IVec3::ZERO, size.as_ivec3() - 1);
+        if level < top_level {
+            let parent_position = position >> 1;
+            range_min = range_min.max(parent_position * 2 - 2);
+            range_max = range_max.min(parent_position * 2 + 3);
+        }
+        for z in range_min.z..=range_max.z {
+            for y in range_min.y..=range_max.y {
+                for x in range_min.x..=range_max.x {
+                    let cell_position = IVec3::new(x, y, z);
+                    if (cell_position - position).abs().cmple(IVec3::ONE).all() {
+                        continue;
+                    }
+                    let cell = &mass_grid[mass_cell_index(cell_position, level, &mass_grid_sizes)];
+                    if cell.mass > 0.0 {
+                        pull += point_mass_pull(
+                            cell.centre - particle.position,
+                            cell.mass,
+                            self_gravity.softening_length,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    let range_min = (grid_position - 1).max(IVec3::ZERO);
+    let range_max = (grid_position + 1).min(grid_size.as_ivec3() - 1);
+    for z in range_min.z..=range_max.z {
+        for y in range_min.y..=range_max.y {
+            for x in range_min.x..=range_max.x {
+                let cell_position = IVec3::new(x, y, z);
+                let grid_index = grid_position_to_grid_index(cell_position, grid_size);
+                for &neighbour_index in grid.cell_particles(grid_index, configuration) {
+                    let neighbour = &snapshot[neighbour_index as usize];
+                    let neighbour_grid_position = world_position_to_clamped_grid_position(
+                        neighbour.position,
+                        bounds,
+                        grid_size,
+                    );
+                    if neighbour_index as usize != particle_index
+                        && neighbour_grid_position == cell_position
+                    {
+                        pull += point_mass_pull(
+                            neighbour.position - particle.position,
+                            neighbour.mass,
+                            self_gravity.softening_length,
+                        );
+                    }
+                }
+            }
+        }
+    }
+    pull * self_gravity.gravitational_constant
+}
+
+/// The exact softened gravity of every other live particle on the particle at `particle_index`,
+/// which [`build_mass_grid`] and the grid approximate.
+pub fn direct_self_gravity(
+    self_gravity: &SelfGravity,
+    particle_index: usize,
+    particles: &[Particle],
+) -> Vec3 {
+    let particle = &particles[particle_index];
+    let pull: Vec3 = particles
+        .iter()
+        .enumerate()
+        .filter(|(neighbour_index, neighbour)| {
+            *neighbour_index != particle_index && neighbour.is_alive()
+        })
+        .map(|(_, neighbour)| {
+            point_mass_pull(
+                neighbour.position - particle.position,
+                neighbour.mass,
+                self_gravity.softening_length,
+            )
+        })
+        .sum();
+    pull * self_gravity.gravitational_constant
+}
+
+/// The approximate gravity of every other particle on each particle, as [`simulate`] applies it at
+/// the start of a step.
+pub fn approximate_self_gravity(
+    self_gravity_settings: &SelfGravity,
+    particles: &[Particle],
+    bounds: &Bounds,
+    configuration: &Configuration,
+) -> Vec<Vec3> {
+    let grid = build_grid(particles, bounds, configuration);
+    let mass_grid = build_mass_grid(particles, bounds, configuration);
+    (0..particles.len())
+        .map(|particle_index| {
+            self_gravity(
+                self_gravity_settings,
+                particle_index,
+                particles,
+                bounds,
+                &grid,
+                &mass_grid,
+                configuration,
+            )
+        })
+        .collect()
+}
+
+/// Mirrors `simulation.wgsl::solve_contacts_grid` and the grid search of
+/// `simulation.wgsl::sum_fluid`, which searches within `radius`, returning the neighbours in the
+/// order they are visited.
+fn grid_neighbours(
+    position: Vec3,
+    radius: f32,
+    snapshot: &[Particle],
+    bounds: &Bounds,
+    grid: &Grid,
+    configuration: &Configuration,
+) -> Vec<usize> {
+    let grid_size = configuration.grid_size;
+    let (min_grid_position, max_grid_position) =
+        world_sphere_to_grid_range(position, radius, bounds, grid_size);
+    let mut neighbours = Vec::new();
+    for x in min_grid_position.x..=max_grid_position.x {
+        for y in min_grid_position.y..=max_grid_position.y {
+            for z in min_grid_position.z..=max_grid_position.z {
+                let grid_position = IVec3::new(x, y, z);
+                let grid_index = grid_position_to_grid_index(grid_position, grid_size);
+                for &neighbour_index in grid.cell_particles(grid_index, configuration) {
+                    let neighbour = &snapshot[neighbour_index as usize];
+                    let (neighbour_min_grid_position, _) = world_sphere_to_grid_range(
+                        neighbour.position,
+                        neighbour.radius,
+                        bounds,
+                        grid_size,
+                    );
+                    if min_grid_position.max(neighbour_min_grid_position) == grid_position {
+                        neighbours.push(neighbour_index as usize);
+                    }
+                }
+            }
+        }
+    }
+    neighbours
+}
+
+/// Mirrors `simulation.wgsl::read_material` reading the table written by
+/// [`crate::Resources::set_materials`].
+fn read_material(materials: &[Material], material_index: u32) -> Material {
+    materials
+        .get(material_index.min(MAX_MATERIALS - 1) as usize)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Mirrors `simulation.wgsl::is_fluid`.
+fn is_fluid(materials: &[Material], settings: &Settings, particle: &Particle) -> bool {
+    match settings.phase {
+        Phase::PerMaterial => read_material(materials, particle.material).phase == PHASE_FLUID,
+        Phase::Granular => false,
+        Phase::Fluid => true,
+    }
+}
+
+/// Mirrors `sph.wgsl::POLY6_COEFFICIENT`.
+const POLY6_COEFFICIENT: f32 = 1.5666815;
+/// Mirrors `sph.wgsl::SPIKY_COEFFICIENT`.
+const SPIKY_COEFFICIENT: f32 = 14.323945;
+
+/// Mirrors `sph.wgsl::poly6`.
+fn poly6(distance: f32, h: f32) -> f32 {
+    if distance >= h {
+        return 0.0;
+    }
+    let h3 = h * h * h;
+    let x = h * h - distance * distance;
+    POLY6_COEFFICIENT / (h3 * h3 * h3) * x * x * x
+}
+
+/// Mirrors `sph.wgsl::spiky_gradient`.
+fn spiky_gradient(distance: f32, h: f32) -> f32 {
+    if distance >= h {
+        return 0.0;
+    }
+    let h3 = h * h * h;
+    let x = h - distance;
+    SPIKY_COEFFICIENT / (h3 * h3) * x * x
+}
+
+/// Mirrors `sph.wgsl::viscosity_laplacian`.
+fn viscosity_laplacian(distance: f32, h: f32) -> f32 {
+    if distance >= h {
+        return 0.0;
+    }
+    let h3 = h * h * h;
+    SPIKY_COEFFICIENT / (h3 * h3) * (h - distance)
+}
+
+/// Mirrors `simulation.wgsl::FluidParticle`.
+#[derive(Clone, Copy, Default)]
+struct FluidParticle {
+    density: f32,
+    pressure: f32,
+}
+
+/// The state that the fluid computations of a substep read.
+struct FluidContext<'a> {
+    materials: &'a [Material],
+    settings: &'a Settings,
+    snapshot: &'a [Particle],
+    bounds: &'a Bounds,
+    grid: &'a Grid,
+    configuration: &'a Configuration,
+}
+
+impl FluidContext<'_> {
+    /// Mirrors the neighbour search of `simulation.wgsl::sum_fluid`, including non-fluid and dead
+    /// particles, which `simulation.wgsl::add_fluid_neighbour` skips.
+    fn neighbours(&self, particle: &Particle) -> Vec<usize> {
+        match self.settings.neighbour_search {
+            NeighbourSearch::Grid => grid_neighbours(
+                particle.position,
+                self.settings.smoothing_length,
+                self.snapshot,
+                self.bounds,
+                self.grid,
+                self.configuration,
+            ),
+            NeighbourSearch::BruteForce => (0..self.snapshot.len()).collect(),
+        }
+    }
+
+    /// The fluid neighbours of `particle` within the smoothing length, with their offsets from it
+    /// and their distances.
+    fn fluid_neighbours(&self, particle: &Particle) -> Vec<(usize, Vec3, f32)> {
+        self.neighbours(particle)
+            .into_iter()
+            .filter_map(|neighbour_index| {
+                let neighbour = &self.snapshot[neighbour_index];
+                let direction = particle.position - neighbour.position;
+                let distance = direction.length();
+                (distance < self.settings.smoothing_length
+                    && neighbour.is_alive()
+                    && is_fluid(self.materials, self.settings, neighbour))
+                .then_some((neighbour_index, direction, distance))
+            })
+            .collect()
+    }
+
+    /// Mirrors `simulation.wgsl::compute_density`.
+    fn fluid_particle(&self, particle: &Particle) -> FluidParticle {
+        let h = self.settings.smoothing_length;
+        let material = read_material(self.materials, particle.material);
+        let mut density = 0.0;
+        for (neighbour_index, _, distance) in self.fluid_neighbours(particle) {
+            density += self.snapshot[neighbour_index].mass * poly6(distance, h);
+        }
+        let pressure = (material.stiffness * (density - material.density)).max(0.0);
+        FluidParticle { density, pressure }
+    }
+
+    /// Mirrors `simulation.wgsl::sum_fluid` summing `FLUID_ACCELERATION`.
+    fn acceleration(
+        &self,
+        fluid_particles: &[FluidParticle],
+        particle_index: usize,
+        delta_time: f32,
+    ) -> Vec3 {
+        let h = self.settings.smoothing_length;
+        let particle = &self.snapshot[particle_index];
+        let material = read_material(self.materials, particle.material);
+        let fluid_particle = fluid_particles[particle_index];
+        let mut acceleration = Vec3::ZERO;
+        for (neighbour_index, direction, distance) in self.fluid_neighbours(particle) {
+            let neighbour = &self.snapshot[neighbour_index];
+            let fluid_neighbour = fluid_particles[neighbour_index];
+            if distance > COLLISION_EPSILON {
+                let pressure = fluid_particle.pressure
+                    / (fluid_particle.density * fluid_particle.density)
+                    + fluid_neighbour.pressure
+                        / (fluid_neighbour.density * fluid_neighbour.density);
+                acceleration +=
+                    direction / distance * neighbour.mass * pressure * spiky_gradient(distance, h);
+            }
+
+            let velocity = (particle.position - particle.old_position) / delta_time;
+            let neighbour_velocity = (neighbour.position - neighbour.old_position) / delta_time;
+            acceleration += material.viscosity * neighbour.mass * (neighbour_velocity - velocity)
+                / (fluid_neighbour.density * fluid_particle.density)
+                * viscosity_laplacian(distance, h);
+
+            acceleration -= material.surface_tension / particle.mass
+                * neighbour.mass
+                * direction
+                * poly6(distance, h);
+        }
+        acceleration
+    }
+}
+
+/// Mirrors `simulation.wgsl::break_constraints`.
+fn break_constraints(constraints: &mut [DistanceConstraint], particles: &[Particle]) {
+    for constraint in constraints {
+        if constraint.broken != 0 {
+            continue;
+        }
+        let (a, b) = (
+            &particles[constraint.a as usize],
+            &particles[constraint.b as usize],
+        );
+        if !a.is_alive() || !b.is_alive() {
+            constraint.broken = 1;
+            continue;
+        }
+        if constraint.break_strain <= 0.0 {
+            continue;
+        }
+        let stretched_length = a.position.distance(b.position);
+        if stretched_length > constraint.rest_length * (1.0 + constraint.break_strain) {
+            constraint.broken = 1;
+        }
+    }
+}
+
+/// The indices of the constraints of each particle, in the order that
+/// [`crate::Resources::set_constraints`] writes them.
+fn particle_constraints(
+    constraints: &[DistanceConstraint],
+    particle_count: usize,
+) -> Vec<Vec<usize>> {
+    let mut particle_constraints = vec![Vec::new(); particle_count];
+    for (constraint_index, constraint) in constraints.iter().enumerate() {
+        particle_constraints[constraint.a as usize].push(constraint_index);
+        particle_constraints[constraint.b as usize].push(constraint_index);
+    }
+    particle_constraints
+}
+
+/// Mirrors `simulation.wgsl::solve_constraints`.
+fn solve_constraints(
+    contacts: &mut Contacts,
+    constraints: &[DistanceConstraint],
+    particle_constraints: &[usize],
+    particle_index: usize,
+    snapshot: &[Particle],
+) {
+    let particle = &snapshot[particle_index];
+    for &constraint_index in particle_constraints {
+        let constraint = &constraints[constraint_index];
+        if constraint.broken != 0 {
+            continue;
+        }
+        let neighbour_index = if constraint.a as usize == particle_index {
+            constraint.b
+        } else {
+            constraint.a
+        };
+        let neighbour = &snapshot[neighbour_index as usize];
+        let direction = neighbour.position - particle.position;
+        let distance = direction.length();
+        if distance <= COLLISION_EPSILON {
+            continue;
+        }
+        let weight = neighbour.mass / (particle.mass + neighbour.mass);
+        contacts.correction += direction / distance
+            * (distance - constraint.rest_length)
+            * weight
+            * constraint.stiffness;
+        contacts.count += 1;
+    }
+}
+
+/// Mirrors `shape_matching.wgsl::ROTATION_ITERATIONS`.
+const ROTATION_ITERATIONS: u32 = 8;
+
+/// Mirrors `shape_matching.wgsl::match_clusters` and `shape_matching.wgsl::snap_to_clusters`, with
+/// `rest_offsets` laid out as [`crate::Resources::set_clusters`] expects them.
+fn match_clusters(particles: &mut [Particle], clusters: &mut [Cluster], rest_offsets: &[Vec3]) {
+    let mut rest_offsets = rest_offsets.iter();
+    for cluster in clusters {
+        let first_particle = cluster.first_particle as usize;
+        let members =
+            &mut particles[first_particle..first_particle + cluster.particle_count as usize];
+        let rest_offsets: Vec<Vec3> = rest_offsets.by_ref().take(members.len()).copied().collect();
+
+        let alive = |particle: &&Particle| particle.is_alive();
+        let mass: f32 = members
+            .iter()
+            .filter(alive)
+            .map(|particle| particle.mass)
+            .sum();
+        if mass <= 0.0 {
+            continue;
+        }
+        let centre = members
+            .iter()
+            .filter(alive)
+            .map(|particle| particle.position * particle.mass)
+            .sum::<Vec3>()
+            / mass;
+
+        let mut covariance = Mat3::ZERO;
+        for (particle, rest_offset) in members.iter().zip(&rest_offsets) {
+            if !particle.is_alive() {
+                continue;
+            }
+            let offset = (particle.position - centre) * particle.mass;
+            covariance += Mat3::from_cols(
+                offset * rest_offset.x,
+                offset * rest_offset.y,
+                offset * rest_offset.z,
+            );
+        }
+
+        let mut rotation = Quat::from_vec4(cluster.rotation);
+        for _ in 0..ROTATION_ITERATIONS {
+            let axes = Mat3::from_quat(rotation);
+            let torque = axes.x_axis.cross(covariance.x_axis)
+                + axes.y_axis.cross(covariance.y_axis)
+                + axes.z_axis.cross(covariance.z_axis);
+            let alignment = axes.x_axis.dot(covariance.x_axis)
+                + axes.y_axis.dot(covariance.y_axis)
+                + axes.z_axis.dot(covariance.z_axis);
+            let angular_correction = torque / (alignment.abs() + 1e-9);
+            let angle = angular_correction.length();
+            if angle < 1e-9 {
+                break;
+            }
+            rotation =
+                (Quat::from_axis_angle(angular_correction / angle, angle) * rotation).normalize();
+        }
+
+        cluster.centre = centre;
+        cluster.rotation = Vec4::from(rotation);
+        for (particle, rest_offset) in members.iter_mut().zip(&rest_offsets) {
+            if !particle.is_alive() {
+                continue;
+            }
+            let goal = centre + rotation * *rest_offset;
+            particle.position += (goal - particle.position) * cluster.stiffness;
+        }
+    }
+}
+
+/// Mirrors `simulation.wgsl::combine_restitution`.
+fn combine_restitution(a: &Material, b: &Material) -> f32 {
+    a.restitution.max(b.restitution)
+}
+
+/// Mirrors `simulation.wgsl::combine_friction`.
+fn combine_friction(a: f32, b: f32) -> f32 {
+    (a * b).sqrt()
+}
+
+/// Mirrors `simulation.wgsl::coulomb_friction`.
+fn coulomb_friction(
+    displacement: Vec3,
+    normal: Vec3,
+    penetration: f32,
+    static_friction: f32,
+    kinetic_friction: f32,
+) -> Vec3 {
+    let tangential_displacement = displacement - displacement.dot(normal) * normal;
+    let tangential_distance = tangential_displacement.length();
+    if tangential_distance < static_friction * penetration {
+        return tangential_displacement;
+    }
+    if tangential_distance <= 0.0 {
+        return Vec3::ZERO;
+    }
+    tangential_displacement * (kinetic_friction * penetration / tangential_distance).min(1.0)
+}
+
+/// Mirrors `sdf.wgsl::collider_distance`.
+fn collider_distance(collider: &Collider, position: Vec3) -> f32 {
+    let local_position = rotate_inverse(collider.rotation, position - collider.position);
+    match collider.shape {
+        SHAPE_SPHERE => local_position.length() - collider.size.x,
+        SHAPE_BOX => {
+            let q = local_position.abs() - collider.size;
+            q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+        }
+        SHAPE_CAPSULE => {
+            let closest = Vec3::new(
+                0.0,
+                local_position.y.clamp(-collider.size.y, collider.size.y),
+                0.0,
+            );
+            (local_position - closest).length() - collider.size.x
+        }
+        _ => local_position.y,
+    }
+}
+
+/// Mirrors `sdf.wgsl::combine_collider`.
+fn combine_collider(collider: &Collider, scene_distance: f32, distance: f32) -> f32 {
+    let k = collider.smoothing;
+    let (a, b) = (distance, scene_distance);
+    match collider.operation {
+        OPERATION_SUBTRACTION if k > 0.0 => {
+            let h = (0.5 - 0.5 * (b + a) / k).clamp(0.0, 1.0);
+            mix(b, -a, h) + k * h * (1.0 - h)
+        }
+        OPERATION_SUBTRACTION => (-a).max(b),
+        OPERATION_INTERSECTION if k > 0.0 => {
+            let h = (0.5 - 0.5 * (b - a) / k).clamp(0.0, 1.0);
+            mix(b, a, h) + k * h * (1.0 - h)
+        }
+        OPERATION_INTERSECTION => a.max(b),
+        _ if k > 0.0 => {
+            let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+            mix(b, a, h) - k * h * (1.0 - h)
+        }
+        _ => a.min(b),
+    }
+}
+
+/// WGSL's `mix`.
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+/// Mirrors `simulation.wgsl::radial_gravity`.
+fn radial_gravity_acceleration(radial_gravity: &RadialGravity, position: Vec3) -> Vec3 {
+    let offset = radial_gravity.centre - position;
+    let distance = offset.length();
+    if distance <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let radius = radial_gravity.radius;
+    let mut strength = radial_gravity.strength;
+    if distance < radius {
+        strength *= distance / radius;
+    } else if radial_gravity.falloff == RadialFalloff::InverseSquare {
+        strength *= radius * radius / (distance * distance);
+    }
+    offset / distance * strength
+}
+
+/// Mirrors `force_field.wgsl::acceleration`.
+pub fn force_field_acceleration(
+    force_field: &ForceField,
+    position: Vec3,
+    velocity: Vec3,
+    delta_time: f32,
+) -> Vec3 {
+    let offset = position - force_field.position;
+    let fade = |distance: f32| (1.0 - distance / force_field.radius).powf(force_field.falloff);
+    if force_field.kind == FORCE_FIELD_ATTRACTOR {
+        let distance = offset.length();
+        if distance <= 0.0 || distance >= force_field.radius {
+            return Vec3::ZERO;
+        }
+        return -offset / distance * force_field.strength * fade(distance);
+    }
+    if force_field.kind == FORCE_FIELD_VORTEX {
+        let radial = offset - force_field.axis * offset.dot(force_field.axis);
+        let distance = radial.length();
+        if distance <= 0.0 || distance >= force_field.radius {
+            return Vec3::ZERO;
+        }
+        return force_field.axis.cross(radial) / distance * force_field.strength * fade(distance);
+    }
+    let local_position = rotate_inverse(force_field.rotation, offset);
+    if local_position.abs().cmpgt(force_field.size).any() {
+        return Vec3::ZERO;
+    }
+    let rate = (force_field.drag * delta_time).min(1.0) / delta_time;
+    (force_field.axis * force_field.strength - velocity) * rate
+}
+
+/// Mirrors `sdf.wgsl::rotate`.
+fn rotate(quaternion: Vec4, vector: Vec3) -> Vec3 {
+    let axis = quaternion.truncate();
+    let t = 2.0 * axis.cross(vector);
+    vector + quaternion.w * t + axis.cross(t)
+}
+
+/// Mirrors `sdf.wgsl::rotate_inverse`.
+fn rotate_inverse(quaternion: Vec4, vector: Vec3) -> Vec3 {
+    let axis = -quaternion.truncate();
+    let t = 2.0 * axis.cross(vector);
+    vector + quaternion.w * t + axis.cross(t)
+}
+
+/// Mirrors `simulation.wgsl::colliders_distance`.
+fn colliders_distance(colliders: &[Collider], position: Vec3) -> f32 {
+    colliders
+        .iter()
+        .fold(EMPTY_DISTANCE, |scene_distance, collider| {
+            combine_collider(
+                collider,
+                scene_distance,
+                collider_distance(collider, position),
+            )
+        })
+}
+
+/// Mirrors `simulation.wgsl::colliders_gradient`.
+fn colliders_gradient(colliders: &[Collider], position: Vec3) -> Vec3 {
+    let distance = |offset: Vec3| colliders_distance(colliders, position + offset);
+    Vec3::new(
+        distance(Vec3::X * GRADIENT_STEP) - distance(-Vec3::X * GRADIENT_STEP),
+        distance(Vec3::Y * GRADIENT_STEP) - distance(-Vec3::Y * GRADIENT_STEP),
+        distance(Vec3::Z * GRADIENT_STEP) - distance(-Vec3::Z * GRADIENT_STEP),
+    )
+}
+
+/// Mirrors `simulation.wgsl::solve_colliders`.
+fn solve_colliders(
+    colliders: &[Collider],
+    materials: &[Material],
+    position: Vec3,
+    old_position: Vec3,
+    particle: &Particle,
+) -> Vec3 {
+    if colliders.is_empty() {
+        return position;
+    }
+    let penetration = particle.radius - colliders_distance(colliders, position);
+    if penetration <= 0.0 {
+        return position;
+    }
+    let gradient = colliders_gradient(colliders, position);
+    let gradient_length = gradient.length();
+    if gradient_length <= 0.0 {
+        return position;
+    }
+    let normal = gradient / gradient_length;
+    let pushed_position = position + normal * penetration;
+    let material = read_material(materials, particle.material);
+    let friction = coulomb_friction(
+        pushed_position - old_position,
+        normal,
+        penetration,
+        material.static_friction,
+        material.kinetic_friction,
+    );
+    pushed_position - friction
+}
+
+/// Mirrors `simulation.wgsl::solve_container`.
+fn solve_container(
+    container: Option<&Container>,
+    materials: &[Material],
+    position: Vec3,
+    old_position: Vec3,
+    particle: &Particle,
+) -> Vec3 {
+    let Some(container) = container else {
+        return position;
+    };
+    let clamped_position = clamp_to_container(container, position, particle.radius);
+    let push = clamped_position - position;
+    let penetration = push.length();
+    if penetration <= 0.0 {
+        return position;
+    }
+    let material = read_material(materials, particle.material);
+    let friction = coulomb_friction(
+        clamped_position - old_position,
+        push / penetration,
+        penetration,
+        material.static_friction,
+        material.kinetic_friction,
+    );
+    clamp_to_container(container, clamped_position - friction, particle.radius)
+}
+
+/// Mirrors `simulation.wgsl::clamp_to_container`.
+fn clamp_to_container(container: &Container, position: Vec3, radius: f32) -> Vec3 {
+    let half_size = (container.half_size - radius).max(Vec3::ZERO);
+    let local_position = rotate_inverse(container.rotation, position - container.centre);
+    container.centre
+        + rotate(
+            container.rotation,
+            local_position.clamp(-half_size, half_size),
+        )
+}
+
+/// Mirrors `simulation.wgsl::Contacts`.
+#[derive(Default)]
+struct Contacts {
+    correction: Vec3,
+    restitution_correction: Vec3,
+    count: u32,
+    friction_correction: Vec3,
+}
+
+/// Mirrors `simulation.wgsl::solve_contact`.
+fn solve_contact(
+    contacts: &mut Contacts,
+    materials: &[Material],
+    settings: &Settings,
+    particle: &Particle,
+    neighbour: &Particle,
+) {
+    if is_fluid(materials, settings, particle) && is_fluid(materials, settings, neighbour) {
+        return;
+    }
+    let direction = neighbour.position - particle.position;
+    let distance = direction.length();
+    let min_distance = particle.radius + neighbour.radius;
+    if distance >= min_distance || distance <= COLLISION_EPSILON {
+        return;
+    }
+    let normal = direction / distance;
+    let penetration = min_distance - distance;
+    let weight = neighbour.mass / (particle.mass + neighbour.mass);
+    let material = read_material(materials, particle.material);
+    let neighbour_material = read_material(materials, neighbour.material);
+    if penetration > CONTACT_SLOP {
+        let correction = -normal * penetration * weight;
+        contacts.correction += correction;
+        contacts.restitution_correction +=
+            correction * combine_restitution(&material, &neighbour_material);
+        contacts.count += 1;
+    }
+    let relative_displacement =
+        (particle.position - particle.old_position) - (neighbour.position - neighbour.old_position);
+    let friction = coulomb_friction(
+        relative_displacement,
+        normal,
+        penetration,
+        combine_friction(material.static_friction, neighbour_material.static_friction),
+        combine_friction(
+            material.kinetic_friction,
+            neighbour_material.kinetic_friction,
+        ),
+    );
+    contacts.friction_correction -= friction * weight;
+}
+
+/// The settings of a [`crate::Simulation`] that [`simulate`] takes into account.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub neighbour_search: NeighbourSearch,
+    pub substeps: u32,
+    pub iterations: u32,
+    pub relaxation: f32,
+    pub phase: Phase,
+    pub smoothing_length: f32,
+    pub self_gravity: Option<SelfGravity>,
+    pub gravity_model: GravityModel,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            neighbour_search: NeighbourSearch::default(),
+            substeps: DEFAULT_SUBSTEPS,
+            iterations: DEFAULT_ITERATIONS,
+            relaxation: DEFAULT_RELAXATION,
+            phase: Phase::default(),
+            smoothing_length: DEFAULT_SMOOTHING_LENGTH,
+            self_gravity: None,
+            gravity_model: GravityModel::default(),
+        }
+    }
+}
+
+/// Everything that [`simulate`] reads but does not change, as it was uploaded to the
+/// [`crate::Resources`] and the [`crate::Simulation`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Scene<'a> {
+    pub materials: &'a [Material],
+    /// Combined in order, as [`crate::Resources::set_colliders`] uploads them
+    pub colliders: &'a [Collider],
+    pub container: Option<&'a Container>,
+    pub force_fields: &'a [ForceField],
+    /// The rest offsets of the members of the clusters passed to [`simulate`]
+    pub rest_offsets: &'a [Vec3],
+    pub configuration: Configuration,
+    pub settings: Settings,
+}
+
+/// Mirrors `simulation.wgsl::break_constraints`, `simulation.wgsl::compute_density`,
+/// `simulation.wgsl::predict`, `attraction.wgsl::attract`, `simulation.wgsl::solve_contacts` and
+/// `shape_matching.wgsl::match_clusters` for every substep, with the grid built from the particles
+/// at the start of the step. Broken constraints are marked in `constraints`, and where each cluster
+/// was matched to is written to `clusters`. With [`Settings::self_gravity`], the mass grid is
+/// summed from the particles at the start of the step, as [`crate::FramePipeline`] does before each
+/// step. `gravity` is the gravity vector of the step, which [`Settings::gravity_model`] uses as the
+/// simulation does.
+///
+/// Neighbouring positions are read from the state at the start of each iteration, as the shader
+/// reads them from the current particle buffer while writing the next one.
+pub fn simulate(
+    particles: &mut [Particle],
+    constraints: &mut [DistanceConstraint],
+    clusters: &mut [Cluster],
+    scene: &Scene,
+    bounds: &Bounds,
+    delta_time: f32,
+    gravity: Vec3,
+) {
+    let &Scene {
+        materials,
+        colliders,
+        container,
+        force_fields,
+        rest_offsets,
+        ref configuration,
+        ref settings,
+    } = scene;
+    let (gravity, radial_gravity) = match settings.gravity_model {
+        GravityModel::Uniform => (gravity, None),
+        GravityModel::Radial(radial_gravity) => (Vec3::ZERO, Some(radial_gravity)),
+        GravityModel::UniformAndRadial(radial_gravity) => (gravity, Some(radial_gravity)),
+    };
+    let delta_time = delta_time / settings.substeps as f32;
+    let delta_time_squared = delta_time * delta_time;
+
+    let grid = build_grid(particles, bounds, configuration);
+    let mass_grid = settings
+        .self_gravity
+        .map(|_| build_mass_grid(particles, bounds, configuration));
+    let particle_constraints = particle_constraints(constraints, particles.len());
+    for _ in 0..settings.substeps {
+        break_constraints(constraints, particles);
+        let snapshot = particles.to_vec();
+        let fluid = FluidContext {
+            materials,
+            settings,
+            snapshot: &snapshot,
+            bounds,
+            grid: &grid,
+            configuration,
+        };
+        let fluid_particles: Vec<FluidParticle> = snapshot
+            .iter()
+            .map(|particle| {
+                if particle.is_alive() && is_fluid(materials, settings, particle) {
+                    fluid.fluid_particle(particle)
+                } else {
+                    FluidParticle::default()
+                }
+            })
+            .collect();
+
+        for (particle_index, particle) in particles.iter_mut().enumerate() {
+            if !particle.is_alive() {
+                continue;
+            }
+            let material = read_material(materials, particle.material);
+            let velocity = particle.position - particle.old_position;
+            let gravitational_force = gravity * particle.mass;
+            let drag_force = -velocity / delta_time * material.drag;
+            let mut acceleration = (gravitational_force + drag_force) / particle.mass;
+            if let Some(radial_gravity) = radial_gravity.filter(|radial| radial.strength != 0.0) {
+                acceleration += radial_gravity_acceleration(&radial_gravity, particle.position);
+            }
+            for force_field in force_fields {
+                acceleration += force_field_acceleration(
+                    force_field,
+                    particle.position,
+                    velocity / delta_time,
+                    delta_time,
+                );
+            }
+            if is_fluid(materials, settings, particle) {
+                acceleration += fluid.acceleration(&fluid_particles, particle_index, delta_time);
+            }
+
+            particle.old_position = particle.position;
+            particle.position = particle.position + velocity + acceleration * delta_time_squared;
+        }
+        if let (Some(self_gravity_settings), Some(mass_grid)) =
+            (settings.self_gravity.as_ref(), mass_grid.as_ref())
+        {
+            for (particle_index, particle) in particles.iter_mut().enumerate() {
+                if particle.is_alive() {
+                    particle.position += self_gravity(
+                        self_gravity_settings,
+                        particle_index,
+                        &snapshot,
+                        bounds,
+                        &grid,
+                        mass_grid,
+                        configuration,
+                    ) * delta_time_squared;
+                }
+            }
+        }
+
+        for _ in 0..settings.iterations {
+            let snapshot = particles.to_vec();
+            for (particle_index, particle) in particles.iter_mut().enumerate() {
+                if !particle.is_alive() {
+                    continue;
+                }
+                let neighbours = match settings.neighbour_search {
+                    NeighbourSearch::Grid => grid_neighbours(
+                        particle.position,
+                        particle.radius,
+                        &snapshot,
+                        bounds,
+                        &grid,
+                        configuration,
+                    ),
+                    NeighbourSearch::BruteForce => (0..snapshot.len()).collect(),
+                };
+                let mut contacts = Contacts::default();
+                for neighbour_index in neighbours {
+                    if neighbour_index == particle_index || !snapshot[neighbour_index].is_alive() {
+                        continue;
+                    }
+                    solve_contact(
+                        &mut contacts,
+                        materials,
+                        settings,
+                        &snapshot[particle_index],
+                        &snapshot[neighbour_index],
+                    );
+                }
+                solve_constraints(
+                    &mut contacts,
+                    constraints,
+                    &particle_constraints[particle_index],
+                    particle_index,
+                    &snapshot,
+                );
+
+                let scale = settings.relaxation / contacts.count.max(1) as f32;
+                particle.position += (contacts.correction + contacts.friction_correction) * scale;
+                particle.old_position -= contacts.restitution_correction * scale;
+                particle.position = solve_colliders(
+                    colliders,
+                    materials,
+                    particle.position,
+                    particle.old_position,
+                    &snapshot[particle_index],
+                );
+                particle.position = solve_container(
+                    container,
+                    materials,
+                    particle.position,
+                    particle.old_position,
+                    &snapshot[particle_index],
+                );
+            }
+        }
+        match_clusters(particles, clusters, rest_offsets);
+    }
+}
+
+/// Per-particle distance between two particle states.
+pub struct Divergence {
+    /// The larger of the distances between the current and previous positions of each particle
+    pub distances: Vec<f32>,
+}
+
+impl Divergence {
+    pub fn max(&self) -> f32 {
+        self.distances.iter().copied().fold(0.0, f32::max)
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.distances.iter().sum::<f32>() / self.distances.len().max(1) as f32
+    }
+
+    /// Indices of the particles that diverge by more than `tolerance`.
+    pub fn exceeding(&self, tolerance: f32) -> Vec<usize> {
+        self.distances
+            .iter()
+            .enumerate()
+            .filter(|(_, distance)| **distance > tolerance)
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Compares two particle states of the same length, e.g. the GPU and CPU results of the same step.
+pub fn divergence(a: &[Particle], b: &[Particle]) -> Divergence {
+    assert_eq!(a.len(), b.len());
+    Divergence {
+        distances: a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| {
+                a.position
+                    .distance(b.position)
+                    .max(a.old_position.distance(b.old_position))
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,3 @@
+//! Buffers shared between the simulation stages.
+mod resources;
+pub use resources::{Configuration, Resources};
@@ -0,0 +1,849 @@
+use crate::common::{
+    Bounds, Cluster, ClusterParticle, Container, DistanceConstraint, GridCell, MassCell, Material,
+    Parameters, Particle, ParticleConstraints, ParticleCounters, MASS_GRID_TOP_SIZE,
+    MAX_CONSTRAINTS_PER_PARTICLE, MAX_MATERIALS, NO_CLUSTER,
+};
+use crate::error::SolError;
+use crate::sdf::{Collider, MAX_COLLIDERS};
+use crate::wgpu_utilities::{Device, QueueUtilities};
+use bytemuck::Zeroable;
+use encase::{ShaderSize, StorageBuffer};
+use glam::{UVec3, Vec3};
+use wgpu::{Buffer, BufferDescriptor, BufferUsages, Queue};
+
+/// Sizes of the buffers owned by [`Resources`] and the extent of the work dispatched over them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Configuration {
+    /// Number of particles the particle buffer can hold
+    pub particle_capacity: u32,
+    /// Number of particle slots that are simulated and rendered, at most `particle_capacity`
+    ///
+    /// This is the count the particles were last populated with. Emitters and sinks change the
+    /// number of slots in use on the GPU, see [`Resources::particle_counters_buffer`].
+    pub particle_count: u32,
+    /// Number of grid cells along each axis
+    ///
+    /// The grid spans the bounds. Particles are binned into every cell they overlap, so cells that
+    /// are much narrower than a particle fill up quickly.
+    pub grid_size: UVec3,
+    /// Particles binned into a cell beyond this count are dropped from the grid
+    pub max_particles_per_grid_cell: u32,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            particle_capacity: 512,
+            particle_count: 512,
+            grid_size: UVec3::splat(16),
+            max_particles_per_grid_cell: 32,
+        }
+    }
+}
+
+impl Configuration {
+    /// A configuration that holds exactly `particle_count` particles, with the default grid.
+    pub fn with_particles(particle_count: u32) -> Self {
+        Configuration {
+            particle_capacity: particle_count,
+            particle_count,
+            ..Default::default()
+        }
+    }
+
+    pub fn grid_cell_count(&self) -> u32 {
+        self.grid_size.x * self.grid_size.y * self.grid_size.z
+    }
+
+    /// Number of cells along each axis of each level of the mass grid, from the grid itself up to
+    /// the first level with at most [`MASS_GRID_TOP_SIZE`] cells along every axis.
+    pub fn mass_grid_sizes(&self) -> Vec<UVec3> {
+        let mut sizes = vec![self.grid_size];
+        while sizes.last().unwrap().max_element() > MASS_GRID_TOP_SIZE {
+            let scale = 1 << sizes.len();
+            sizes.push((self.grid_size + scale - 1) / scale);
+        }
+        sizes
+    }
+
+    pub fn mass_cell_count(&self) -> u32 {
+        self.mass_grid_sizes()
+            .iter()
+            .map(|size| size.x * size.y * size.z)
+            .sum()
+    }
+
+    fn parameters(&self) -> Parameters {
+        Parameters {
+            particle_count: self.particle_count,
+            max_particles_per_grid_cell: self.max_particles_per_grid_cell,
+            grid_size: self.grid_size,
+        }
+    }
+}
+
+/// Owns the buffers that are read and written by more than one stage.
+///
+/// Stages bind these buffers once when they are created and only rebuild their bind groups when
+/// [`Resources::generation`] changes, which happens whenever a buffer is reallocated.
+///
+/// The particles are double buffered: [`Simulation`](crate::simulation::Simulation) reads the
+/// current particle buffer and writes the next one, then swaps them, so that no invocation reads a
+/// particle that another invocation of the same dispatch is writing. Stages keep a bind group for
+/// each particle buffer and use the one for [`Resources::particle_buffer_index`].
+///
+/// Stages dispatch their per-particle work indirectly, over the slots that the [`ParticleCounters`]
+/// say are in use, so that particles can be emitted and deleted without the CPU knowing how many
+/// there are.
+pub struct Resources {
+    configuration: Configuration,
+    parameters_buffer: Buffer,
+    materials: Vec<Material>,
+    material_buffer: Buffer,
+    colliders: Vec<Collider>,
+    collider_buffer: Buffer,
+    container: Option<Container>,
+    container_buffer: Buffer,
+    constraints: Vec<DistanceConstraint>,
+    constraint_buffer: Buffer,
+    clusters: Vec<Cluster>,
+    rest_offsets: Vec<Vec3>,
+    cluster_buffer: Buffer,
+    particle_buffers: [Buffer; 2],
+    particle_buffer_index: usize,
+    particle_counters_buffer: Buffer,
+    free_slots_buffer: Buffer,
+    bounds_buffer: Buffer,
+    grid_buffer: Buffer,
+    grid_particles_buffer: Buffer,
+    mass_grid_buffer: Buffer,
+    particle_constraints_buffer: Buffer,
+    cluster_particles_buffer: Buffer,
+    generation: u64,
+}
+
+impl Drop for Resources {
+    fn drop(&mut self) {
+        self.parameters_buffer.destroy();
+        self.material_buffer.destroy();
+        self.collider_buffer.destroy();
+        self.container_buffer.destroy();
+        self.constraint_buffer.destroy();
+        self.cluster_buffer.destroy();
+        for particle_buffer in &self.particle_buffers {
+            particle_buffer.destroy();
+        }
+        self.particle_counters_buffer.destroy();
+        self.free_slots_buffer.destroy();
+        self.bounds_buffer.destroy();
+        self.grid_buffer.destroy();
+        self.grid_particles_buffer.destroy();
+        self.mass_grid_buffer.destroy();
+        self.particle_constraints_buffer.destroy();
+        self.cluster_particles_buffer.destroy();
+    }
+}
+
+impl Resources {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        configuration: Configuration,
+    ) -> Result<Self, SolError> {
+        check_particle_count(
+            configuration.particle_count,
+            configuration.particle_capacity,
+        )?;
+
+        let materials = vec![Material::default()];
+        let (
+            parameters_buffer,
+            material_buffer,
+            collider_buffer,
+            container_buffer,
+            constraint_buffer,
+            cluster_buffer,
+            particle_counters_buffer,
+            (
+                particle_buffers,
+                free_slots_buffer,
+                bounds_buffer,
+                grid_buffer,
+                grid_particles_buffer,
+                mass_grid_buffer,
+                particle_constraints_buffer,
+                cluster_particles_buffer,
+            ),
+        ) = device.capture_errors(|| {
+            let parameters_buffer = device.create_buffer(&BufferDescriptor {
+                size: Parameters::SHADER_SIZE.get(),
+                label: Some("Resources::parameters_buffer"),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            queue.write_encased_uniform_buffer(&parameters_buffer, configuration.parameters());
+            let material_buffer = device.create_buffer(&BufferDescriptor {
+                size: Material::SHADER_SIZE.get() * MAX_MATERIALS as u64,
+                label: Some("Resources::material_buffer"),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            queue.write_encased_uniform_buffer(&material_buffer, material_table(&materials));
+            let collider_buffer = device.create_buffer(&BufferDescriptor {
+                size: Collider::SHADER_SIZE.get() * MAX_COLLIDERS as u64,
+                label: Some("Resources::collider_buffer"),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let container_buffer = device.create_buffer(&BufferDescriptor {
+                size: Container::SHADER_SIZE.get(),
+                label: Some("Resources::container_buffer"),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let particle_counters_buffer = device.create_buffer(&BufferDescriptor {
+                size: ParticleCounters::SHADER_SIZE.get(),
+                label: Some("Resources::particle_counters_buffer"),
+                usage: BufferUsages::STORAGE
+                    | BufferUsages::INDIRECT
+                    | BufferUsages::COPY_DST
+                    | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            queue.write_encased_uniform_buffer(
+                &particle_counters_buffer,
+                ParticleCounters::new(configuration.particle_count, 0),
+            );
+            (
+                parameters_buffer,
+                material_buffer,
+                collider_buffer,
+                container_buffer,
+                Self::create_constraint_buffer(device, 0),
+                Self::create_cluster_buffer(device, 0),
+                particle_counters_buffer,
+                Self::create_buffers(device, &configuration),
+            )
+        })?;
+
+        Ok(Resources {
+            configuration,
+            parameters_buffer,
+            materials,
+            material_buffer,
+            colliders: Vec::new(),
+            collider_buffer,
+            container: None,
+            container_buffer,
+            constraints: Vec::new(),
+            constraint_buffer,
+            clusters: Vec::new(),
+            rest_offsets: Vec::new(),
+            cluster_buffer,
+            particle_buffers,
+            particle_buffer_index: 0,
+            particle_counters_buffer,
+            free_slots_buffer,
+            bounds_buffer,
+            grid_buffer,
+            grid_particles_buffer,
+            mass_grid_buffer,
+            particle_constraints_buffer,
+            cluster_particles_buffer,
+            generation: 0,
+        })
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn create_buffers(
+        device: &Device,
+        configuration: &Configuration,
+    ) -> (
+        [Buffer; 2],
+        Buffer,
+        Buffer,
+        Buffer,
+        Buffer,
+        Buffer,
+        Buffer,
+        Buffer,
+    ) {
+        let particle_buffers = [
+            "Resources::particle_buffers[0]",
+            "Resources::particle_buffers[1]",
+        ]
+        .map(|label| {
+            device.create_buffer(&BufferDescriptor {
+                size: Particle::SHADER_SIZE.get() * configuration.particle_capacity.max(1) as u64,
+                label: Some(label),
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        });
+
+        let free_slots_buffer = device.create_buffer(&BufferDescriptor {
+            size: std::mem::size_of::<u32>() as u64 * configuration.particle_capacity.max(1) as u64,
+            label: Some("Resources::free_slots_buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let bounds_buffer = device.create_buffer(&BufferDescriptor {
+            size: Bounds::SHADER_SIZE.get(),
+            label: Some("Resources::bounds_buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let grid_cell_count = configuration.grid_cell_count() as u64;
+        let grid_buffer = device.create_buffer(&BufferDescriptor {
+            size: GridCell::SHADER_SIZE.get() * grid_cell_count,
+            label: Some("Resources::grid_buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let grid_particles_buffer = device.create_buffer(&BufferDescriptor {
+            size: std::mem::size_of::<u32>() as u64
+                * grid_cell_count
+                * configuration.max_particles_per_grid_cell as u64,
+            label: Some("Resources::grid_particles_buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let mass_grid_buffer = device.create_buffer(&BufferDescriptor {
+            size: MassCell::SHADER_SIZE.get() * configuration.mass_cell_count() as u64,
+            label: Some("Resources::mass_grid_buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        // Zeroed, so that particles start out without constraints
+        let particle_constraints_buffer = device.create_buffer(&BufferDescriptor {
+            size: ParticleConstraints::SHADER_SIZE.get()
+                * configuration.particle_capacity.max(1) as u64,
+            label: Some("Resources::particle_constraints_buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        // Only read once clusters have been set, which writes every particle
+        let cluster_particles_buffer = device.create_buffer(&BufferDescriptor {
+            size: ClusterParticle::SHADER_SIZE.get()
+                * configuration.particle_capacity.max(1) as u64,
+            label: Some("Resources::cluster_particles_buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        (
+            particle_buffers,
+            free_slots_buffer,
+            bounds_buffer,
+            grid_buffer,
+            grid_particles_buffer,
+            mass_grid_buffer,
+            particle_constraints_buffer,
+            cluster_particles_buffer,
+        )
+    }
+
+    fn create_constraint_buffer(device: &Device, constraint_capacity: usize) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            size: DistanceConstraint::SHADER_SIZE.get() * constraint_capacity.max(1) as u64,
+            label: Some("Resources::constraint_buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_cluster_buffer(device: &Device, cluster_capacity: usize) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            size: Cluster::SHADER_SIZE.get() * cluster_capacity.max(1) as u64,
+            label: Some("Resources::cluster_buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Applies `configuration`, reallocating the buffers if their sizes have changed.
+    ///
+    /// Reallocation discards the contents of every buffer, along with the constraints and clusters.
+    /// Stages notice the new [`Resources::generation`] and rebind the buffers on their next use.
+    ///
+    /// If the new buffers cannot be allocated, the previous buffers and configuration are kept.
+    pub fn configure(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        configuration: Configuration,
+    ) -> Result<(), SolError> {
+        check_particle_count(
+            configuration.particle_count,
+            configuration.particle_capacity,
+        )?;
+
+        if self.configuration.particle_capacity == configuration.particle_capacity
+            && self.configuration.grid_size == configuration.grid_size
+            && self.configuration.max_particles_per_grid_cell
+                == configuration.max_particles_per_grid_cell
+        {
+            self.set_particle_count(queue, configuration.particle_count)?;
+            self.configuration = configuration;
+            return Ok(());
+        }
+
+        let (
+            particle_buffers,
+            free_slots_buffer,
+            bounds_buffer,
+            grid_buffer,
+            grid_particles_buffer,
+            mass_grid_buffer,
+            particle_constraints_buffer,
+            cluster_particles_buffer,
+        ) = device.capture_errors(|| Self::create_buffers(device, &configuration))?;
+        self.configuration = configuration;
+        self.set_particle_count(queue, configuration.particle_count)?;
+        for particle_buffer in std::mem::replace(&mut self.particle_buffers, particle_buffers) {
+            particle_buffer.destroy();
+        }
+        self.particle_buffer_index = 0;
+        std::mem::replace(&mut self.free_slots_buffer, free_slots_buffer).destroy();
+        std::mem::replace(&mut self.bounds_buffer, bounds_buffer).destroy();
+        std::mem::replace(&mut self.grid_buffer, grid_buffer).destroy();
+        std::mem::replace(&mut self.grid_particles_buffer, grid_particles_buffer).destroy();
+        std::mem::replace(&mut self.mass_grid_buffer, mass_grid_buffer).destroy();
+        std::mem::replace(
+            &mut self.particle_constraints_buffer,
+            particle_constraints_buffer,
+        )
+        .destroy();
+        std::mem::replace(&mut self.cluster_particles_buffer, cluster_particles_buffer).destroy();
+        self.constraints.clear();
+        self.clusters.clear();
+        self.rest_offsets.clear();
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Changes the number of particle slots in use without reallocating anything, treating every
+    /// one of them as live.
+    pub fn set_particle_count(
+        &mut self,
+        queue: &Queue,
+        particle_count: u32,
+    ) -> Result<(), SolError> {
+        self.set_particle_slots(queue, particle_count, &[])
+    }
+
+    /// Uses the first `particle_count` slots, of which the ascending `free_slots` are dead.
+    fn set_particle_slots(
+        &mut self,
+        queue: &Queue,
+        particle_count: u32,
+        free_slots: &[u32],
+    ) -> Result<(), SolError> {
+        check_particle_count(particle_count, self.configuration.particle_capacity)?;
+        self.configuration.particle_count = particle_count;
+        queue
+            .write_encased_uniform_buffer(&self.parameters_buffer, self.configuration.parameters());
+        queue.write_encased_uniform_buffer(
+            &self.particle_counters_buffer,
+            ParticleCounters::new(particle_count, free_slots.len()),
+        );
+        if !free_slots.is_empty() {
+            queue.write_buffer(&self.free_slots_buffer, 0, bytemuck::cast_slice(free_slots));
+        }
+        Ok(())
+    }
+
+    /// Replaces the material table, which particles index with [`Particle::material`].
+    ///
+    /// There can be at most [`MAX_MATERIALS`] materials, otherwise
+    /// [`SolError::InvalidConfiguration`] is returned. The rest of the table is filled with the
+    /// default [`Material`]. Indices beyond the table use its last entry.
+    pub fn set_materials(&mut self, queue: &Queue, materials: &[Material]) -> Result<(), SolError> {
+        if materials.len() > MAX_MATERIALS as usize {
+            return Err(SolError::InvalidConfiguration(format!(
+                "{} materials exceed the maximum of {MAX_MATERIALS}",
+                materials.len()
+            )));
+        }
+        self.materials = materials.to_vec();
+        queue.write_encased_uniform_buffer(&self.material_buffer, material_table(materials));
+        Ok(())
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    /// Replaces the colliders that particles are pushed out of, which are combined in order.
+    ///
+    /// There can be at most [`MAX_COLLIDERS`] colliders, otherwise
+    /// [`SolError::InvalidConfiguration`] is returned.
+    pub fn set_colliders(&mut self, queue: &Queue, colliders: &[Collider]) -> Result<(), SolError> {
+        if colliders.len() > MAX_COLLIDERS as usize {
+            return Err(SolError::InvalidConfiguration(format!(
+                "{} colliders exceed the maximum of {MAX_COLLIDERS}",
+                colliders.len()
+            )));
+        }
+        self.colliders = colliders.to_vec();
+        let mut table = [Collider::zeroed(); MAX_COLLIDERS as usize];
+        table[..colliders.len()].copy_from_slice(colliders);
+        queue.write_encased_uniform_buffer(&self.collider_buffer, table);
+        Ok(())
+    }
+
+    pub fn colliders(&self) -> &[Collider] {
+        &self.colliders
+    }
+
+    /// Keeps every particle inside `container`, or lets them go anywhere if it is `None`, which is
+    /// the default.
+    ///
+    /// The container is independent of the [`Bounds`], which only decide how the particles are
+    /// partitioned.
+    pub fn set_container(&mut self, queue: &Queue, container: Option<Container>) {
+        self.container = container;
+        queue.write_encased_uniform_buffer(
+            &self.container_buffer,
+            container.unwrap_or_else(Container::zeroed),
+        );
+    }
+
+    pub fn container(&self) -> Option<Container> {
+        self.container
+    }
+
+    /// Replaces the distance constraints between particles, which start out unbroken.
+    ///
+    /// Every constrained particle must be within the particle capacity, and can be part of at most
+    /// [`MAX_CONSTRAINTS_PER_PARTICLE`] constraints, otherwise [`SolError::InvalidConfiguration`]
+    /// is returned. The constraint buffer is reallocated when it is too small to hold
+    /// `constraints`, which changes [`Self::generation`].
+    pub fn set_constraints(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        constraints: &[DistanceConstraint],
+    ) -> Result<(), SolError> {
+        let particle_capacity = self.configuration.particle_capacity as usize;
+        let mut particle_constraints = vec![ParticleConstraints::zeroed(); particle_capacity];
+        for (constraint_index, constraint) in constraints.iter().enumerate() {
+            if constraint.a == constraint.b {
+                return Err(SolError::InvalidConfiguration(format!(
+                    "constraint {constraint_index} constrains particle {} to itself",
+                    constraint.a
+                )));
+            }
+            for particle_index in [constraint.a, constraint.b] {
+                let particle_constraints = particle_constraints
+                    .get_mut(particle_index as usize)
+                    .ok_or_else(|| {
+                        SolError::InvalidConfiguration(format!(
+                            "constrained particle {particle_index} is beyond the particle capacity \
+                             of {particle_capacity}"
+                        ))
+                    })?;
+                if particle_constraints.count >= MAX_CONSTRAINTS_PER_PARTICLE {
+                    return Err(SolError::InvalidConfiguration(format!(
+                        "particle {particle_index} is part of more than \
+                         {MAX_CONSTRAINTS_PER_PARTICLE} constraints"
+                    )));
+                }
+                particle_constraints.constraints[particle_constraints.count as usize] =
+                    constraint_index as u32;
+                particle_constraints.count += 1;
+            }
+        }
+
+        let constraints_size = DistanceConstraint::SHADER_SIZE.get() * constraints.len() as u64;
+        if constraints_size > self.constraint_buffer.size() {
+            let constraint_buffer = device
+                .capture_errors(|| Self::create_constraint_buffer(device, constraints.len()))?;
+            std::mem::replace(&mut self.constraint_buffer, constraint_buffer).destroy();
+            self.generation += 1;
+        }
+
+        self.constraints = constraints
+            .iter()
+            .map(|&constraint| DistanceConstraint {
+                broken: 0,
+                ..constraint
+            })
+            .collect();
+        if !self.constraints.is_empty() {
+            let mut encased_constraint_buffer = StorageBuffer::new(Vec::<u8>::new());
+            encased_constraint_buffer.write(&self.constraints).unwrap();
+            queue.write_buffer(
+                &self.constraint_buffer,
+                0,
+                &encased_constraint_buffer.into_inner(),
+            );
+        }
+        let mut encased_particle_constraints_buffer = StorageBuffer::new(Vec::<u8>::new());
+        encased_particle_constraints_buffer
+            .write(&particle_constraints)
+            .unwrap();
+        queue.write_buffer(
+            &self.particle_constraints_buffer,
+            0,
+            &encased_particle_constraints_buffer.into_inner(),
+        );
+        Ok(())
+    }
+
+    /// The constraints last passed to [`Self::set_constraints`], as they were before any of them
+    /// broke.
+    pub fn constraints(&self) -> &[DistanceConstraint] {
+        &self.constraints
+    }
+
+    /// Replaces the rigid clusters, each of which holds its members to their rest offsets from its
+    /// centre.
+    ///
+    /// `rest_offsets` holds the offset of every member of every cluster, in the order of `clusters`
+    /// and then of their members. Clusters must not overlap and their members must be within the
+    /// particle capacity, otherwise [`SolError::InvalidConfiguration`] is returned. The cluster
+    /// buffer is reallocated when it is too small to hold `clusters`, which changes
+    /// [`Self::generation`].
+    pub fn set_clusters(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        clusters: &[Cluster],
+        rest_offsets: &[Vec3],
+    ) -> Result<(), SolError> {
+        let member_count = clusters
+            .iter()
+            .map(|cluster| cluster.particle_count as usize)
+            .sum::<usize>();
+        if member_count != rest_offsets.len() {
+            return Err(SolError::InvalidConfiguration(format!(
+                "{member_count} cluster members have {} rest offsets",
+                rest_offsets.len()
+            )));
+        }
+        let particle_capacity = self.configuration.particle_capacity as usize;
+        let mut cluster_particles = vec![
+            ClusterParticle {
+                rest_offset: Vec3::ZERO,
+                cluster: NO_CLUSTER,
+            };
+            particle_capacity
+        ];
+        let mut rest_offsets_iter = rest_offsets.iter();
+        for (cluster_index, cluster) in clusters.iter().enumerate() {
+            let first_particle = cluster.first_particle as usize;
+            let members = cluster_particles
+                .get_mut(first_particle..first_particle + cluster.particle_count as usize)
+                .ok_or_else(|| {
+                    SolError::InvalidConfiguration(format!(
+                        "the members of cluster {cluster_index} are beyond the particle capacity \
+                         of {particle_capacity}"
+                    ))
+                })?;
+            for (cluster_particle, &rest_offset) in members.iter_mut().zip(&mut rest_offsets_iter) {
+                if cluster_particle.cluster != NO_CLUSTER {
+                    return Err(SolError::InvalidConfiguration(format!(
+                        "cluster {cluster_index} overlaps cluster {}",
+                        cluster_particle.cluster
+                    )));
+                }
+                *cluster_particle = ClusterParticle {
+                    rest_offset,
+                    cluster: cluster_index as u32,
+                };
+            }
+        }
+
+        let clusters_size = Cluster::SHADER_SIZE.get() * clusters.len() as u64;
+        if clusters_size > self.cluster_buffer.size() {
+            let cluster_buffer =
+                device.capture_errors(|| Self::create_cluster_buffer(device, clusters.len()))?;
+            std::mem::replace(&mut self.cluster_buffer, cluster_buffer).destroy();
+            self.generation += 1;
+        }
+
+        self.clusters = clusters.to_vec();
+        self.rest_offsets = rest_offsets.to_vec();
+        if !self.clusters.is_empty() {
+            let mut encased_cluster_buffer = StorageBuffer::new(Vec::<u8>::new());
+            encased_cluster_buffer.write(&self.clusters).unwrap();
+            queue.write_buffer(
+                &self.cluster_buffer,
+                0,
+                &encased_cluster_buffer.into_inner(),
+            );
+        }
+        let mut encased_cluster_particles_buffer = StorageBuffer::new(Vec::<u8>::new());
+        encased_cluster_particles_buffer
+            .write(&cluster_particles)
+            .unwrap();
+        queue.write_buffer(
+            &self.cluster_particles_buffer,
+            0,
+            &encased_cluster_particles_buffer.into_inner(),
+        );
+        Ok(())
+    }
+
+    /// The clusters last passed to [`Self::set_clusters`], as they were before they moved.
+    pub fn clusters(&self) -> &[Cluster] {
+        &self.clusters
+    }
+
+    /// The rest offsets last passed to [`Self::set_clusters`].
+    pub fn rest_offsets(&self) -> &[Vec3] {
+        &self.rest_offsets
+    }
+
+    pub fn configuration(&self) -> &Configuration {
+        &self.configuration
+    }
+
+    /// Incremented every time the buffers are reallocated.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The [`Parameters`] uniform derived from the current [`Configuration`].
+    pub fn parameters_buffer(&self) -> &Buffer {
+        &self.parameters_buffer
+    }
+
+    /// The material table, as an `array<Material, MAX_MATERIALS>`.
+    pub fn material_buffer(&self) -> &Buffer {
+        &self.material_buffer
+    }
+
+    /// The collider table, as an `array<Collider, MAX_COLLIDERS>` of which the first
+    /// [`Self::colliders`] are used.
+    pub fn collider_buffer(&self) -> &Buffer {
+        &self.collider_buffer
+    }
+
+    /// The [`Container`] uniform, which is disabled when there is no container.
+    pub fn container_buffer(&self) -> &Buffer {
+        &self.container_buffer
+    }
+
+    /// The current state of every particle, as an `array<Particle>`.
+    pub fn particle_buffer(&self) -> &Buffer {
+        &self.particle_buffers[self.particle_buffer_index]
+    }
+
+    /// Both particle buffers, indexed by [`Self::particle_buffer_index`].
+    pub fn particle_buffers(&self) -> &[Buffer; 2] {
+        &self.particle_buffers
+    }
+
+    /// Which of [`Self::particle_buffers`] holds the current state of the particles.
+    pub fn particle_buffer_index(&self) -> usize {
+        self.particle_buffer_index
+    }
+
+    /// The [`ParticleCounters`], which hold the arguments of the indirect dispatches over the
+    /// particle slots in use.
+    pub fn particle_counters_buffer(&self) -> &Buffer {
+        &self.particle_counters_buffer
+    }
+
+    /// The free particle slots, as an `array<u32>` of which the first
+    /// [`ParticleCounters::free_count`] are used.
+    pub fn free_slots_buffer(&self) -> &Buffer {
+        &self.free_slots_buffer
+    }
+
+    /// Makes the other particle buffer current, after work that reads the current buffer and writes
+    /// the other one has been recorded.
+    pub fn swap_particle_buffers(&mut self) {
+        self.particle_buffer_index = 1 - self.particle_buffer_index;
+    }
+
+    /// The [`Bounds`] that the particles are partitioned within.
+    pub fn bounds_buffer(&self) -> &Buffer {
+        &self.bounds_buffer
+    }
+
+    /// The number of particles in each cell of the grid partition, as an `array<GridCell>`.
+    pub fn grid_buffer(&self) -> &Buffer {
+        &self.grid_buffer
+    }
+
+    /// The indices of the particles in each cell of the grid partition, as an `array<u32>` with
+    /// `max_particles_per_grid_cell` slots per cell.
+    pub fn grid_particles_buffer(&self) -> &Buffer {
+        &self.grid_particles_buffer
+    }
+
+    /// The mass and centre of mass of the particles in each cell of the mass grid, as an
+    /// `array<MassCell>` with [`Configuration::mass_cell_count`] cells, level by level from the
+    /// finest.
+    pub fn mass_grid_buffer(&self) -> &Buffer {
+        &self.mass_grid_buffer
+    }
+
+    /// The distance constraints, as an `array<DistanceConstraint>` with room for at least
+    /// [`Self::constraints`], including whether each of them has broken.
+    pub fn constraint_buffer(&self) -> &Buffer {
+        &self.constraint_buffer
+    }
+
+    /// The constraints that each particle is part of, as an `array<ParticleConstraints>`.
+    pub fn particle_constraints_buffer(&self) -> &Buffer {
+        &self.particle_constraints_buffer
+    }
+
+    /// The rigid clusters, as an `array<Cluster>` with room for at least [`Self::clusters`],
+    /// including where each of them was last matched to.
+    pub fn cluster_buffer(&self) -> &Buffer {
+        &self.cluster_buffer
+    }
+
+    /// The cluster that each particle is a member of, as an `array<ClusterParticle>`.
+    pub fn cluster_particles_buffer(&self) -> &Buffer {
+        &self.cluster_particles_buffer
+    }
+
+    /// Replaces the particles with `particles`, which fill the start of the current particle
+    /// buffer.
+    ///
+    /// The slots of the dead particles among them are freed for emitters to fill, and the particle
+    /// count becomes the number of `particles`, which must fit in the particle capacity.
+    pub fn populate(&mut self, queue: &Queue, particles: &[Particle]) -> Result<(), SolError> {
+        let free_slots: Vec<u32> = (0..particles.len() as u32)
+            .filter(|&slot| !particles[slot as usize].is_alive())
+            .collect();
+        self.set_particle_slots(queue, particles.len() as u32, &free_slots)?;
+        let mut encased_particle_buffer = StorageBuffer::new(Vec::<u8>::new());
+        encased_particle_buffer.write(particles).unwrap();
+        queue.write_buffer(
+            self.particle_buffer(),
+            0,
+            &encased_particle_buffer.into_inner(),
+        );
+        Ok(())
+    }
+}
+
+/// Fails if `particle_count` does not fit in `particle_capacity` slots.
+fn check_particle_count(particle_count: u32, particle_capacity: u32) -> Result<(), SolError> {
+    if particle_count > particle_capacity {
+        return Err(SolError::InvalidConfiguration(format!(
+            "{particle_count} particles exceed the particle capacity of {particle_capacity}"
+        )));
+    }
+    Ok(())
+}
+
+/// `materials` padded out to [`MAX_MATERIALS`] with the default material.
+fn material_table(materials: &[Material]) -> [Material; MAX_MATERIALS as usize] {
+    let mut table = [Material::default(); MAX_MATERIALS as usize];
+    table[..materials.len()].copy_from_slice(materials);
+    table
+}
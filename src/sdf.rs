@@ -0,0 +1,72 @@
+//! Signed distance functions shared between the simulation and the visualisation, and the colliders
+//! built from them.
+use bytemuck::{Pod, Zeroable};
+use glam::{Quat, Vec3, Vec4};
+
+#[include_wgsl_oil::include_wgsl_oil("sdf.wgsl")]
+pub mod sdf {}
+
+pub use sdf::constants::EMPTY_DISTANCE::VALUE as EMPTY_DISTANCE;
+pub use sdf::constants::MAX_COLLIDERS::VALUE as MAX_COLLIDERS;
+pub use sdf::constants::OPERATION_INTERSECTION::VALUE as OPERATION_INTERSECTION;
+pub use sdf::constants::OPERATION_SUBTRACTION::VALUE as OPERATION_SUBTRACTION;
+pub use sdf::constants::OPERATION_UNION::VALUE as OPERATION_UNION;
+pub use sdf::constants::SHAPE_BOX::VALUE as SHAPE_BOX;
+pub use sdf::constants::SHAPE_CAPSULE::VALUE as SHAPE_CAPSULE;
+pub use sdf::constants::SHAPE_PLANE::VALUE as SHAPE_PLANE;
+pub use sdf::constants::SHAPE_SPHERE::VALUE as SHAPE_SPHERE;
+
+/// A static shape that particles are pushed out of and that is drawn alongside them.
+///
+/// Colliders are combined in the order they are uploaded with [`crate::Resources::set_colliders`],
+/// each with its own operation, so a collider can carve into or intersect those before it.
+pub use sdf::types::Collider;
+unsafe impl Pod for Collider {}
+unsafe impl Zeroable for Collider {}
+impl Copy for Collider {}
+
+impl Collider {
+    /// A grey sphere of `radius` centred on `position`, added to the colliders before it.
+    pub fn sphere(position: Vec3, radius: f32) -> Self {
+        Self::new(SHAPE_SPHERE, position, Vec3::new(radius, 0.0, 0.0))
+    }
+
+    /// A grey box centred on `position` that extends `half_size` either side of it along each axis.
+    pub fn cuboid(position: Vec3, half_size: Vec3) -> Self {
+        Self::new(SHAPE_BOX, position, half_size)
+    }
+
+    /// A grey upright capsule of `radius` centred on `position`, with the centres of its caps
+    /// `half_length` above and below it.
+    pub fn capsule(position: Vec3, half_length: f32, radius: f32) -> Self {
+        Self::new(SHAPE_CAPSULE, position, Vec3::new(radius, half_length, 0.0))
+    }
+
+    /// A grey plane through `position` that faces `normal` and is solid behind it.
+    pub fn plane(position: Vec3, normal: Vec3) -> Self {
+        Collider {
+            rotation: Vec4::from(Quat::from_rotation_arc(Vec3::Y, normal.normalize())),
+            ..Self::new(SHAPE_PLANE, position, Vec3::ZERO)
+        }
+    }
+
+    fn new(shape: u32, position: Vec3, size: Vec3) -> Self {
+        Collider {
+            position,
+            shape,
+            rotation: Vec4::from(Quat::IDENTITY),
+            size,
+            operation: OPERATION_UNION,
+            colour: Vec3::splat(0.6),
+            smoothing: 0.0,
+        }
+    }
+
+    /// The same collider rotated by `rotation` about its centre.
+    pub fn rotated(self, rotation: Quat) -> Self {
+        Collider {
+            rotation: Vec4::from(rotation * Quat::from_vec4(self.rotation)),
+            ..self
+        }
+    }
+}
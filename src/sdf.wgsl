@@ -0,0 +1,131 @@
+// Signed distance functions shared by the simulation, which pushes particles out of the colliders,
+// and the visualisation, which ray marches them. Distances are negative inside a shape
+
+// Number of entries in the collider table
+const MAX_COLLIDERS = 16u;
+
+// The distance to a scene without colliders, from which the colliders are combined in order
+const EMPTY_DISTANCE = 1e9;
+
+// The primitive that a collider is made of, in its local space
+const SHAPE_SPHERE = 0u;
+const SHAPE_BOX = 1u;
+// Along the local y axis
+const SHAPE_CAPSULE = 2u;
+// Facing up the local y axis, solid below it
+const SHAPE_PLANE = 3u;
+
+// How a collider combines with the colliders before it in the table
+const OPERATION_UNION = 0u;
+// Carves the collider out of the colliders before it
+const OPERATION_SUBTRACTION = 1u;
+const OPERATION_INTERSECTION = 2u;
+
+@export struct Collider {
+  position: vec3<f32>,
+  // One of the `SHAPE_*` constants
+  shape: u32,
+  // Rotation from local to world space, as a quaternion
+  rotation: vec4<f32>,
+  // The radius of a sphere in `x`, the half extents of a box, the radius and half the length
+  // between the centres of the caps of a capsule in `x` and `y`, and unused for a plane
+  size: vec3<f32>,
+  // One of the `OPERATION_*` constants
+  operation: u32,
+  colour: vec3<f32>,
+  // Distance over which the collider blends into the colliders before it, 0 for a sharp edge
+  smoothing: f32,
+}
+
+// The distance from `position`, in world space, to the surface of `collider` alone
+fn collider_distance(collider: Collider, position: vec3<f32>) -> f32 {
+  let local_position = rotate_inverse(collider.rotation, position - collider.position);
+  if (collider.shape == SHAPE_SPHERE) {
+    return sphere(local_position, collider.size.x);
+  }
+  if (collider.shape == SHAPE_BOX) {
+    return cube(local_position, collider.size);
+  }
+  if (collider.shape == SHAPE_CAPSULE) {
+    return capsule_y(local_position, collider.size.y, collider.size.x);
+  }
+  return plane_y_infinite(local_position);
+}
+
+// Combines the `distance` to `collider` with the `scene_distance` to the colliders before it
+fn combine_collider(collider: Collider, scene_distance: f32, distance: f32) -> f32 {
+  let k = collider.smoothing;
+  if (collider.operation == OPERATION_SUBTRACTION) {
+    if (k > 0.0) {
+      return smooth_subtraction(distance, scene_distance, k);
+    }
+    return sharp_subtraction(distance, scene_distance);
+  }
+  if (collider.operation == OPERATION_INTERSECTION) {
+    if (k > 0.0) {
+      return smooth_intersection(distance, scene_distance, k);
+    }
+    return sharp_intersection(distance, scene_distance);
+  }
+  if (k > 0.0) {
+    return smooth_union(distance, scene_distance, k);
+  }
+  return sharp_union(distance, scene_distance);
+}
+
+// Rotates `vector` by the unit `quaternion`
+fn rotate(quaternion: vec4<f32>, vector: vec3<f32>) -> vec3<f32> {
+  let t = 2.0 * cross(quaternion.xyz, vector);
+  return vector + quaternion.w * t + cross(quaternion.xyz, t);
+}
+
+// Rotates `vector` by the inverse of the unit `quaternion`
+fn rotate_inverse(quaternion: vec4<f32>, vector: vec3<f32>) -> vec3<f32> {
+  let t = 2.0 * cross(-quaternion.xyz, vector);
+  return vector + quaternion.w * t + cross(-quaternion.xyz, t);
+}
+
+// https://iquilezles.org/articles/distfunctions/
+
+fn sphere(position: vec3<f32>, radius: f32) -> f32 {
+  return length(position) - radius;
+}
+
+fn sphere_relative(position: vec3<f32>, translation: vec3<f32>, radius: f32) -> f32 {
+  return length(position - translation) - radius;
+}
+
+fn cube(position: vec3<f32>, extent: vec3<f32>) -> f32 {
+  let q = abs(position) - extent;
+  return length(max(q, vec3<f32>(.0))) + min(max(q.x, max(q.y, q.z)), .0);
+}
+
+fn capsule_y(position: vec3<f32>, half_length: f32, radius: f32) -> f32 {
+  let closest = vec3<f32>(0.0, clamp(position.y, -half_length, half_length), 0.0);
+  return length(position - closest) - radius;
+}
+
+fn plane_y_infinite(position: vec3<f32>) -> f32 {
+  return position.y;
+}
+
+fn sharp_union(a: f32, b: f32) -> f32 { return min(a, b); }
+
+fn sharp_subtraction(a: f32, b: f32) -> f32 { return max(-a, b); }
+
+fn sharp_intersection(a: f32, b: f32) -> f32 { return max(a, b); }
+
+fn smooth_union(a: f32, b: f32, k: f32) -> f32 {
+  let h = clamp(.5 + .5 * (b - a) / k, .0, 1.);
+  return mix(b, a, h) - k * h * (1. - h);
+}
+
+fn smooth_subtraction(a: f32, b: f32, k: f32) -> f32 {
+  let h = clamp(.5 - .5 * (b + a) / k, .0, 1.);
+  return mix(b, -a, h) + k * h * (1. - h);
+}
+
+fn smooth_intersection(a: f32, b: f32, k: f32) -> f32 {
+  let h = clamp(.5 - .5 * (b - a) / k, .0, 1.);
+  return mix(b, a, h) + k * h * (1. - h);
+}
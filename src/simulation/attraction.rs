@@ -0,0 +1,266 @@
+use super::simulation::SelfGravity;
+use crate::resources::Resources;
+use crate::wgpu_utilities::{Device, QueueUtilities};
+use bytemuck::{Pod, Zeroable};
+use encase::ShaderSize;
+use std::borrow::Cow;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    ComputePass, ComputePipeline, ComputePipelineDescriptor, PipelineLayoutDescriptor, Queue,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages,
+};
+
+#[include_wgsl_oil::include_wgsl_oil("attraction.wgsl")]
+mod shader {}
+use shader::types::AttractionUniforms;
+unsafe impl Pod for AttractionUniforms {}
+unsafe impl Zeroable for AttractionUniforms {}
+impl Copy for AttractionUniforms {}
+
+/// Accelerates the particles of [`Resources`] by the gravity between them, recorded by
+/// [`super::Simulation`] after the particles are predicted in every substep.
+///
+/// Kept apart from the rest of the simulation so that the mass grid does not take one more storage
+/// buffer than every adapter supports.
+pub struct Attraction {
+    bind_group_layout: BindGroupLayout,
+    bind_groups: [BindGroup; 2],
+    resources_generation: u64,
+    attract_compute_pipeline: ComputePipeline,
+    uniform_buffer: Buffer,
+}
+
+impl Drop for Attraction {
+    fn drop(&mut self) {
+        self.uniform_buffer.destroy();
+    }
+}
+
+impl Attraction {
+    /// Creates the compute pipeline and binds the parameters, particle, bounds, grid and mass grid
+    /// buffers of `resources`, without capturing errors.
+    pub fn create(device: &Device, resources: &Resources) -> Self {
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(Cow::Borrowed(shader::SOURCE)),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                // Uniforms
+                BindGroupLayoutEntry {
+                    binding: shader::globals::uniforms::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Particles
+                BindGroupLayoutEntry {
+                    binding: shader::globals::particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Bounds
+                BindGroupLayoutEntry {
+                    binding: shader::globals::bounds::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Grid
+                BindGroupLayoutEntry {
+                    binding: shader::globals::grid::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Grid particles
+                BindGroupLayoutEntry {
+                    binding: shader::globals::grid_particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Parameters
+                BindGroupLayoutEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Next particles
+                BindGroupLayoutEntry {
+                    binding: shader::globals::next_particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Mass grid
+                BindGroupLayoutEntry {
+                    binding: shader::globals::mass_grid::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let attract_compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: shader::entry_points::attract::NAME,
+        });
+
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: AttractionUniforms::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_groups =
+            Self::create_bind_groups(device, &bind_group_layout, &uniform_buffer, resources);
+
+        Attraction {
+            bind_group_layout,
+            bind_groups,
+            resources_generation: resources.generation(),
+            attract_compute_pipeline,
+            uniform_buffer,
+        }
+    }
+
+    /// One bind group for each of the particle buffers of `resources`, which reads that buffer and
+    /// writes the other one.
+    fn create_bind_groups(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        resources: &Resources,
+    ) -> [BindGroup; 2] {
+        let [front, back] = resources.particle_buffers();
+        [(front, back), (back, front)].map(|(particle_buffer, next_particle_buffer)| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: shader::globals::uniforms::binding::BINDING,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::particles::binding::BINDING,
+                        resource: particle_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::bounds::binding::BINDING,
+                        resource: resources.bounds_buffer().as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::grid::binding::BINDING,
+                        resource: resources.grid_buffer().as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::grid_particles::binding::BINDING,
+                        resource: resources.grid_particles_buffer().as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::parameters::binding::BINDING,
+                        resource: resources.parameters_buffer().as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::next_particles::binding::BINDING,
+                        resource: next_particle_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::mass_grid::binding::BINDING,
+                        resource: resources.mass_grid_buffer().as_entire_binding(),
+                    },
+                ],
+            })
+        })
+    }
+
+    /// Rebuilds the bind groups if the buffers of `resources` have been reallocated since they were
+    /// created, and writes the uniforms for `self_gravity` over substeps of `delta_time` seconds.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        resources: &Resources,
+        self_gravity: SelfGravity,
+        delta_time: f32,
+    ) {
+        if self.resources_generation != resources.generation() {
+            self.bind_groups = Self::create_bind_groups(
+                device,
+                &self.bind_group_layout,
+                &self.uniform_buffer,
+                resources,
+            );
+            self.resources_generation = resources.generation();
+        }
+        let uniforms = AttractionUniforms {
+            delta_time,
+            gravitational_constant: self_gravity.gravitational_constant,
+            softening_length: self_gravity.softening_length,
+        };
+        queue.write_encased_uniform_buffer(&self.uniform_buffer, uniforms);
+    }
+
+    /// Records accelerating the particles into `compute_pass`, over the particle slots that
+    /// `indirect_buffer` dispatches, then swaps the particle buffers of `resources`.
+    pub fn attract<'a>(
+        &'a self,
+        compute_pass: &mut ComputePass<'a>,
+        resources: &mut Resources,
+        indirect_buffer: &'a Buffer,
+    ) {
+        compute_pass.set_bind_group(0, &self.bind_groups[resources.particle_buffer_index()], &[]);
+        compute_pass.set_pipeline(&self.attract_compute_pipeline);
+        compute_pass.dispatch_workgroups_indirect(indirect_buffer, 0);
+        resources.swap_particle_buffers();
+    }
+}
@@ -0,0 +1,133 @@
+#import ../common.wgsl as Common
+
+@export struct AttractionUniforms {
+    // Length of a substep
+    delta_time: f32,
+    // Strength of the gravity between the particles
+    gravitational_constant: f32,
+    // Distance over which the gravity between two particles is smoothed out, so that it stays
+    // finite as they meet
+    softening_length: f32,
+}
+
+@group(0)
+@binding(0)
+var<uniform> uniforms: AttractionUniforms;
+
+@group(0)
+@binding(1)
+var<storage, read> particles: array<Common::Particle>;
+
+@group(0)
+@binding(2)
+var<storage, read> bounds: Common::Bounds;
+
+@group(0)
+@binding(3)
+var<storage, read> grid: array<Common::GridCell>;
+
+@group(0)
+@binding(4)
+var<storage, read> grid_particles: array<u32>;
+
+@group(0)
+@binding(5)
+var<uniform> parameters: Common::Parameters;
+
+@group(0)
+@binding(6)
+var<storage, read_write> next_particles: array<Common::Particle>;
+
+// Summed from the particles at the start of the step
+@group(0)
+@binding(7)
+var<storage, read> mass_grid: array<Common::MassCell>;
+
+// Accelerates every particle moved by `simulation.wgsl::predict` by the gravity between the
+// particles, taken at the positions from the start of the substep, which `predict` keeps as their
+// old positions
+@compute
+@workgroup_size(64)
+fn attract(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particle_index = global_invocation_id.x;
+    if (particle_index >= parameters.particle_count) {
+        return;
+    }
+    var particle = particles[particle_index];
+    if (particle.alive != 0u) {
+        let delta_time_squared = uniforms.delta_time * uniforms.delta_time;
+        particle.position += self_gravity(particle_index, particle.old_position) * delta_time_squared;
+    }
+    next_particles[particle_index] = particle;
+}
+
+// The gravity that every other particle pulls the particle at `particle_index` and `position` with.
+// The particles in the cells around it are summed one by one, and those further away as the point
+// masses of the cells of the mass grid, at the coarsest level at which the cells are still a cell
+// apart from the one that it is in, as in the fast multipole method but only keeping the monopoles
+fn self_gravity(particle_index: u32, position: vec3<f32>) -> vec3<f32> {
+    let grid_position = Common::world_position_to_clamped_grid_position(position, bounds, parameters);
+    var pull = vec3<f32>(0.0);
+
+    let top_level = Common::mass_grid_top_level(parameters);
+    for (var level = 0u; level <= top_level; level++) {
+        let size = vec3<i32>(Common::mass_grid_size(level, parameters));
+        let level_position = grid_position >> vec3<u32>(level);
+        // The cells within the cells around the cell above that are not around this cell, which
+        // were too close to take as a whole at the level above
+        var range_min = vec3<i32>(0);
+        var range_max = size - 1;
+        if (level < top_level) {
+            let parent_position = level_position >> vec3<u32>(1u);
+            range_min = max(parent_position * 2 - 2, range_min);
+            range_max = min(parent_position * 2 + 3, range_max);
+        }
+        let offset = Common::mass_grid_offset(level, parameters);
+        var cell_position = vec3<i32>();
+        for (cell_position.z = range_min.z; cell_position.z <= range_max.z; cell_position.z++) {
+            for (cell_position.y = range_min.y; cell_position.y <= range_max.y; cell_position.y++) {
+                for (cell_position.x = range_min.x; cell_position.x <= range_max.x; cell_position.x++) {
+                    if (all(abs(cell_position - level_position) <= vec3<i32>(1))) {
+                        continue;
+                    }
+                    let index = cell_position.x + cell_position.y * size.x + cell_position.z * size.x * size.y;
+                    let cell = mass_grid[offset + u32(index)];
+                    if (cell.mass > 0.0) {
+                        pull += point_mass_pull(cell.centre - position, cell.mass);
+                    }
+                }
+            }
+        }
+    }
+
+    let range_min = max(grid_position - 1, vec3<i32>(0));
+    let range_max = min(grid_position + 1, vec3<i32>(parameters.grid_size) - 1);
+    var cell_position = vec3<i32>();
+    for (cell_position.z = range_min.z; cell_position.z <= range_max.z; cell_position.z++) {
+        for (cell_position.y = range_min.y; cell_position.y <= range_max.y; cell_position.y++) {
+            for (cell_position.x = range_min.x; cell_position.x <= range_max.x; cell_position.x++) {
+                let grid_index = Common::grid_position_to_grid_index(cell_position, parameters);
+                let particles_length = min(grid[grid_index].particles_length, parameters.max_particles_per_grid_cell);
+                for (var i = 0u; i < particles_length; i++) {
+                    let neighbour_index = grid_particles[Common::grid_particle_index(grid_index, i, parameters)];
+                    // Only particles that are alive are in the grid, so `predict` has moved each of
+                    // them from its old position
+                    let neighbour = particles[neighbour_index];
+                    // Only counted in the cell that its centre is in, as the mass grid counts it
+                    let neighbour_grid_position = Common::world_position_to_clamped_grid_position(neighbour.old_position, bounds, parameters);
+                    if (neighbour_index != particle_index && all(neighbour_grid_position == cell_position)) {
+                        pull += point_mass_pull(neighbour.old_position - position, neighbour.mass);
+                    }
+                }
+            }
+        }
+    }
+    return pull * uniforms.gravitational_constant;
+}
+
+// The softened pull of `mass` at `offset`, without the gravitational constant
+fn point_mass_pull(offset: vec3<f32>, mass: f32) -> vec3<f32> {
+    let softening_length = uniforms.softening_length;
+    let distance_squared = dot(offset, offset) + softening_length * softening_length;
+    return offset * mass / (distance_squared * sqrt(distance_squared));
+}
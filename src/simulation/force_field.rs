@@ -0,0 +1,86 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Quat, Vec3, Vec4};
+
+#[include_wgsl_oil::include_wgsl_oil("force_field.wgsl")]
+mod shader {}
+pub use shader::constants::FORCE_FIELD_ATTRACTOR::VALUE as FORCE_FIELD_ATTRACTOR;
+pub use shader::constants::FORCE_FIELD_VORTEX::VALUE as FORCE_FIELD_VORTEX;
+pub use shader::constants::FORCE_FIELD_WIND::VALUE as FORCE_FIELD_WIND;
+pub use shader::constants::MAX_FORCE_FIELDS::VALUE as MAX_FORCE_FIELDS;
+
+/// Accelerates the particles within it, regardless of their mass, on top of gravity.
+///
+/// Force fields are uploaded with [`crate::Simulation::set_force_fields`], which can be called
+/// between steps to move them or change their strength.
+pub use shader::types::ForceField;
+unsafe impl Pod for ForceField {}
+unsafe impl Zeroable for ForceField {}
+impl Copy for ForceField {}
+
+impl ForceField {
+    /// Pulls the particles within `radius` of `position` towards it, or pushes them away if
+    /// `strength` is negative, at `strength` at its centre fading linearly to nothing at `radius`.
+    pub fn attractor(position: Vec3, radius: f32, strength: f32) -> Self {
+        assert!(radius > 0.0, "an attractor needs a positive radius");
+        ForceField {
+            radius,
+            falloff: 1.0,
+            ..Self::new(FORCE_FIELD_ATTRACTOR, position, strength)
+        }
+    }
+
+    /// Turns the particles within `radius` of the line through `position` along `axis` around it,
+    /// anticlockwise looking down `axis`, at `strength` at its axis fading linearly to nothing at
+    /// `radius`.
+    pub fn vortex(position: Vec3, axis: Vec3, radius: f32, strength: f32) -> Self {
+        assert!(radius > 0.0, "a vortex needs a positive radius");
+        ForceField {
+            axis: axis.normalize(),
+            radius,
+            falloff: 1.0,
+            ..Self::new(FORCE_FIELD_VORTEX, position, strength)
+        }
+    }
+
+    /// Blows the particles within a box centred on `position` that extends `half_size` either side
+    /// of it along each axis towards `velocity`, losing `drag` of the difference in velocity per
+    /// second.
+    pub fn wind(position: Vec3, half_size: Vec3, velocity: Vec3, drag: f32) -> Self {
+        assert!(drag >= 0.0, "drag cannot be negative");
+        ForceField {
+            size: half_size,
+            axis: velocity.normalize_or_zero(),
+            drag,
+            ..Self::new(FORCE_FIELD_WIND, position, velocity.length())
+        }
+    }
+
+    /// Slows the particles within a box centred on `position` that extends `half_size` either side
+    /// of it along each axis, losing `drag` of their velocity per second, as still wind.
+    pub fn drag(position: Vec3, half_size: Vec3, drag: f32) -> Self {
+        Self::wind(position, half_size, Vec3::ZERO, drag)
+    }
+
+    fn new(kind: u32, position: Vec3, strength: f32) -> Self {
+        ForceField {
+            position,
+            kind,
+            rotation: Vec4::from(Quat::IDENTITY),
+            size: Vec3::ZERO,
+            strength,
+            axis: Vec3::Y,
+            radius: 0.0,
+            falloff: 0.0,
+            drag: 0.0,
+        }
+    }
+
+    /// The same force field rotated by `rotation` about its centre.
+    pub fn rotated(self, rotation: Quat) -> Self {
+        ForceField {
+            rotation: Vec4::from(rotation * Quat::from_vec4(self.rotation)),
+            axis: rotation * self.axis,
+            ..self
+        }
+    }
+}
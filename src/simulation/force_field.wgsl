@@ -0,0 +1,65 @@
+#import ../sdf.wgsl as Sdf
+
+// Number of entries in the force field table
+const MAX_FORCE_FIELDS = 8u;
+
+// How a force field accelerates the particles
+const FORCE_FIELD_ATTRACTOR = 0u;
+const FORCE_FIELD_VORTEX = 1u;
+// Drag zones are still wind
+const FORCE_FIELD_WIND = 2u;
+
+@export struct ForceField {
+    position: vec3<f32>,
+    // One of the `FORCE_FIELD_*` constants
+    kind: u32,
+    // Rotation of the box that wind blows within, from local to world space, as a quaternion
+    rotation: vec4<f32>,
+    // Half extents of the box that wind blows within
+    size: vec3<f32>,
+    // Acceleration towards an attractor, negative to repel, or around a vortex at its centre,
+    // negative to turn the other way, and the speed of wind
+    strength: f32,
+    // World space unit axis that a vortex turns around and that wind blows along
+    axis: vec3<f32>,
+    // Distance from the centre of an attractor, or the axis of a vortex, at which it fades to
+    // nothing
+    radius: f32,
+    // Power of the fade of attractors and vortices from their centre to their radius, 0 for none
+    falloff: f32,
+    // Fraction of the difference between the velocity of a particle and the wind that is lost per
+    // second
+    drag: f32,
+}
+
+// The acceleration of a particle at `position` moving at `velocity` due to `field`, over a step of
+// `delta_time` seconds
+fn acceleration(field: ForceField, position: vec3<f32>, velocity: vec3<f32>, delta_time: f32) -> vec3<f32> {
+    let offset = position - field.position;
+    if (field.kind == FORCE_FIELD_ATTRACTOR) {
+        let distance = length(offset);
+        if (distance <= 0.0 || distance >= field.radius) {
+            return vec3<f32>(0.0);
+        }
+        return -offset / distance * field.strength * fade(field, distance);
+    }
+    if (field.kind == FORCE_FIELD_VORTEX) {
+        let radial = offset - field.axis * dot(offset, field.axis);
+        let distance = length(radial);
+        if (distance <= 0.0 || distance >= field.radius) {
+            return vec3<f32>(0.0);
+        }
+        return cross(field.axis, radial) / distance * field.strength * fade(field, distance);
+    }
+    let local_position = Sdf::rotate_inverse(field.rotation, offset);
+    if (any(abs(local_position) > field.size)) {
+        return vec3<f32>(0.0);
+    }
+    // Losing more than the whole difference in a step would overshoot the wind
+    let rate = min(field.drag * delta_time, 1.0) / delta_time;
+    return (field.axis * field.strength - velocity) * rate;
+}
+
+fn fade(field: ForceField, distance: f32) -> f32 {
+    return pow(1.0 - distance / field.radius, field.falloff);
+}
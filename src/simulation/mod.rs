@@ -1,2 +1,14 @@
+//! Integration and collision resolution of the particles, the forces between fluid particles, force
+//! fields, the gravity between the particles and the shape matching of rigid clusters.
+mod attraction;
+mod force_field;
+mod shape_matching;
 mod simulation;
-pub use simulation::Simulation;
+pub use force_field::{
+    ForceField, FORCE_FIELD_ATTRACTOR, FORCE_FIELD_VORTEX, FORCE_FIELD_WIND, MAX_FORCE_FIELDS,
+};
+pub use simulation::{
+    GravityModel, NeighbourSearch, Phase, RadialFalloff, RadialGravity, SelfGravity, Simulation,
+    COLLISION_EPSILON, CONTACT_SLOP, DEFAULT_ITERATIONS, DEFAULT_RELAXATION,
+    DEFAULT_SMOOTHING_LENGTH, DEFAULT_SUBSTEPS, GRADIENT_STEP,
+};
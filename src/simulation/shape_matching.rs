@@ -0,0 +1,252 @@
+use crate::resources::Resources;
+use crate::wgpu_utilities::{Device, QueueUtilities};
+use bytemuck::{Pod, Zeroable};
+use encase::ShaderSize;
+use std::borrow::Cow;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    ComputePass, ComputePipeline, ComputePipelineDescriptor, PipelineLayoutDescriptor, Queue,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages,
+};
+
+#[include_wgsl_oil::include_wgsl_oil("shape_matching.wgsl")]
+mod shader {}
+use shader::types::ShapeMatchingUniforms;
+unsafe impl Pod for ShapeMatchingUniforms {}
+unsafe impl Zeroable for ShapeMatchingUniforms {}
+impl Copy for ShapeMatchingUniforms {}
+
+/// Matches the rigid clusters of [`Resources`] to their rest shapes, recorded by
+/// [`super::Simulation`] at the end of every substep.
+///
+/// Kept apart from the rest of the simulation so that neither of their bind groups needs more
+/// storage buffers than every adapter supports.
+pub struct ShapeMatching {
+    bind_group_layout: BindGroupLayout,
+    bind_groups: [BindGroup; 2],
+    resources_generation: u64,
+    match_clusters_compute_pipeline: ComputePipeline,
+    snap_to_clusters_compute_pipeline: ComputePipeline,
+    uniform_buffer: Buffer,
+}
+
+impl Drop for ShapeMatching {
+    fn drop(&mut self) {
+        self.uniform_buffer.destroy();
+    }
+}
+
+impl ShapeMatching {
+    /// Creates the compute pipelines and binds the parameters, particle and cluster buffers of
+    /// `resources`, without capturing errors.
+    pub fn create(device: &Device, resources: &Resources) -> Self {
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(Cow::Borrowed(shader::SOURCE)),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                // Uniforms
+                BindGroupLayoutEntry {
+                    binding: shader::globals::uniforms::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Particles
+                BindGroupLayoutEntry {
+                    binding: shader::globals::particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Parameters
+                BindGroupLayoutEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Next particles
+                BindGroupLayoutEntry {
+                    binding: shader::globals::next_particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Clusters
+                BindGroupLayoutEntry {
+                    binding: shader::globals::clusters::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Cluster particles
+                BindGroupLayoutEntry {
+                    binding: shader::globals::cluster_particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let match_clusters_compute_pipeline =
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: shader::entry_points::match_clusters::NAME,
+            });
+
+        let snap_to_clusters_compute_pipeline =
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: shader::entry_points::snap_to_clusters::NAME,
+            });
+
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: ShapeMatchingUniforms::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_groups =
+            Self::create_bind_groups(device, &bind_group_layout, &uniform_buffer, resources);
+
+        ShapeMatching {
+            bind_group_layout,
+            bind_groups,
+            resources_generation: resources.generation(),
+            match_clusters_compute_pipeline,
+            snap_to_clusters_compute_pipeline,
+            uniform_buffer,
+        }
+    }
+
+    /// One bind group for each of the particle buffers of `resources`, which reads that buffer and
+    /// writes the other one.
+    fn create_bind_groups(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        resources: &Resources,
+    ) -> [BindGroup; 2] {
+        let [front, back] = resources.particle_buffers();
+        [(front, back), (back, front)].map(|(particle_buffer, next_particle_buffer)| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: shader::globals::uniforms::binding::BINDING,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::particles::binding::BINDING,
+                        resource: particle_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::parameters::binding::BINDING,
+                        resource: resources.parameters_buffer().as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::next_particles::binding::BINDING,
+                        resource: next_particle_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::clusters::binding::BINDING,
+                        resource: resources.cluster_buffer().as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: shader::globals::cluster_particles::binding::BINDING,
+                        resource: resources.cluster_particles_buffer().as_entire_binding(),
+                    },
+                ],
+            })
+        })
+    }
+
+    /// Rebuilds the bind groups if the buffers of `resources` have been reallocated since they were
+    /// created, and writes the uniforms for the clusters of `resources`.
+    pub fn prepare(&mut self, device: &Device, queue: &Queue, resources: &Resources) {
+        if self.resources_generation != resources.generation() {
+            self.bind_groups = Self::create_bind_groups(
+                device,
+                &self.bind_group_layout,
+                &self.uniform_buffer,
+                resources,
+            );
+            self.resources_generation = resources.generation();
+        }
+        let uniforms = ShapeMatchingUniforms {
+            cluster_count: resources.clusters().len() as u32,
+        };
+        queue.write_encased_uniform_buffer(&self.uniform_buffer, uniforms);
+    }
+
+    /// Records matching the clusters and snapping their members to them into `compute_pass`, over
+    /// the particle slots that `indirect_buffer` dispatches, then swaps the particle buffers of
+    /// `resources`. Records nothing if there are no clusters.
+    pub fn match_clusters<'a>(
+        &'a self,
+        compute_pass: &mut ComputePass<'a>,
+        resources: &mut Resources,
+        indirect_buffer: &'a Buffer,
+    ) {
+        let cluster_count = resources.clusters().len() as u32;
+        if cluster_count == 0 {
+            return;
+        }
+        compute_pass.set_bind_group(0, &self.bind_groups[resources.particle_buffer_index()], &[]);
+
+        // Only writes the clusters, so the buffers are not swapped
+        let workgroup_size = shader::entry_points::match_clusters::WORKGROUP_SIZE;
+        compute_pass.set_pipeline(&self.match_clusters_compute_pipeline);
+        compute_pass.dispatch_workgroups(
+            (cluster_count as f32 / workgroup_size[0] as f32).ceil() as u32,
+            workgroup_size[1],
+            workgroup_size[2],
+        );
+
+        compute_pass.set_pipeline(&self.snap_to_clusters_compute_pipeline);
+        compute_pass.dispatch_workgroups_indirect(indirect_buffer, 0);
+        resources.swap_particle_buffers();
+    }
+}
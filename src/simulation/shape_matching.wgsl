@@ -0,0 +1,127 @@
+#import ../common.wgsl as Common
+
+// Iterations spent refining the rotation of each cluster per substep, starting from its previous
+// rotation
+const ROTATION_ITERATIONS = 8u;
+
+@export struct ShapeMatchingUniforms {
+    // Number of clusters in `clusters`
+    cluster_count: u32,
+}
+
+@group(0)
+@binding(0)
+var<uniform> uniforms: ShapeMatchingUniforms;
+
+@group(0)
+@binding(1)
+var<storage, read> particles: array<Common::Particle>;
+
+@group(0)
+@binding(2)
+var<uniform> parameters: Common::Parameters;
+
+@group(0)
+@binding(3)
+var<storage, read_write> next_particles: array<Common::Particle>;
+
+@group(0)
+@binding(4)
+var<storage, read_write> clusters: array<Common::Cluster>;
+
+@group(0)
+@binding(5)
+var<storage, read> cluster_particles: array<Common::ClusterParticle>;
+
+// Finds the rigid transform that best fits the members of each cluster to its rest shape, from
+// Müller et al. 2005, "Meshless Deformations Based on Shape Matching". The rotation is extracted
+// from the covariance of the members with the iterative method of Müller et al. 2016, "A Robust
+// Method to Extract the Rotational Part of Deformations", which stays stable however far the
+// cluster tumbles. Deleted members are left out, and a cluster without any members left keeps its
+// last transform
+@compute
+@workgroup_size(64)
+fn match_clusters(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let cluster_index = global_invocation_id.x;
+    if (cluster_index >= uniforms.cluster_count) {
+        return;
+    }
+    var cluster = clusters[cluster_index];
+    let last_particle = cluster.first_particle + cluster.particle_count;
+
+    var weighted_position = vec3<f32>(0.0);
+    var mass = 0.0;
+    for (var particle_index = cluster.first_particle; particle_index < last_particle; particle_index++) {
+        let particle = particles[particle_index];
+        if (particle.alive == 0u) {
+            continue;
+        }
+        weighted_position += particle.position * particle.mass;
+        mass += particle.mass;
+    }
+    if (mass <= 0.0) {
+        return;
+    }
+    let centre = weighted_position / mass;
+
+    // The covariance between where the members are and where they sit at rest
+    var covariance = mat3x3<f32>(vec3<f32>(0.0), vec3<f32>(0.0), vec3<f32>(0.0));
+    for (var particle_index = cluster.first_particle; particle_index < last_particle; particle_index++) {
+        let particle = particles[particle_index];
+        if (particle.alive == 0u) {
+            continue;
+        }
+        let offset = (particle.position - centre) * particle.mass;
+        let rest_offset = cluster_particles[particle_index].rest_offset;
+        covariance += mat3x3<f32>(offset * rest_offset.x, offset * rest_offset.y, offset * rest_offset.z);
+    }
+
+    var rotation = cluster.rotation;
+    for (var iteration = 0u; iteration < ROTATION_ITERATIONS; iteration++) {
+        let x = rotate(rotation, vec3<f32>(1.0, 0.0, 0.0));
+        let y = rotate(rotation, vec3<f32>(0.0, 1.0, 0.0));
+        let z = rotate(rotation, vec3<f32>(0.0, 0.0, 1.0));
+        let torque = cross(x, covariance[0]) + cross(y, covariance[1]) + cross(z, covariance[2]);
+        let alignment = dot(x, covariance[0]) + dot(y, covariance[1]) + dot(z, covariance[2]);
+        let angular_correction = torque / (abs(alignment) + 1e-9);
+        let angle = length(angular_correction);
+        if (angle < 1e-9) {
+            break;
+        }
+        let half_angle = angle * 0.5;
+        let correction = vec4<f32>(angular_correction / angle * sin(half_angle), cos(half_angle));
+        rotation = normalize(multiply_quaternions(correction, rotation));
+    }
+
+    cluster.centre = centre;
+    cluster.rotation = rotation;
+    clusters[cluster_index] = cluster;
+}
+
+// Moves every member of a cluster towards where the rigid transform found by `match_clusters` puts
+// it. Only the position moves, so that the correction carries over into the velocity
+@compute
+@workgroup_size(64)
+fn snap_to_clusters(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particle_index = global_invocation_id.x;
+    if (particle_index >= parameters.particle_count) {
+        return;
+    }
+    var particle = particles[particle_index];
+    let cluster_particle = cluster_particles[particle_index];
+    if (particle.alive != 0u && cluster_particle.cluster != Common::NO_CLUSTER) {
+        let cluster = clusters[cluster_particle.cluster];
+        let goal = cluster.centre + rotate(cluster.rotation, cluster_particle.rest_offset);
+        particle.position += (goal - particle.position) * cluster.stiffness;
+    }
+    next_particles[particle_index] = particle;
+}
+
+fn multiply_quaternions(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(a.w * b.xyz + b.w * a.xyz + cross(a.xyz, b.xyz), a.w * b.w - dot(a.xyz, b.xyz));
+}
+
+fn rotate(quaternion: vec4<f32>, vector: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(quaternion.xyz, vector);
+    return vector + quaternion.w * t + cross(quaternion.xyz, t);
+}
@@ -1,34 +1,256 @@
-use crate::common::{Particle, MAX_PARTICLES};
+use super::attraction::Attraction;
+use super::force_field::{ForceField, MAX_FORCE_FIELDS};
+use super::shape_matching::ShapeMatching;
+use crate::common::{ParticleCounters, DEFAULT_PARTICLE_RADIUS, PHASE_FLUID, PHASE_GRANULAR};
+use crate::error::SolError;
+use crate::resources::Resources;
+use crate::wgpu_utilities::{submit, Device, QueueUtilities};
 use bytemuck::{Pod, Zeroable};
-use encase::{ShaderSize, UniformBuffer};
+use encase::ShaderSize;
 use glam::Vec3;
 use std::borrow::Cow;
 use wgpu::{
-    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer,
-    BufferBindingType, BufferDescriptor, BufferUsages, ComputePipeline, ComputePipelineDescriptor,
-    Device, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages,
 };
 
 #[include_wgsl_oil::include_wgsl_oil("simulation.wgsl")]
 mod shader {}
+pub use shader::constants::CONTACT_SLOP::VALUE as CONTACT_SLOP;
+pub use shader::constants::EPSILON::VALUE as COLLISION_EPSILON;
+pub use shader::constants::GRADIENT_STEP::VALUE as GRADIENT_STEP;
 pub use shader::types::Uniforms;
 unsafe impl Pod for Uniforms {}
 unsafe impl Zeroable for Uniforms {}
 impl Copy for Uniforms {}
+use shader::types::FluidParticle;
 
+/// Number of substeps each step is split into unless [`Simulation::set_substeps`] is called.
+pub const DEFAULT_SUBSTEPS: u32 = 2;
+
+/// Number of contact solver iterations per substep unless [`Simulation::set_iterations`] is called.
+pub const DEFAULT_ITERATIONS: u32 = 4;
+
+/// Scale applied to the averaged contact corrections unless [`Simulation::set_relaxation`] is
+/// called.
+pub const DEFAULT_RELAXATION: f32 = 1.0;
+
+/// Distance within which fluid particles interact unless [`Simulation::set_smoothing_length`] is
+/// called.
+pub const DEFAULT_SMOOTHING_LENGTH: f32 = 3.0 * DEFAULT_PARTICLE_RADIUS;
+
+/// How [`Simulation`] finds the particles that a particle may collide with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NeighbourSearch {
+    /// Only the particles in the grid cells that the particle overlaps, which needs the grid to
+    /// have been built from the current particles
+    #[default]
+    Grid,
+    /// Every other particle, which is slow but does not depend on the grid, for validation
+    BruteForce,
+}
+
+impl NeighbourSearch {
+    fn uniform(self) -> u32 {
+        match self {
+            NeighbourSearch::Grid => shader::constants::NEIGHBOUR_SEARCH_GRID::VALUE,
+            NeighbourSearch::BruteForce => shader::constants::NEIGHBOUR_SEARCH_BRUTE_FORCE::VALUE,
+        }
+    }
+}
+
+/// How [`RadialGravity`] weakens beyond its radius.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RadialFalloff {
+    /// With the square of the distance from the centre, as around a planet
+    #[default]
+    InverseSquare,
+    /// Not at all
+    Constant,
+}
+
+impl RadialFalloff {
+    fn uniform(self) -> u32 {
+        match self {
+            RadialFalloff::InverseSquare => shader::constants::RADIAL_FALLOFF_INVERSE_SQUARE::VALUE,
+            RadialFalloff::Constant => shader::constants::RADIAL_FALLOFF_CONSTANT::VALUE,
+        }
+    }
+}
+
+/// A pull towards a point, such as the centre of a planet, see [`GravityModel`].
+///
+/// Within `radius` of the centre the pull weakens linearly to nothing at the centre, as within a
+/// ball of uniform density.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RadialGravity {
+    pub centre: Vec3,
+    /// Radius of the body at the centre, e.g. of a spherical collider that the particles settle
+    /// onto
+    pub radius: f32,
+    /// Acceleration towards the centre at `radius` from it, negative to push away
+    pub strength: f32,
+    pub falloff: RadialFalloff,
+}
+
+impl RadialGravity {
+    /// Pulls towards `centre` at `strength` at `radius` from it, weakening with the square of the
+    /// distance beyond.
+    pub fn inverse_square(centre: Vec3, radius: f32, strength: f32) -> Self {
+        RadialGravity {
+            centre,
+            radius,
+            strength,
+            falloff: RadialFalloff::InverseSquare,
+        }
+    }
+
+    /// Pulls towards `centre` at `strength` anywhere beyond `radius` from it.
+    pub fn constant(centre: Vec3, radius: f32, strength: f32) -> Self {
+        RadialGravity {
+            centre,
+            radius,
+            strength,
+            falloff: RadialFalloff::Constant,
+        }
+    }
+}
+
+/// Which way gravity pulls the particles, see [`Simulation::set_gravity_model`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GravityModel {
+    /// Along the gravity vector of each step, everywhere
+    #[default]
+    Uniform,
+    /// Towards a point, ignoring the gravity vector of each step
+    Radial(RadialGravity),
+    /// Along the gravity vector of each step and towards a point
+    UniformAndRadial(RadialGravity),
+}
+
+impl GravityModel {
+    /// The uniform gravity under `gravity`, the gravity vector of the step, and the radial gravity,
+    /// if any.
+    fn split(self, gravity: Vec3) -> (Vec3, Option<RadialGravity>) {
+        match self {
+            GravityModel::Uniform => (gravity, None),
+            GravityModel::Radial(radial_gravity) => (Vec3::ZERO, Some(radial_gravity)),
+            GravityModel::UniformAndRadial(radial_gravity) => (gravity, Some(radial_gravity)),
+        }
+    }
+}
+
+/// The gravity between the particles, see [`Simulation::set_self_gravity`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelfGravity {
+    /// Acceleration towards a particle of unit mass at unit distance
+    pub gravitational_constant: f32,
+    /// Distance over which the pull between two particles is smoothed out, so that it stays finite
+    /// as they meet
+    ///
+    /// Contacts keep particles at least a diameter apart, so a particle radius or so is enough.
+    pub softening_length: f32,
+}
+
+impl SelfGravity {
+    /// Gravity of `gravitational_constant`, softened over a default particle radius.
+    pub fn new(gravitational_constant: f32) -> Self {
+        SelfGravity {
+            gravitational_constant,
+            softening_length: DEFAULT_PARTICLE_RADIUS,
+        }
+    }
+}
+
+/// How [`Simulation`] moves the particles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Phase {
+    /// Each particle behaves according to the [`crate::Material::phase`] of its material
+    #[default]
+    PerMaterial,
+    /// Every particle is a hard sphere that only interacts with the particles it touches
+    Granular,
+    /// Every particle is part of a fluid, simulated with smoothed particle hydrodynamics
+    Fluid,
+}
+
+impl Phase {
+    fn uniform(self) -> u32 {
+        match self {
+            Phase::PerMaterial => shader::constants::PHASE_PER_MATERIAL::VALUE,
+            Phase::Granular => PHASE_GRANULAR,
+            Phase::Fluid => PHASE_FLUID,
+        }
+    }
+}
+
+/// Steps the particles forward in time with Verlet integration, resolving contacts between
+/// neighbouring particles with a Jacobi position based solver. Particles are pushed out of the
+/// colliders of [`Resources`] along the gradient of their signed distance, and kept inside its
+/// container, if it has one. Besides gravity, which pulls them one way or towards a point according
+/// to the [`GravityModel`], they are accelerated by the [`ForceField`]s of the stage and, with
+/// [`SelfGravity`], by each other.
+///
+/// Fluid particles are instead pushed apart by pressure, with weakly compressible smoothed particle
+/// hydrodynamics over the same neighbour search, and also feel the viscosity and surface tension of
+/// their material. They still collide with granular particles.
+///
+/// The distance constraints of [`Resources`] are solved alongside the contacts, after breaking
+/// those that were stretched too far by the previous substep. At the end of each substep, the
+/// members of each rigid cluster are moved back onto its rest shape, at the rotation and
+/// translation that best fit them.
+///
+/// Every dispatch reads the current particle buffer of [`Resources`] and writes the other one, then
+/// swaps them, so that stepping with a fixed `delta_time` from the same initial particles produces
+/// bit-identical results on the same adapter.
 pub struct Simulation {
     bind_group_layout: BindGroupLayout,
-    simulate_compute_pipeline: ComputePipeline,
+    bind_groups: [BindGroup; 2],
+    resources_generation: u64,
+    break_constraints_compute_pipeline: ComputePipeline,
+    compute_density_compute_pipeline: ComputePipeline,
+    predict_compute_pipeline: ComputePipeline,
+    solve_contacts_compute_pipeline: ComputePipeline,
     uniform_buffer: Buffer,
-    pub particle_buffer: Buffer,
+    force_field_buffer: Buffer,
+    fluid_particle_buffer: Buffer,
+    // The dispatch arguments of the particle counters of `Resources`, copied at the start of each
+    // step as the particle buffers of `Resources` are swapped while the compute pass holds the
+    // arguments
+    indirect_buffer: Buffer,
+    shape_matching: ShapeMatching,
+    attraction: Attraction,
+    substeps: u32,
+    iterations: u32,
+    relaxation: f32,
+    neighbour_search: NeighbourSearch,
+    phase: Phase,
+    smoothing_length: f32,
+    force_fields: Vec<ForceField>,
+    self_gravity: Option<SelfGravity>,
+    gravity_model: GravityModel,
 }
 
 impl Drop for Simulation {
-    fn drop(&mut self) {}
+    fn drop(&mut self) {
+        self.uniform_buffer.destroy();
+        self.force_field_buffer.destroy();
+        self.fluid_particle_buffer.destroy();
+        self.indirect_buffer.destroy();
+    }
 }
 
 impl Simulation {
-    pub fn new(device: &Device) -> Self {
+    /// Creates the compute pipelines and binds the parameters, material, collider, container,
+    /// particle, bounds, grid, mass grid, constraint and cluster buffers of `resources`.
+    pub fn new(device: &Device, resources: &Resources) -> Result<Self, SolError> {
+        device.capture_errors(|| Self::create(device, resources))
+    }
+
+    fn create(device: &Device, resources: &Resources) -> Self {
         let shader_module = device.create_shader_module(ShaderModuleDescriptor {
             label: None,
             source: ShaderSource::Wgsl(Cow::Borrowed(shader::SOURCE)),
@@ -53,7 +275,7 @@ impl Simulation {
                     binding: shader::globals::particles::binding::BINDING,
                     visibility: ShaderStages::COMPUTE,
                     ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: false },
+                        ty: BufferBindingType::Storage { read_only: true },
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
@@ -81,6 +303,116 @@ impl Simulation {
                     },
                     count: None,
                 },
+                // Grid particles
+                BindGroupLayoutEntry {
+                    binding: shader::globals::grid_particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Parameters
+                BindGroupLayoutEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Next particles
+                BindGroupLayoutEntry {
+                    binding: shader::globals::next_particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Materials
+                BindGroupLayoutEntry {
+                    binding: shader::globals::materials::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Fluid particles
+                BindGroupLayoutEntry {
+                    binding: shader::globals::fluid_particles::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Constraints
+                BindGroupLayoutEntry {
+                    binding: shader::globals::constraints::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Particle constraints
+                BindGroupLayoutEntry {
+                    binding: shader::globals::particle_constraints::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Colliders
+                BindGroupLayoutEntry {
+                    binding: shader::globals::colliders::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Container
+                BindGroupLayoutEntry {
+                    binding: shader::globals::container::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Force fields
+                BindGroupLayoutEntry {
+                    binding: shader::globals::force_fields::binding::BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -90,12 +422,35 @@ impl Simulation {
             push_constant_ranges: &[],
         });
 
-        let simulate_compute_pipeline =
+        let break_constraints_compute_pipeline =
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: shader::entry_points::break_constraints::NAME,
+            });
+
+        let compute_density_compute_pipeline =
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: shader::entry_points::compute_density::NAME,
+            });
+
+        let predict_compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: shader::entry_points::predict::NAME,
+        });
+
+        let solve_contacts_compute_pipeline =
             device.create_compute_pipeline(&ComputePipelineDescriptor {
                 label: None,
                 layout: Some(&pipeline_layout),
                 module: &shader_module,
-                entry_point: shader::entry_points::simulate::NAME,
+                entry_point: shader::entry_points::solve_contacts::NAME,
             });
 
         let uniform_buffer: Buffer = device.create_buffer(&BufferDescriptor {
@@ -105,74 +460,448 @@ impl Simulation {
             mapped_at_creation: false,
         });
 
-        let particle_buffer = device.create_buffer(&BufferDescriptor {
-            size: Particle::SHADER_SIZE.get() * MAX_PARTICLES as u64,
+        let force_field_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Simulation::force_field_buffer"),
+            size: ForceField::SHADER_SIZE.get() * MAX_FORCE_FIELDS as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let fluid_particle_buffer = Self::create_fluid_particle_buffer(device, resources);
+
+        let indirect_buffer = device.create_buffer(&BufferDescriptor {
             label: None,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            size: ParticleCounters::SHADER_SIZE.get(),
+            usage: BufferUsages::INDIRECT | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            [&uniform_buffer, &force_field_buffer, &fluid_particle_buffer],
+            resources,
+        );
+
         Simulation {
             bind_group_layout,
-            simulate_compute_pipeline,
+            bind_groups,
+            resources_generation: resources.generation(),
+            break_constraints_compute_pipeline,
+            compute_density_compute_pipeline,
+            predict_compute_pipeline,
+            solve_contacts_compute_pipeline,
             uniform_buffer,
-            particle_buffer,
+            force_field_buffer,
+            fluid_particle_buffer,
+            indirect_buffer,
+            shape_matching: ShapeMatching::create(device, resources),
+            attraction: Attraction::create(device, resources),
+            substeps: DEFAULT_SUBSTEPS,
+            iterations: DEFAULT_ITERATIONS,
+            relaxation: DEFAULT_RELAXATION,
+            neighbour_search: NeighbourSearch::default(),
+            phase: Phase::default(),
+            smoothing_length: DEFAULT_SMOOTHING_LENGTH,
+            force_fields: Vec::new(),
+            self_gravity: None,
+            gravity_model: GravityModel::default(),
         }
     }
 
-    pub fn simulate(
-        &self,
+    /// The density and pressure of every particle, written at the start of each substep for fluid
+    /// particles.
+    fn create_fluid_particle_buffer(device: &Device, resources: &Resources) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("Simulation::fluid_particle_buffer"),
+            size: FluidParticle::SHADER_SIZE.get()
+                * resources.configuration().particle_capacity.max(1) as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// One bind group for each of the particle buffers of `resources`, which reads that buffer and
+    /// writes the other one.
+    fn create_bind_groups(
         device: &Device,
-        queue: &Queue,
-        bounds_buffer: &Buffer,
-        grid_buffer: &Buffer,
-        delta_time: f32,
-        gravity: Vec3,
-    ) {
-        let uniforms = Uniforms {
-            delta_time,
-            gravity,
-        };
-        let mut encased_uniform_buffer = UniformBuffer::new(Vec::<u8>::new());
-        encased_uniform_buffer.write(&uniforms).unwrap();
-        queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            &encased_uniform_buffer.into_inner(),
-        );
+        bind_group_layout: &BindGroupLayout,
+        stage_buffers: [&Buffer; 3],
+        resources: &Resources,
+    ) -> [BindGroup; 2] {
+        let [front, back] = resources.particle_buffers();
+        [(front, back), (back, front)].map(|(particle_buffer, next_particle_buffer)| {
+            Self::create_bind_group(
+                device,
+                bind_group_layout,
+                stage_buffers,
+                particle_buffer,
+                next_particle_buffer,
+                resources,
+            )
+        })
+    }
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    /// Binds `stage_buffers`, which are the uniform, force field and fluid particle buffers of the
+    /// stage.
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        [uniform_buffer, force_field_buffer, fluid_particle_buffer]: [&Buffer; 3],
+        particle_buffer: &Buffer,
+        next_particle_buffer: &Buffer,
+        resources: &Resources,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
             label: None,
-            layout: &self.bind_group_layout,
+            layout: bind_group_layout,
             entries: &[
-                wgpu::BindGroupEntry {
+                BindGroupEntry {
                     binding: shader::globals::uniforms::binding::BINDING,
-                    resource: self.uniform_buffer.as_entire_binding(),
+                    resource: uniform_buffer.as_entire_binding(),
                 },
-                wgpu::BindGroupEntry {
+                BindGroupEntry {
                     binding: shader::globals::particles::binding::BINDING,
-                    resource: self.particle_buffer.as_entire_binding(),
+                    resource: particle_buffer.as_entire_binding(),
                 },
-                wgpu::BindGroupEntry {
+                BindGroupEntry {
                     binding: shader::globals::bounds::binding::BINDING,
-                    resource: bounds_buffer.as_entire_binding(),
+                    resource: resources.bounds_buffer().as_entire_binding(),
                 },
-                wgpu::BindGroupEntry {
+                BindGroupEntry {
                     binding: shader::globals::grid::binding::BINDING,
-                    resource: grid_buffer.as_entire_binding(),
+                    resource: resources.grid_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::grid_particles::binding::BINDING,
+                    resource: resources.grid_particles_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::parameters::binding::BINDING,
+                    resource: resources.parameters_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::next_particles::binding::BINDING,
+                    resource: next_particle_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::materials::binding::BINDING,
+                    resource: resources.material_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::fluid_particles::binding::BINDING,
+                    resource: fluid_particle_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::constraints::binding::BINDING,
+                    resource: resources.constraint_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::particle_constraints::binding::BINDING,
+                    resource: resources.particle_constraints_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::colliders::binding::BINDING,
+                    resource: resources.collider_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::container::binding::BINDING,
+                    resource: resources.container_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: shader::globals::force_fields::binding::BINDING,
+                    resource: force_field_buffer.as_entire_binding(),
                 },
             ],
-        });
+        })
+    }
 
-        let mut encoder =
-            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    /// Rebuilds the bind groups if the buffers of `resources` have been reallocated since they were
+    /// created.
+    fn update_bind_groups(&mut self, device: &Device, resources: &Resources) {
+        if self.resources_generation != resources.generation() {
+            let fluid_particle_buffer = Self::create_fluid_particle_buffer(device, resources);
+            std::mem::replace(&mut self.fluid_particle_buffer, fluid_particle_buffer).destroy();
+            self.bind_groups = Self::create_bind_groups(
+                device,
+                &self.bind_group_layout,
+                [
+                    &self.uniform_buffer,
+                    &self.force_field_buffer,
+                    &self.fluid_particle_buffer,
+                ],
+                resources,
+            );
+            self.resources_generation = resources.generation();
+        }
+    }
+
+    pub fn substeps(&self) -> u32 {
+        self.substeps
+    }
+
+    /// Splits every step into `substeps` Verlet steps, each resolving contacts over
+    /// [`Self::iterations`] iterations.
+    ///
+    /// More substeps keep dense piles stable at the cost of more work per step.
+    pub fn set_substeps(&mut self, substeps: u32) {
+        assert!(substeps > 0, "a step needs at least one substep");
+        self.substeps = substeps;
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// Solves the contacts `iterations` times per substep.
+    ///
+    /// Every iteration only sees the positions left by the previous one, so corrections take an
+    /// iteration to propagate to the next particle in a pile, and tall piles need more iterations.
+    pub fn set_iterations(&mut self, iterations: u32) {
+        assert!(iterations > 0, "a substep needs at least one iteration");
+        self.iterations = iterations;
+    }
+
+    pub fn relaxation(&self) -> f32 {
+        self.relaxation
+    }
+
+    /// Scales the correction applied to each particle, which is the average of the corrections of
+    /// its contacts.
+    ///
+    /// Values above 1 converge faster but can overshoot, values below 1 are more stable but softer.
+    pub fn set_relaxation(&mut self, relaxation: f32) {
+        assert!(
+            relaxation > 0.0 && relaxation < 2.0,
+            "relaxation must be between 0 and 2"
+        );
+        self.relaxation = relaxation;
+    }
+
+    pub fn neighbour_search(&self) -> NeighbourSearch {
+        self.neighbour_search
+    }
+
+    pub fn set_neighbour_search(&mut self, neighbour_search: NeighbourSearch) {
+        self.neighbour_search = neighbour_search;
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Makes every particle granular or fluid, regardless of its material.
+    pub fn set_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+    }
+
+    pub fn smoothing_length(&self) -> f32 {
+        self.smoothing_length
+    }
+
+    /// Sets the distance within which fluid particles interact.
+    ///
+    /// Longer smoothing lengths give smoother fluids at the cost of visiting more neighbours. A few
+    /// particle radii is typical.
+    pub fn set_smoothing_length(&mut self, smoothing_length: f32) {
+        assert!(smoothing_length > 0.0, "smoothing length must be positive");
+        self.smoothing_length = smoothing_length;
+    }
+
+    pub fn force_fields(&self) -> &[ForceField] {
+        &self.force_fields
+    }
+
+    /// Replaces the force fields that accelerate the particles on top of gravity, from the next
+    /// step.
+    ///
+    /// There can be at most [`MAX_FORCE_FIELDS`] force fields, otherwise
+    /// [`SolError::InvalidConfiguration`] is returned.
+    pub fn set_force_fields(&mut self, force_fields: &[ForceField]) -> Result<(), SolError> {
+        if force_fields.len() > MAX_FORCE_FIELDS as usize {
+            return Err(SolError::InvalidConfiguration(format!(
+                "{} force fields exceed the maximum of {MAX_FORCE_FIELDS}",
+                force_fields.len()
+            )));
+        }
+        self.force_fields = force_fields.to_vec();
+        Ok(())
+    }
+
+    pub fn self_gravity(&self) -> Option<SelfGravity> {
+        self.self_gravity
+    }
+
+    /// Makes the particles pull each other together with `self_gravity`, on top of the uniform
+    /// gravity of each step, or stops them if it is `None`.
+    ///
+    /// Distant particles are pulled by the cells of the mass grid that [`crate::MassPartition`]
+    /// sums, which [`crate::FramePipeline`] only does while there is gravity between the particles.
+    pub fn set_self_gravity(&mut self, self_gravity: Option<SelfGravity>) {
+        if let Some(self_gravity) = self_gravity {
+            assert!(
+                self_gravity.gravitational_constant > 0.0,
+                "the gravitational constant must be positive"
+            );
+            assert!(
+                self_gravity.softening_length > 0.0,
+                "softening length must be positive"
+            );
+        }
+        self.self_gravity = self_gravity;
+    }
+
+    pub fn gravity_model(&self) -> GravityModel {
+        self.gravity_model
+    }
+
+    /// Chooses whether the particles are pulled along the gravity vector of each step, towards a
+    /// point, or both, from the next step.
+    pub fn set_gravity_model(&mut self, gravity_model: GravityModel) {
+        if let GravityModel::Radial(radial_gravity)
+        | GravityModel::UniformAndRadial(radial_gravity) = gravity_model
         {
-            let mut compute_pass =
-                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-            compute_pass.set_pipeline(&self.simulate_compute_pipeline);
-            compute_pass.set_bind_group(0, &bind_group, &[]);
-            compute_pass.dispatch_workgroups(MAX_PARTICLES, 1, 1);
+            assert!(
+                radial_gravity.radius > 0.0,
+                "radial gravity needs a positive radius"
+            );
+        }
+        self.gravity_model = gravity_model;
+    }
+
+    /// Records advancing the particles by `delta_time` seconds under `gravity`, as the gravity
+    /// model uses it, into `command_encoder`.
+    ///
+    /// The particle buffers of `resources` are swapped after every recorded dispatch, so stages
+    /// recorded afterwards read the particles that this step produces. The uniforms are written
+    /// through `queue`, so they take effect when `command_encoder` is next submitted.
+    pub fn simulate_with_encoder(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_encoder: &mut CommandEncoder,
+        resources: &mut Resources,
+        delta_time: f32,
+        gravity: Vec3,
+    ) {
+        self.update_bind_groups(device, resources);
+        self.shape_matching.prepare(device, queue, resources);
+        if let Some(self_gravity) = self.self_gravity {
+            self.attraction.prepare(
+                device,
+                queue,
+                resources,
+                self_gravity,
+                delta_time / self.substeps as f32,
+            );
         }
-        queue.submit(Some(encoder.finish()));
+
+        let (gravity, radial_gravity) = self.gravity_model.split(gravity);
+        let radial_gravity =
+            radial_gravity.unwrap_or(RadialGravity::inverse_square(Vec3::ZERO, 1.0, 0.0));
+        let uniforms = Uniforms {
+            delta_time,
+            gravity,
+            substeps: self.substeps,
+            neighbour_search: self.neighbour_search.uniform(),
+            relaxation: self.relaxation,
+            phase: self.phase.uniform(),
+            smoothing_length: self.smoothing_length,
+            constraint_count: resources.constraints().len() as u32,
+            collider_count: resources.colliders().len() as u32,
+            force_field_count: self.force_fields.len() as u32,
+            radial_gravity_centre: radial_gravity.centre,
+            radial_gravity_strength: radial_gravity.strength,
+            radial_gravity_radius: radial_gravity.radius,
+            radial_gravity_falloff: radial_gravity.falloff.uniform(),
+        };
+        queue.write_encased_uniform_buffer(&self.uniform_buffer, uniforms);
+        let mut force_field_table = [ForceField::zeroed(); MAX_FORCE_FIELDS as usize];
+        force_field_table[..self.force_fields.len()].copy_from_slice(&self.force_fields);
+        queue.write_encased_uniform_buffer(&self.force_field_buffer, force_field_table);
+
+        command_encoder.copy_buffer_to_buffer(
+            resources.particle_counters_buffer(),
+            0,
+            &self.indirect_buffer,
+            0,
+            ParticleCounters::SHADER_SIZE.get(),
+        );
+        let constraint_count = resources.constraints().len() as u32;
+        let constraint_workgroup_size = shader::entry_points::break_constraints::WORKGROUP_SIZE;
+        let constraint_workgroup_count =
+            (constraint_count as f32 / constraint_workgroup_size[0] as f32).ceil() as u32;
+        let mut compute_pass =
+            command_encoder.begin_compute_pass(&ComputePassDescriptor { label: None });
+        for _ in 0..self.substeps {
+            // Only read the particles, so the buffers are not swapped
+            compute_pass.set_bind_group(
+                0,
+                &self.bind_groups[resources.particle_buffer_index()],
+                &[],
+            );
+            if constraint_count > 0 {
+                compute_pass.set_pipeline(&self.break_constraints_compute_pipeline);
+                compute_pass.dispatch_workgroups(
+                    constraint_workgroup_count,
+                    constraint_workgroup_size[1],
+                    constraint_workgroup_size[2],
+                );
+            }
+            compute_pass.set_pipeline(&self.compute_density_compute_pipeline);
+            compute_pass.dispatch_workgroups_indirect(&self.indirect_buffer, 0);
+
+            compute_pass.set_pipeline(&self.predict_compute_pipeline);
+            compute_pass.set_bind_group(
+                0,
+                &self.bind_groups[resources.particle_buffer_index()],
+                &[],
+            );
+            compute_pass.dispatch_workgroups_indirect(&self.indirect_buffer, 0);
+            resources.swap_particle_buffers();
+            if self.self_gravity.is_some() {
+                self.attraction
+                    .attract(&mut compute_pass, resources, &self.indirect_buffer);
+            }
+
+            compute_pass.set_pipeline(&self.solve_contacts_compute_pipeline);
+            for _ in 0..self.iterations {
+                compute_pass.set_bind_group(
+                    0,
+                    &self.bind_groups[resources.particle_buffer_index()],
+                    &[],
+                );
+                compute_pass.dispatch_workgroups_indirect(&self.indirect_buffer, 0);
+                resources.swap_particle_buffers();
+            }
+            self.shape_matching
+                .match_clusters(&mut compute_pass, resources, &self.indirect_buffer);
+        }
+    }
+
+    /// Advances the particles by `delta_time` seconds under `gravity` and submits the work to
+    /// `queue`.
+    pub fn simulate(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        resources: &mut Resources,
+        delta_time: f32,
+        gravity: Vec3,
+    ) -> Result<(), SolError> {
+        let command_buffer = device.capture_errors(|| {
+            let mut command_encoder =
+                device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            self.simulate_with_encoder(
+                device,
+                queue,
+                &mut command_encoder,
+                resources,
+                delta_time,
+                gravity,
+            );
+            command_encoder.finish()
+        })?;
+        submit(device, queue, command_buffer)
     }
 }
@@ -1,19 +1,77 @@
 #import ../common.wgsl as Common
+#import sph.wgsl as Sph
+#import ../sdf.wgsl as Sdf
+#import force_field.wgsl as Force
 
 const EPSILON = .1;
+// Contacts that penetrate by less than this are not pushed apart, as contacts resolved by an
+// earlier iteration sit at the contact distance give or take rounding, and would otherwise dilute
+// the average correction. They still have friction, as resting contacts only sink by a fraction of
+// this each substep and would otherwise slide freely
+const CONTACT_SLOP = .001;
+// Step used to take the gradient of the colliders by central differences
+const GRADIENT_STEP = .01;
+
+const NEIGHBOUR_SEARCH_GRID = 0u;
+const NEIGHBOUR_SEARCH_BRUTE_FORCE = 1u;
+
+// Uses the phase of the material of each particle rather than a single phase for every particle
+const PHASE_PER_MATERIAL = 4294967295u;
+
+// How the radial gravity weakens beyond its radius
+const RADIAL_FALLOFF_INVERSE_SQUARE = 0u;
+const RADIAL_FALLOFF_CONSTANT = 1u;
+
+// What `sum_fluid` sums over the fluid neighbours of a particle
+const FLUID_DENSITY = 0u;
+const FLUID_ACCELERATION = 1u;
 
 @export struct Uniforms {
     delta_time: f32,
     gravity: vec3<f32>,
+    // Number of Verlet steps that `delta_time` is split into
+    substeps: u32,
+    // One of the `NEIGHBOUR_SEARCH_*` constants
+    neighbour_search: u32,
+    // Scale applied to the averaged contact corrections of each iteration
+    relaxation: f32,
+    // One of the `Common::PHASE_*` constants, or `PHASE_PER_MATERIAL`
+    phase: u32,
+    // Distance within which fluid particles interact
+    smoothing_length: f32,
+    // Number of constraints in `constraints`
+    constraint_count: u32,
+    // Number of colliders in use at the start of `colliders`
+    collider_count: u32,
+    // Number of force fields in use at the start of `force_fields`
+    force_field_count: u32,
+    // Point that the radial gravity pulls towards
+    radial_gravity_centre: vec3<f32>,
+    // Acceleration towards `radial_gravity_centre` at `radial_gravity_radius` from it, 0 for none
+    radial_gravity_strength: f32,
+    // Radius of the body at the centre of the radial gravity, within which it weakens towards the
+    // centre
+    radial_gravity_radius: f32,
+    // One of the `RADIAL_FALLOFF_*` constants
+    radial_gravity_falloff: u32,
+}
+
+// The density of a fluid particle and the pressure that it results in, computed at the start of
+// each substep
+@export struct FluidParticle {
+    density: f32,
+    pressure: f32,
 }
 
 @group(0)
 @binding(0)
 var<uniform> uniforms: Uniforms;
 
+// Every kernel reads the current particles and writes `next_particles`, which become current once
+// the dispatch has finished, so that no invocation reads a particle that another is writing
 @group(0)
 @binding(1)
-var<storage, read_write> particles: array<Common::Particle>;
+var<storage, read> particles: array<Common::Particle>;
 
 @group(0)
 @binding(2)
@@ -23,119 +81,523 @@ var<storage, read> bounds: Common::Bounds;
 @binding(3)
 var<storage, read> grid: array<Common::GridCell>;
 
-const ITERATIONS = 2u;
+@group(0)
+@binding(4)
+var<storage, read> grid_particles: array<u32>;
+
+@group(0)
+@binding(5)
+var<uniform> parameters: Common::Parameters;
+
+@group(0)
+@binding(6)
+var<storage, read_write> next_particles: array<Common::Particle>;
+
+@group(0)
+@binding(7)
+var<uniform> materials: array<Common::Material, Common::MAX_MATERIALS>;
+
+// Only written and read for fluid particles
+@group(0)
+@binding(8)
+var<storage, read_write> fluid_particles: array<FluidParticle>;
+
+@group(0)
+@binding(9)
+var<storage, read_write> constraints: array<Common::DistanceConstraint>;
+
+@group(0)
+@binding(10)
+var<storage, read> particle_constraints: array<Common::ParticleConstraints>;
+
+@group(0)
+@binding(11)
+var<uniform> colliders: array<Sdf::Collider, Sdf::MAX_COLLIDERS>;
+
+@group(0)
+@binding(12)
+var<uniform> container: Common::Container;
+
+@group(0)
+@binding(13)
+var<uniform> force_fields: array<Force::ForceField, Force::MAX_FORCE_FIELDS>;
+
+// Breaks the constraints that have been stretched beyond their break strain, or that hold a deleted
+// particle, at the start of each substep
+@compute
+@workgroup_size(64)
+fn break_constraints(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let constraint_index = global_invocation_id.x;
+    if (constraint_index >= uniforms.constraint_count) {
+        return;
+    }
+    let constraint = constraints[constraint_index];
+    if (constraint.broken != 0u) {
+        return;
+    }
+    if (particles[constraint.a].alive == 0u || particles[constraint.b].alive == 0u) {
+        constraints[constraint_index].broken = 1u;
+        return;
+    }
+    if (constraint.break_strain <= 0.0) {
+        return;
+    }
+    let stretched_length = distance(particles[constraint.a].position, particles[constraint.b].position);
+    if (stretched_length > constraint.rest_length * (1.0 + constraint.break_strain)) {
+        constraints[constraint_index].broken = 1u;
+    }
+}
 
+// Sums the density of each fluid particle from its neighbours, weakly compressible, so that
+// pressure pushes it back towards the rest density of its material
 @compute
-@workgroup_size(1)
-fn simulate(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
-    // TODO: Use per-particle or per-material properties
-    let mass = 1.0;
-    let frictional_coefficient = 0.5;
+@workgroup_size(64)
+fn compute_density(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particle_index = global_invocation_id.x;
+    if (particle_index >= parameters.particle_count) {
+        return;
+    }
+    let particle = particles[particle_index];
+    if (particle.alive == 0u || !is_fluid(particle)) {
+        return;
+    }
+    let material = read_material(particle.material);
+
+    let density = sum_fluid(particle_index, particle, FLUID_DENSITY).density;
+    // Negative pressure would clump particles together, which surface tension does instead
+    let pressure = max(material.stiffness * (density - material.density), 0.0);
+    fluid_particles[particle_index] = FluidParticle(density, pressure);
+}
 
+// Advances a particle by one substep with Verlet integration, ignoring contacts, which
+// `solve_contacts` then resolves, including their friction. Particles are slowed by the air drag of
+// their material and accelerated by the force fields, and fluid particles are also accelerated by
+// the pressure, viscosity and surface tension of their fluid neighbours. The gravity between the
+// particles is added afterwards by `attraction.wgsl::attract`
+@compute
+@workgroup_size(64)
+fn predict(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
     let particle_index = global_invocation_id.x;
-    var previous_position = particles[particle_index].old_position;
-    var current_position = particles[particle_index].position;
+    if (particle_index >= parameters.particle_count) {
+        return;
+    }
+    let particle = particles[particle_index];
+    if (particle.alive == 0u) {
+        next_particles[particle_index] = particle;
+        return;
+    }
+    let material = read_material(particle.material);
 
-    let delta_time = uniforms.delta_time / f32(ITERATIONS);
+    let delta_time = substep_delta_time();
     let delta_time_squared = delta_time * delta_time;
 
-    let bounds_min = vec3<f32>(vec3<i32>(bounds.min_x, bounds.min_y, bounds.min_z));
-    let bounds_max = vec3<f32>(vec3<i32>(bounds.max_x, bounds.max_y, bounds.max_z));
-    for (var i = 0u; i < ITERATIONS; i++) {
-        // Solve inter-particle collision
-        for (var i = 1u; i < Common::MAX_PARTICLES; i++) {
-            if (i == particle_index) {
-                continue; // Skip self-collision
-            }
-            current_position = solve_collision(
-                current_position,
-                particles[i].position,
-                // TODO: Replace this with actual particle radius rather than constant
-                Common::PARTICLE_RADIUS + Common::PARTICLE_RADIUS,
-            );
-        }
+    let velocity = particle.position - particle.old_position;
+    let gravitational_force = uniforms.gravity * particle.mass;
+    let drag_force = -velocity / delta_time * material.drag;
+    var acceleration = (gravitational_force + drag_force) / particle.mass;
+    if (uniforms.radial_gravity_strength != 0.0) {
+        acceleration += radial_gravity(particle.position);
+    }
+    for (var i = 0u; i < uniforms.force_field_count; i++) {
+        acceleration += Force::acceleration(force_fields[i], particle.position, velocity / delta_time, delta_time);
+    }
+    if (is_fluid(particle)) {
+        acceleration += sum_fluid(particle_index, particle, FLUID_ACCELERATION).acceleration;
+    }
 
-        let velocity = (current_position - previous_position);
-        let gravitational_force = uniforms.gravity * mass;
-        let frictional_force = -velocity / delta_time * frictional_coefficient;
-        let acceleration = (gravitational_force + frictional_force) / mass;
-
-        // Apply verlet integration
-        let next_position = current_position + velocity + acceleration * delta_time_squared;
-        previous_position = current_position;
-        current_position = next_position;
-
-        // Solve bounding box collision
-        if (current_position.x < bounds_min.x) {
-            current_position.x = bounds_min.x;
-        } else if (current_position.x > bounds_max.x) {
-            current_position.x = bounds_max.x;
-        }
-        if (current_position.y < bounds_min.y) {
-            current_position.y = bounds_min.y;
-        } else if (current_position.y > bounds_max.y) {
-            current_position.y = bounds_max.y;
+    var next_particle = particle;
+    next_particle.old_position = particle.position;
+    next_particle.position = particle.position + velocity + acceleration * delta_time_squared;
+    next_particles[particle_index] = next_particle;
+}
+
+// The pull towards the centre of the radial gravity at `position`. Within its radius the pull
+// weakens linearly to nothing at the centre, as within a ball of uniform density, so that it stays
+// finite
+fn radial_gravity(position: vec3<f32>) -> vec3<f32> {
+    let offset = uniforms.radial_gravity_centre - position;
+    let distance = length(offset);
+    if (distance <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let radius = uniforms.radial_gravity_radius;
+    var strength = uniforms.radial_gravity_strength;
+    if (distance < radius) {
+        strength *= distance / radius;
+    } else if (uniforms.radial_gravity_falloff == RADIAL_FALLOFF_INVERSE_SQUARE) {
+        strength *= radius * radius / (distance * distance);
+    }
+    return offset / distance * strength;
+}
+
+// One Jacobi iteration: every contact and constraint of a particle is solved against the positions
+// from the start of the iteration, and the corrections are averaged so that particles with many
+// contacts do not overshoot
+@compute
+@workgroup_size(64)
+fn solve_contacts(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let particle_index = global_invocation_id.x;
+    if (particle_index >= parameters.particle_count) {
+        return;
+    }
+    let particle = particles[particle_index];
+    if (particle.alive == 0u) {
+        next_particles[particle_index] = particle;
+        return;
+    }
+
+    var contacts: Contacts;
+    if (uniforms.neighbour_search == NEIGHBOUR_SEARCH_BRUTE_FORCE) {
+        contacts = solve_contacts_brute_force(particle_index, particle);
+    } else {
+        contacts = solve_contacts_grid(particle_index, particle);
+    }
+    contacts = solve_constraints(contacts, particle_index, particle);
+
+    var position = particle.position;
+    var old_position = particle.old_position;
+    let scale = uniforms.relaxation / f32(max(contacts.count, 1u));
+    position += (contacts.correction + contacts.friction_correction) * scale;
+    // Moving the previous position away from the contact turns part of the correction into velocity
+    old_position -= contacts.restitution_correction * scale;
+
+    position = solve_colliders(position, old_position, particle);
+    position = solve_container(position, old_position, particle);
+
+    var next_particle = particle;
+    next_particle.position = position;
+    next_particle.old_position = old_position;
+    next_particles[particle_index] = next_particle;
+}
+
+// Keeps a particle inside the container, with the walls of the container as static walls made of
+// the particle's material
+fn solve_container(position: vec3<f32>, old_position: vec3<f32>, particle: Common::Particle) -> vec3<f32> {
+    if (container.enabled == 0u) {
+        return position;
+    }
+    let clamped_position = clamp_to_container(position, particle.radius);
+    let push = clamped_position - position;
+    let penetration = length(push);
+    if (penetration <= 0.0) {
+        return position;
+    }
+    let material = read_material(particle.material);
+    let friction = coulomb_friction(
+        clamped_position - old_position,
+        push / penetration,
+        penetration,
+        material.static_friction,
+        material.kinetic_friction,
+    );
+    // Friction along an edge or corner is not parallel to every wall it touches, so clamp again
+    return clamp_to_container(clamped_position - friction, particle.radius);
+}
+
+// The closest position to `position` at which a particle of `radius` is inside the container
+fn clamp_to_container(position: vec3<f32>, radius: f32) -> vec3<f32> {
+    let half_size = max(container.half_size - radius, vec3<f32>(0.0));
+    let local_position = Sdf::rotate_inverse(container.rotation, position - container.centre);
+    return container.centre + Sdf::rotate(container.rotation, clamp(local_position, -half_size, half_size));
+}
+
+// Pushes a particle out of the colliders along the gradient of their combined distance, with the
+// colliders as static walls made of the particle's material, like the container
+fn solve_colliders(position: vec3<f32>, old_position: vec3<f32>, particle: Common::Particle) -> vec3<f32> {
+    if (uniforms.collider_count == 0u) {
+        return position;
+    }
+    let penetration = particle.radius - colliders_distance(position);
+    if (penetration <= 0.0) {
+        return position;
+    }
+    let gradient = colliders_gradient(position);
+    let gradient_length = length(gradient);
+    if (gradient_length <= 0.0) {
+        return position;
+    }
+    let normal = gradient / gradient_length;
+    let pushed_position = position + normal * penetration;
+    let material = read_material(particle.material);
+    let friction = coulomb_friction(
+        pushed_position - old_position,
+        normal,
+        penetration,
+        material.static_friction,
+        material.kinetic_friction,
+    );
+    return pushed_position - friction;
+}
+
+// The distance to the surface of the colliders, combined in order
+fn colliders_distance(position: vec3<f32>) -> f32 {
+    var scene_distance = Sdf::EMPTY_DISTANCE;
+    for (var i = 0u; i < uniforms.collider_count; i++) {
+        let collider = colliders[i];
+        scene_distance = Sdf::combine_collider(collider, scene_distance, Sdf::collider_distance(collider, position));
+    }
+    return scene_distance;
+}
+
+// Points away from the colliders, by central differences as CSG combinations have no analytic
+// gradient
+fn colliders_gradient(position: vec3<f32>) -> vec3<f32> {
+    let step = vec2<f32>(GRADIENT_STEP, 0.0);
+    return vec3<f32>(
+        colliders_distance(position + step.xyy) - colliders_distance(position - step.xyy),
+        colliders_distance(position + step.yxy) - colliders_distance(position - step.yxy),
+        colliders_distance(position + step.yyx) - colliders_distance(position - step.yyx),
+    );
+}
+
+fn substep_delta_time() -> f32 {
+    return uniforms.delta_time / f32(uniforms.substeps);
+}
+
+// Indices beyond the table use its last entry
+fn read_material(material_index: u32) -> Common::Material {
+    return materials[min(material_index, Common::MAX_MATERIALS - 1u)];
+}
+
+fn is_fluid(particle: Common::Particle) -> bool {
+    if (uniforms.phase != PHASE_PER_MATERIAL) {
+        return uniforms.phase == Common::PHASE_FLUID;
+    }
+    return read_material(particle.material).phase == Common::PHASE_FLUID;
+}
+
+// The sum of either the density or the acceleration that the fluid neighbours of a particle
+// contribute
+struct FluidSum {
+    density: f32,
+    acceleration: vec3<f32>,
+}
+
+// Visits every fluid particle within the smoothing length, including the particle itself
+fn sum_fluid(particle_index: u32, particle: Common::Particle, quantity: u32) -> FluidSum {
+    var sum: FluidSum;
+    if (uniforms.neighbour_search == NEIGHBOUR_SEARCH_BRUTE_FORCE) {
+        for (var i = 0u; i < parameters.particle_count; i++) {
+            sum = add_fluid_neighbour(sum, quantity, particle_index, particle, i, particles[i]);
         }
-        if (current_position.z < bounds_min.z) {
-            current_position.z = bounds_min.z;
-        } else if (current_position.z > bounds_max.z) {
-            current_position.z = bounds_max.z;
+        return sum;
+    }
+
+    // Deduplicated in the same way as `solve_contacts_grid`, but over the cells within the
+    // smoothing length
+    let range = Common::world_sphere_to_grid_range(particle.position, uniforms.smoothing_length, bounds, parameters);
+    var grid_position = vec3<i32>();
+    for (grid_position.x = range.min.x; grid_position.x <= range.max.x; grid_position.x++) {
+        for (grid_position.y = range.min.y; grid_position.y <= range.max.y; grid_position.y++) {
+            for (grid_position.z = range.min.z; grid_position.z <= range.max.z; grid_position.z++) {
+                let grid_index = Common::grid_position_to_grid_index(grid_position, parameters);
+                let particles_length = min(grid[grid_index].particles_length, parameters.max_particles_per_grid_cell);
+                for (var i = 0u; i < particles_length; i++) {
+                    let neighbour_index = grid_particles[Common::grid_particle_index(grid_index, i, parameters)];
+                    let neighbour = particles[neighbour_index];
+                    let neighbour_range = Common::world_sphere_to_grid_range(neighbour.position, neighbour.radius, bounds, parameters);
+                    if (any(max(range.min, neighbour_range.min) != grid_position)) {
+                        continue; // Visited in another cell
+                    }
+                    sum = add_fluid_neighbour(sum, quantity, particle_index, particle, neighbour_index, neighbour);
+                }
+            }
         }
     }
+    return sum;
+}
+
+fn add_fluid_neighbour(
+    sum: FluidSum,
+    quantity: u32,
+    particle_index: u32,
+    particle: Common::Particle,
+    neighbour_index: u32,
+    neighbour: Common::Particle,
+) -> FluidSum {
+    let h = uniforms.smoothing_length;
+    let direction = particle.position - neighbour.position;
+    let distance = length(direction);
+    if (distance >= h || neighbour.alive == 0u || !is_fluid(neighbour)) {
+        return sum;
+    }
+
+    var adjusted_sum = sum;
+    if (quantity == FLUID_DENSITY) {
+        adjusted_sum.density += neighbour.mass * Sph::poly6(distance, h);
+        return adjusted_sum;
+    }
+
+    let material = read_material(particle.material);
+    let fluid_particle = fluid_particles[particle_index];
+    let fluid_neighbour = fluid_particles[neighbour_index];
+
+    // Pressure pushes the particles apart, symmetrically so that momentum is conserved
+    if (distance > EPSILON) {
+        let pressure = fluid_particle.pressure / (fluid_particle.density * fluid_particle.density)
+            + fluid_neighbour.pressure / (fluid_neighbour.density * fluid_neighbour.density);
+        adjusted_sum.acceleration += direction / distance * neighbour.mass * pressure * Sph::spiky_gradient(distance, h);
+    }
 
-    particles[particle_index].old_position = previous_position;
-    particles[particle_index].position = current_position;
+    // Viscosity pulls the velocity of the particle towards that of its neighbours
+    let delta_time = substep_delta_time();
+    let velocity = (particle.position - particle.old_position) / delta_time;
+    let neighbour_velocity = (neighbour.position - neighbour.old_position) / delta_time;
+    adjusted_sum.acceleration += material.viscosity * neighbour.mass * (neighbour_velocity - velocity)
+        / (fluid_neighbour.density * fluid_particle.density) * Sph::viscosity_laplacian(distance, h);
+
+    // Surface tension pulls the particles together, after Becker and Teschner 2007
+    adjusted_sum.acceleration -= material.surface_tension / particle.mass * neighbour.mass * direction * Sph::poly6(distance, h);
+    return adjusted_sum;
 }
 
-// fn integrate(particle_index: u32, position: vec3<v32>) {
+// The sum of the corrections of every contact of a particle
+struct Contacts {
+    correction: vec3<f32>,
+    // The part of `correction` that is turned into velocity
+    restitution_correction: vec3<f32>,
+    count: u32,
+    // Also averaged over `count`, even though contacts within the slop have friction, as whether a
+    // resolved contact touches is down to rounding, while the friction that it adds shrinks along
+    // with its penetration
+    friction_correction: vec3<f32>,
+}
 
-// }
+fn solve_contacts_brute_force(particle_index: u32, particle: Common::Particle) -> Contacts {
+    var contacts: Contacts;
+    for (var i = 0u; i < parameters.particle_count; i++) {
+        if (i == particle_index || particles[i].alive == 0u) {
+            continue; // Skip self-collision and the free slots
+        }
+        contacts = solve_contact(contacts, particle, particles[i]);
+    }
+    return contacts;
+}
 
-fn process_particle_collisions(particle_index: u32, position: vec3<f32>) -> vec3<f32> {
-    var processed_position = position;
-    let bounds_min = vec3<f32>(vec3<i32>(bounds.min_x, bounds.min_y, bounds.min_z));
-    let bounds_max = vec3<f32>(vec3<i32>(bounds.max_x, bounds.max_y, bounds.max_z));
+// Only visits the cells that the particle overlaps, which contain every particle it could be
+// touching. Particles that overlap several of those cells are only resolved in the first cell that
+// both particles overlap, so that each contact is resolved once
+fn solve_contacts_grid(particle_index: u32, particle: Common::Particle) -> Contacts {
+    var contacts: Contacts;
+    let range = Common::world_sphere_to_grid_range(particle.position, particle.radius, bounds, parameters);
+    var grid_position = vec3<i32>();
+    for (grid_position.x = range.min.x; grid_position.x <= range.max.x; grid_position.x++) {
+        for (grid_position.y = range.min.y; grid_position.y <= range.max.y; grid_position.y++) {
+            for (grid_position.z = range.min.z; grid_position.z <= range.max.z; grid_position.z++) {
+                let grid_index = Common::grid_position_to_grid_index(grid_position, parameters);
+                let particles_length = min(grid[grid_index].particles_length, parameters.max_particles_per_grid_cell);
+                for (var i = 0u; i < particles_length; i++) {
+                    let neighbour_index = grid_particles[Common::grid_particle_index(grid_index, i, parameters)];
+                    if (neighbour_index == particle_index) {
+                        continue; // Skip self-collision
+                    }
+                    let neighbour = particles[neighbour_index];
+                    let neighbour_range = Common::world_sphere_to_grid_range(neighbour.position, neighbour.radius, bounds, parameters);
+                    if (any(max(range.min, neighbour_range.min) != grid_position)) {
+                        continue; // Resolved in another cell
+                    }
+                    contacts = solve_contact(contacts, particle, neighbour);
+                }
+            }
+        }
+    }
+    return contacts;
+}
 
-    // Process inter-particle collision with neighbouring particles
-    // let grid_index = Common::world_position_to_grid_index(processed_position, bounds);
-    // let particles_length = grid[grid_index].particles_length;
-    // for (var i = 1u; i < particles_length; i++) {
-    //     if (i == particle_index){
-    //         continue; // Skip self-collision
-    //     } 
+// Adds the corrections that restore the rest lengths of the unbroken constraints of a particle to
+// `contacts`, moving it by the share of the error that corresponds to the mass of the other
+// particle
+fn solve_constraints(contacts: Contacts, particle_index: u32, particle: Common::Particle) -> Contacts {
+    var adjusted_contacts = contacts;
+    let count = min(particle_constraints[particle_index].count, Common::MAX_CONSTRAINTS_PER_PARTICLE);
+    for (var i = 0u; i < count; i++) {
+        let constraint = constraints[particle_constraints[particle_index].constraints[i]];
+        if (constraint.broken != 0u) {
+            continue;
+        }
+        var neighbour_index = constraint.a;
+        if (neighbour_index == particle_index) {
+            neighbour_index = constraint.b;
+        }
+        let neighbour = particles[neighbour_index];
+        let direction = neighbour.position - particle.position;
+        let distance = length(direction);
+        if (distance <= EPSILON) {
+            continue;
+        }
+        let weight = neighbour.mass / (particle.mass + neighbour.mass);
+        adjusted_contacts.correction += direction / distance * (distance - constraint.rest_length) * weight * constraint.stiffness;
+        adjusted_contacts.count++;
+    }
+    return adjusted_contacts;
+}
 
-    //     var neighbouring_particle_position = particles[i].position;
-    //     processed_position = solve_collision(
-    //         processed_position,
-    //         neighbouring_particle_position,
-    //         // TODO: Replace this with actual particle radius
-    //         PARTICLE_RADIUS + PARTICLE_RADIUS,
-    //     );
-    // }
-    return processed_position;
+// The bouncier of the two materials wins
+fn combine_restitution(a: Common::Material, b: Common::Material) -> f32 {
+    return max(a.restitution, b.restitution);
 }
 
-// fn solve_verlet_integration(current_position: vec3<f32>, previous_position: vec3<f32>, delta_time_squared: f32) -> vec3<f32> {
-//     let velocity = (current_position - previous_position);
-//     return current_position + velocity + acceleration * delta_time_squared;
-// }
+// Combines the friction coefficients of two materials, such that a frictionless material never
+// sticks
+fn combine_friction(a: f32, b: f32) -> f32 {
+    return sqrt(a * b);
+}
 
-fn solve_collision(
-    position: vec3<f32>,
-    collision_position: vec3<f32>,
-    radius: f32,
+// The part of the `displacement` of a contact over the substep that its friction undoes: all of its
+// tangential motion while that is small enough for the contact to stick, and otherwise a share that
+// is proportional to the `penetration` along `normal`, from Macklin et al. 2014, "Unified Particle
+// Physics for Real-Time Applications"
+fn coulomb_friction(
+    displacement: vec3<f32>,
+    normal: vec3<f32>,
+    penetration: f32,
+    static_friction: f32,
+    kinetic_friction: f32,
 ) -> vec3<f32> {
-    // TODO: Use per-particle or per-material restitution factor?
-    let restitution = 0.0; // Coefficient of restitution (bounciness)
-    var adjusted_position = position;
-    let direction = collision_position - position;
+    let tangential_displacement = displacement - dot(displacement, normal) * normal;
+    let tangential_distance = length(tangential_displacement);
+    if (tangential_distance < static_friction * penetration) {
+        return tangential_displacement;
+    }
+    if (tangential_distance <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    return tangential_displacement * min(kinetic_friction * penetration / tangential_distance, 1.0);
+}
+
+// Adds the correction that separates `particle` from `neighbour` to `contacts`, moving `particle`
+// by the share of the penetration that corresponds to the mass of `neighbour`. Friction resists the
+// motion of the particles relative to each other across the contact, over the substep so far
+fn solve_contact(contacts: Contacts, particle: Common::Particle, neighbour: Common::Particle) -> Contacts {
+    if (is_fluid(particle) && is_fluid(neighbour)) {
+        return contacts; // Fluid particles only push each other apart with pressure
+    }
+    let direction = neighbour.position - particle.position;
     let distance = length(direction);
-    let min_distance = radius;
-    if (distance < min_distance && distance > EPSILON) {
-        let normal = normalize(direction);
-        let penetration = (min_distance - distance) * normal;
-        // 0.125 should be 0.5 but it seems to be more stable :sweat: 
-        adjusted_position -= (penetration * 1.0 + restitution) * 0.125;
-    }
-    return adjusted_position;
-}
\ No newline at end of file
+    let min_distance = particle.radius + neighbour.radius;
+    if (distance >= min_distance || distance <= EPSILON) {
+        return contacts;
+    }
+    var adjusted_contacts = contacts;
+    let normal = direction / distance;
+    let penetration = min_distance - distance;
+    let weight = neighbour.mass / (particle.mass + neighbour.mass);
+    let material = read_material(particle.material);
+    let neighbour_material = read_material(neighbour.material);
+    if (penetration > CONTACT_SLOP) {
+        let correction = -normal * penetration * weight;
+        adjusted_contacts.correction += correction;
+        adjusted_contacts.restitution_correction += correction * combine_restitution(material, neighbour_material);
+        adjusted_contacts.count++;
+    }
+    let relative_displacement = (particle.position - particle.old_position) - (neighbour.position - neighbour.old_position);
+    let friction = coulomb_friction(
+        relative_displacement,
+        normal,
+        penetration,
+        combine_friction(material.static_friction, neighbour_material.static_friction),
+        combine_friction(material.kinetic_friction, neighbour_material.kinetic_friction),
+    );
+    adjusted_contacts.friction_correction -= friction * weight;
+    return adjusted_contacts;
+}
@@ -0,0 +1,37 @@
+// Smoothing kernels for smoothed particle hydrodynamics, from Müller et al. 2003, "Particle-Based
+// Fluid Simulation for Interactive Applications". Each is zero at and beyond the smoothing length
+// `h`
+
+// 315 / (64 pi) and 45 / pi
+const POLY6_COEFFICIENT = 1.5666815;
+const SPIKY_COEFFICIENT = 14.323945;
+
+// Weights the density that a neighbour at `distance` contributes
+fn poly6(distance: f32, h: f32) -> f32 {
+    if (distance >= h) {
+        return 0.0;
+    }
+    let h3 = h * h * h;
+    let x = h * h - distance * distance;
+    return POLY6_COEFFICIENT / (h3 * h3 * h3) * x * x * x;
+}
+
+// The magnitude of the gradient of the spiky kernel, which pushes neighbours apart under pressure
+// without vanishing as they get close
+fn spiky_gradient(distance: f32, h: f32) -> f32 {
+    if (distance >= h) {
+        return 0.0;
+    }
+    let h3 = h * h * h;
+    let x = h - distance;
+    return SPIKY_COEFFICIENT / (h3 * h3) * x * x;
+}
+
+// The laplacian of the viscosity kernel, which smooths the velocities of neighbours
+fn viscosity_laplacian(distance: f32, h: f32) -> f32 {
+    if (distance >= h) {
+        return 0.0;
+    }
+    let h3 = h * h * h;
+    return SPIKY_COEFFICIENT / (h3 * h3) * (h - distance);
+}
@@ -0,0 +1,7 @@
+//! Generation of initial particle states, and of the constraints and clusters that join particles
+//! into bodies.
+mod spawn;
+pub use spawn::{
+    assign_material, cloth_constraints, mix_coarse_grains, random_particles, rope_constraints,
+    seeded_rng, soft_body_constraints, Lattice, RigidBody, Shape,
+};
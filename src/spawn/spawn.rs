@@ -0,0 +1,245 @@
+use crate::common::{
+    Cluster, DistanceConstraint, Material, Particle, DEFAULT_PARTICLE_MASS, DEFAULT_PARTICLE_RADIUS,
+};
+use glam::{IVec3, UVec3, Vec3};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// A random number generator whose output only depends on `seed`, on every platform and release.
+pub fn seeded_rng(seed: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+/// `count` resting particles of the default radius and mass scattered uniformly within a cube of
+/// `half_extent` around the origin.
+pub fn random_particles<R: Rng>(rng: &mut R, count: u32, half_extent: f32) -> Vec<Particle> {
+    (0..count)
+        .map(|_| {
+            let position = Vec3::new(
+                rng.gen_range(-half_extent..half_extent),
+                rng.gen_range(-half_extent..half_extent),
+                rng.gen_range(-half_extent..half_extent),
+            );
+            Particle::new(position, DEFAULT_PARTICLE_RADIUS, DEFAULT_PARTICLE_MASS)
+        })
+        .collect()
+}
+
+/// Replaces roughly `fraction` of `particles` with coarse grains of `radius` made of the material
+/// at `material_index`, whose mass is derived from the density of `material`.
+pub fn mix_coarse_grains<R: Rng>(
+    rng: &mut R,
+    particles: &mut [Particle],
+    fraction: f32,
+    radius: f32,
+    material_index: u32,
+    material: &Material,
+) {
+    for particle in particles {
+        if rng.gen::<f32>() < fraction {
+            particle.radius = radius;
+            assign_material(std::slice::from_mut(particle), material_index, material);
+        }
+    }
+}
+
+/// Makes `particles` out of the material at `material_index`, deriving their mass from their radius
+/// and the density of `material`.
+pub fn assign_material(particles: &mut [Particle], material_index: u32, material: &Material) {
+    for particle in particles {
+        particle.material = material_index;
+        particle.mass = material.particle_mass(particle.radius);
+    }
+}
+
+/// Points on a regular grid, which the constraint helpers connect into ropes, cloth and soft
+/// bodies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lattice {
+    /// Position of the first point
+    pub origin: Vec3,
+    /// Number of points along each axis
+    pub size: UVec3,
+    /// Distance between neighbouring points
+    pub spacing: f32,
+}
+
+impl Lattice {
+    pub fn point_count(&self) -> u32 {
+        self.size.x * self.size.y * self.size.z
+    }
+
+    /// Index of the point at `position`, counting along x, then y, then z.
+    pub fn index(&self, position: UVec3) -> u32 {
+        position.x + position.y * self.size.x + position.z * self.size.x * self.size.y
+    }
+
+    /// Resting particles of `radius` and `mass` at every point, in index order.
+    pub fn particles(&self, radius: f32, mass: f32) -> Vec<Particle> {
+        let mut particles = Vec::with_capacity(self.point_count() as usize);
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    let position = self.origin + UVec3::new(x, y, z).as_vec3() * self.spacing;
+                    particles.push(Particle::new(position, radius, mass));
+                }
+            }
+        }
+        particles
+    }
+
+    /// Constrains every point to the points at `offsets` from it, for particles spawned from this
+    /// lattice starting at `first_index`.
+    fn connect(
+        &self,
+        first_index: u32,
+        offsets: &[IVec3],
+        stiffness: f32,
+    ) -> Vec<DistanceConstraint> {
+        let mut constraints = Vec::new();
+        for z in 0..self.size.z {
+            for y in 0..self.size.y {
+                for x in 0..self.size.x {
+                    let position = UVec3::new(x, y, z);
+                    for &offset in offsets {
+                        let other = position.as_ivec3() + offset;
+                        if other.cmplt(IVec3::ZERO).any() || other.cmpge(self.size.as_ivec3()).any()
+                        {
+                            continue;
+                        }
+                        constraints.push(DistanceConstraint::new(
+                            first_index + self.index(position),
+                            first_index + self.index(other.as_uvec3()),
+                            offset.as_vec3().length() * self.spacing,
+                            stiffness,
+                        ));
+                    }
+                }
+            }
+        }
+        constraints
+    }
+}
+
+/// Chains every row of `lattice` along x into a rope, for particles spawned from it starting at
+/// `first_index`.
+pub fn rope_constraints(
+    lattice: &Lattice,
+    first_index: u32,
+    stiffness: f32,
+) -> Vec<DistanceConstraint> {
+    lattice.connect(first_index, &[IVec3::X], stiffness)
+}
+
+/// Weaves every layer of `lattice` across x and z into a sheet of cloth, which resists stretching
+/// along its rows and columns, shearing along its diagonals and bending across every other point.
+pub fn cloth_constraints(
+    lattice: &Lattice,
+    first_index: u32,
+    stiffness: f32,
+) -> Vec<DistanceConstraint> {
+    let offsets = [
+        IVec3::X,
+        IVec3::Z,
+        IVec3::new(1, 0, 1),
+        IVec3::new(1, 0, -1),
+        IVec3::X * 2,
+        IVec3::Z * 2,
+    ];
+    lattice.connect(first_index, &offsets, stiffness)
+}
+
+/// Fills `lattice` with tetrahedra, splitting every cell into six that share its main diagonal, and
+/// constrains their edges, which makes a soft body that keeps its volume.
+pub fn soft_body_constraints(
+    lattice: &Lattice,
+    first_index: u32,
+    stiffness: f32,
+) -> Vec<DistanceConstraint> {
+    let offsets = [
+        IVec3::X,
+        IVec3::Y,
+        IVec3::Z,
+        IVec3::new(1, 1, 0),
+        IVec3::new(1, 0, 1),
+        IVec3::new(0, 1, 1),
+        IVec3::ONE,
+    ];
+    lattice.connect(first_index, &offsets, stiffness)
+}
+
+/// A solid shape that can be filled with particles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    /// A box reaching `half_size` from its centre along each axis
+    Box { half_size: Vec3 },
+    /// A ball of `radius`
+    Sphere { radius: f32 },
+}
+
+impl Shape {
+    /// Whether the point at `offset` from the centre of the shape is inside it.
+    pub fn contains(&self, offset: Vec3) -> bool {
+        match *self {
+            Shape::Box { half_size } => offset.abs().cmple(half_size).all(),
+            Shape::Sphere { radius } => offset.length() <= radius,
+        }
+    }
+
+    fn half_size(&self) -> Vec3 {
+        match *self {
+            Shape::Box { half_size } => half_size,
+            Shape::Sphere { radius } => Vec3::splat(radius),
+        }
+    }
+
+    /// Resting particles of `radius` and `mass`, packed a diameter apart on a lattice centred at
+    /// `centre`, at every point that is inside the shape.
+    pub fn voxelize(&self, centre: Vec3, radius: f32, mass: f32) -> Vec<Particle> {
+        let spacing = 2.0 * radius;
+        let size = (2.0 * self.half_size() / spacing)
+            .floor()
+            .as_uvec3()
+            .max(UVec3::ONE);
+        let lattice = Lattice {
+            origin: centre - (size - 1).as_vec3() * spacing * 0.5,
+            size,
+            spacing,
+        };
+        let particles: Vec<Particle> = lattice
+            .particles(radius, mass)
+            .into_iter()
+            .filter(|particle| self.contains(particle.position - centre))
+            .collect();
+        assert!(
+            !particles.is_empty(),
+            "the shape is too small to hold a particle"
+        );
+        particles
+    }
+}
+
+/// Particles that move together as one rigid body, along with the [`Cluster`] that holds them
+/// together.
+#[derive(Clone, Debug)]
+pub struct RigidBody {
+    pub particles: Vec<Particle>,
+    pub cluster: Cluster,
+    /// Offset of each particle from the centre of the cluster, as
+    /// [`crate::Resources::set_clusters`] expects them
+    pub rest_offsets: Vec<Vec3>,
+}
+
+impl RigidBody {
+    /// Makes `particles`, spawned starting at `first_index`, into a rigid body in their current
+    /// arrangement.
+    pub fn new(particles: Vec<Particle>, first_index: u32, stiffness: f32) -> Self {
+        let cluster = Cluster::new(&particles, first_index, stiffness);
+        let rest_offsets = cluster.rest_offsets(&particles);
+        RigidBody {
+            particles,
+            cluster,
+            rest_offsets,
+        }
+    }
+}
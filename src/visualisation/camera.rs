@@ -1,12 +1,14 @@
 use glam::{self, Mat4, Quat, Vec3};
 use std::f32::consts::PI;
 
+/// Distances to the near and far clip planes of a [`Camera`].
 #[derive(Default)]
 pub struct Clip {
     pub near: f32,
     pub far: f32,
 }
 
+/// Perspective camera used by [`crate::Visualisation`] to ray march the scene.
 #[derive(Default)]
 pub struct Camera {
     pub fov: f32,
@@ -36,7 +38,7 @@ impl Camera {
     pub fn view(&self) -> Mat4 {
         let forward = self.rotation * Vec3::Z;
         let up = self.rotation * Vec3::Y;
-        return Mat4::look_at_lh(self.position, self.position + forward, up);
+        Mat4::look_at_lh(self.position, self.position + forward, up)
     }
 
     // TODO: look into memoization to avoid expensive matrix recalculation
@@ -1,3 +1,4 @@
+//! Rendering of the simulation state.
 mod camera;
 mod visualisation;
 pub use camera::Camera;
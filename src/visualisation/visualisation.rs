@@ -1,34 +1,62 @@
+use crate::error::SolError;
+use crate::resources::Resources;
+use crate::wgpu_utilities::{submit, Device};
 use crate::Camera;
 use bytemuck::{Pod, Zeroable};
 use encase::{ShaderSize, UniformBuffer};
 use std::borrow::Cow::Borrowed;
 use wgpu::{
-    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
-    Color, ColorTargetState, CommandEncoderDescriptor, Device, FragmentState, LoadOp,
+    Color, ColorTargetState, CommandEncoder, CommandEncoderDescriptor, FragmentState, LoadOp,
     MultisampleState, Operations, PipelineLayoutDescriptor, PrimitiveState, Queue,
     RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
     ShaderModuleDescriptor, ShaderSource::Wgsl, ShaderStages, TextureView, VertexState,
 };
 
 #[include_wgsl_oil::include_wgsl_oil("visualisation.wgsl")]
+#[allow(clippy::approx_constant)]
 mod visualisation_shader {}
 pub use visualisation_shader::types::Uniforms;
 unsafe impl Pod for Uniforms {}
 unsafe impl Zeroable for Uniforms {}
 impl Copy for Uniforms {}
 
+/// Renders the particles as a smooth signed distance field, along with the colliders, by ray
+/// marching a full screen quad.
 pub struct Visualisation {
     bind_group_layout: BindGroupLayout,
+    bind_groups: [BindGroup; 2],
+    resources_generation: u64,
     render_pipeline: RenderPipeline,
     uniform_buffer: Buffer,
 }
 
+impl Drop for Visualisation {
+    fn drop(&mut self) {
+        self.uniform_buffer.destroy();
+    }
+}
+
 impl Visualisation {
-    pub fn new(device: &Device, target: ColorTargetState) -> Self {
+    /// Creates the render pipeline, targeting colour attachments described by `target`, and binds
+    /// the parameters, material, collider, particle, bounds and grid buffers of `resources`.
+    pub fn new(
+        device: &Device,
+        target: ColorTargetState,
+        resources: &Resources,
+    ) -> Result<Self, SolError> {
+        device.capture_errors(|| Self::create(device, target, resources))
+    }
+
+    fn create(device: &Device, target: ColorTargetState, resources: &Resources) -> Self {
         let (bind_group_layout, render_pipeline, uniform_buffer) = Self::initialise(device, target);
+        let bind_groups =
+            Self::create_bind_groups(device, &bind_group_layout, &uniform_buffer, resources);
         Visualisation {
             bind_group_layout,
+            bind_groups,
+            resources_generation: resources.generation(),
             render_pipeline,
             uniform_buffer,
         }
@@ -90,6 +118,50 @@ impl Visualisation {
                     },
                     count: None,
                 },
+                // Grid particles
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Parameters
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Materials
+                BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Colliders
+                BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -125,35 +197,38 @@ impl Visualisation {
             mapped_at_creation: false,
         });
 
-        return (bind_group_layout, render_pipeline, uniform_buffer);
+        (bind_group_layout, render_pipeline, uniform_buffer)
     }
 
-    pub fn visualise(
-        &self,
+    /// One bind group for each of the particle buffers of `resources`.
+    fn create_bind_groups(
         device: &Device,
-        queue: &Queue,
-        view: &TextureView,
-        particle_buffer: &Buffer,
-        bounds_buffer: &Buffer,
-        grid_buffer: &Buffer,
-        camera: &Camera,
-    ) {
-        let (uniform_buffer, bind_group_layout, render_pipeline) = (
-            &self.uniform_buffer,
-            &self.bind_group_layout,
-            &self.render_pipeline,
-        );
-
-        let uniforms = Uniforms {
-            camera_position: camera.position,
-            inverse_view_projection: (camera.projection() * camera.view()).inverse(),
-        };
-
-        let mut encased_uniform_buffer = UniformBuffer::new(Vec::<u8>::new());
-        encased_uniform_buffer.write(&uniforms).unwrap();
-        queue.write_buffer(&uniform_buffer, 0, &encased_uniform_buffer.into_inner());
+        bind_group_layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        resources: &Resources,
+    ) -> [BindGroup; 2] {
+        resources
+            .particle_buffers()
+            .each_ref()
+            .map(|particle_buffer| {
+                Self::create_bind_group(
+                    device,
+                    bind_group_layout,
+                    uniform_buffer,
+                    particle_buffer,
+                    resources,
+                )
+            })
+    }
 
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        particle_buffer: &Buffer,
+        resources: &Resources,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
             label: None,
             layout: bind_group_layout,
             entries: &[
@@ -167,34 +242,115 @@ impl Visualisation {
                 },
                 BindGroupEntry {
                     binding: 2,
-                    resource: bounds_buffer.as_entire_binding(),
+                    resource: resources.bounds_buffer().as_entire_binding(),
                 },
                 BindGroupEntry {
                     binding: 3,
-                    resource: grid_buffer.as_entire_binding(),
+                    resource: resources.grid_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: resources.grid_particles_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: resources.parameters_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: resources.material_buffer().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: resources.collider_buffer().as_entire_binding(),
                 },
             ],
-        });
+        })
+    }
 
-        let mut command_encoder =
-            device.create_command_encoder(&CommandEncoderDescriptor { label: None });
-        {
-            let mut render_pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
-                label: None,
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color::TRANSPARENT),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-            render_pass.set_pipeline(&render_pipeline);
-            render_pass.set_bind_group(0, &bind_group, &[]);
-            render_pass.draw(0..6, 0..1);
+    /// Rebuilds the bind groups if the buffers of `resources` have been reallocated since they were
+    /// created.
+    fn update_bind_groups(&mut self, device: &Device, resources: &Resources) {
+        if self.resources_generation != resources.generation() {
+            self.bind_groups = Self::create_bind_groups(
+                device,
+                &self.bind_group_layout,
+                &self.uniform_buffer,
+                resources,
+            );
+            self.resources_generation = resources.generation();
         }
-        queue.submit(Some(command_encoder.finish()));
+    }
+
+    /// Records rendering the particles into `view` into `command_encoder`.
+    ///
+    /// The uniforms are written through `queue`, so they take effect when `command_encoder` is next
+    /// submitted.
+    pub fn visualise_with_encoder(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        command_encoder: &mut CommandEncoder,
+        view: &TextureView,
+        resources: &Resources,
+        camera: &Camera,
+    ) {
+        self.update_bind_groups(device, resources);
+        let (uniform_buffer, bind_group, render_pipeline) = (
+            &self.uniform_buffer,
+            &self.bind_groups[resources.particle_buffer_index()],
+            &self.render_pipeline,
+        );
+
+        let uniforms = Uniforms {
+            camera_position: camera.position,
+            collider_count: resources.colliders().len() as u32,
+            inverse_view_projection: (camera.projection() * camera.view()).inverse(),
+        };
+
+        let mut encased_uniform_buffer = UniformBuffer::new(Vec::<u8>::new());
+        encased_uniform_buffer.write(&uniforms).unwrap();
+        queue.write_buffer(uniform_buffer, 0, &encased_uniform_buffer.into_inner());
+
+        let mut render_pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(render_pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+
+    /// Renders the particles into `view` and submits the work to `queue`.
+    pub fn visualise(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        view: &TextureView,
+        resources: &Resources,
+        camera: &Camera,
+    ) -> Result<(), SolError> {
+        let command_buffer = device.capture_errors(|| {
+            let mut command_encoder =
+                device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            self.visualise_with_encoder(
+                device,
+                queue,
+                &mut command_encoder,
+                view,
+                resources,
+                camera,
+            );
+            command_encoder.finish()
+        })?;
+        submit(device, queue, command_buffer)
     }
 }
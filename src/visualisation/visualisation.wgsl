@@ -1,4 +1,5 @@
 #import ../common.wgsl as Common
+#import ../sdf.wgsl as Sdf
 
 const PI = 3.141592653589793;
 
@@ -15,6 +16,8 @@ const LIGHT_DIRECTION = vec3<f32>(.5, 1., -.3);
 @export struct Uniforms {
     // TODO: can we just decompose this from `inverse__view_projection`?
     camera_position: vec3<f32>,
+    // Number of colliders in use at the start of `colliders`
+    collider_count: u32,
     inverse_view_projection: mat4x4<f32>,
 }
 
@@ -34,6 +37,22 @@ var<storage, read> bounds: Common::Bounds;
 @binding(3)
 var<storage, read> grid: array<Common::GridCell>;
 
+@group(0)
+@binding(4)
+var<storage, read> grid_particles: array<u32>;
+
+@group(0)
+@binding(5)
+var<uniform> parameters: Common::Parameters;
+
+@group(0)
+@binding(6)
+var<uniform> materials: array<Common::Material, Common::MAX_MATERIALS>;
+
+@group(0)
+@binding(7)
+var<uniform> colliders: array<Sdf::Collider, Sdf::MAX_COLLIDERS>;
+
 struct Vertex {
     @builtin(position) position: vec4<f32>,
     @location(0) ndc: vec2<f32>,
@@ -64,7 +83,8 @@ fn fragment(vertex: Vertex) -> @location(0) vec4<f32> {
     let ray_direction = normalize((uniforms.inverse_view_projection * vec4<f32>(vertex.ndc, 1., 1.)).xyz);
     let bounds_min = vec3<f32>(vec3<i32>(bounds.min_x, bounds.min_y, bounds.min_z));
     let bounds_max = vec3<f32>(vec3<i32>(bounds.max_x, bounds.max_y, bounds.max_z));
-    if (ray_box_intersection(ray_origin, ray_direction, bounds_min, bounds_max)) {
+    // Colliders can extend beyond the bounds, which only contain the particles
+    if (uniforms.collider_count > 0u || ray_box_intersection(ray_origin, ray_direction, bounds_min, bounds_max)) {
         let ray_march_result = ray_march_adaptive(ray_origin, ray_direction);
         if (ray_march_result.hit) {
             // TODO: Calculate this in the actual functions that return SDF so that we can use different SDF mapping where desired
@@ -164,8 +184,7 @@ fn ray_march_adaptive(origin: vec3<f32>, direction: vec3<f32>) -> RayMarchResult
             result.hit = true;
             result.position = position;
             result.normal = evaluate_scene_normal(position);
-            // result.colour = evaluate_scene_result.object.colour;
-            result.colour = vec3<f32>(1., 0.5, 0.3);
+            result.colour = evaluate_scene_result.colour;
             return result;
         }
         distance = evaluate_scene_result.distance;
@@ -184,8 +203,7 @@ fn ray_march(origin: vec3<f32>, direction: vec3<f32>) -> RayMarchResult {
             result.hit = true;
             result.position = position;
             result.normal = evaluate_scene_normal(position);
-            // result.colour = evaluate_scene_result.object.colour;
-            result.colour = vec3<f32>(1.);
+            result.colour = evaluate_scene_result.colour;
             return result;
         }
     }
@@ -196,6 +214,7 @@ fn ray_march(origin: vec3<f32>, direction: vec3<f32>) -> RayMarchResult {
 struct EvaluateSceneResult {
     // object: Object,
     distance: f32,
+    colour: vec3<f32>,
 }
 
 fn evaluate_scene(position: vec3<f32>) -> EvaluateSceneResult {
@@ -203,7 +222,10 @@ fn evaluate_scene(position: vec3<f32>) -> EvaluateSceneResult {
     result.distance = MAX_DISTANCE;
     // result.distance = sphere(position - vec3<f32>(0.0), 0.5);
     result = evaluate_particles(position);
-  
+    let colliders_result = evaluate_colliders(position);
+    if (colliders_result.distance < result.distance) {
+        result = colliders_result;
+    }
     return result;
 }
 
@@ -223,40 +245,72 @@ fn evaluate_scene_normal(position: vec3<f32>) -> vec3<f32> {
 }
 
 fn evaluate_particles(position: vec3<f32>) -> EvaluateSceneResult {
-    var result: EvaluateSceneResult; 
-    result.distance = evaluate_particle(position, 0u);
-    for (var i = 1u; i < Common::MAX_PARTICLES; i++) {
-        result.distance = smooth_union(result.distance, evaluate_particle(position, i), 3.);
+    var result: EvaluateSceneResult;
+    result.distance = Sdf::EMPTY_DISTANCE;
+    for (var i = 0u; i < parameters.particle_count; i++) {
+        if (particles[i].alive == 0u) {
+            continue; // A free slot
+        }
+        result = smooth_union_particle(result, position, i);
     }
     return result;
 }
 
+// Blends the colours of the particles by the same amount as their distances
+fn smooth_union_particle(result: EvaluateSceneResult, position: vec3<f32>, particle_index: u32) -> EvaluateSceneResult {
+    var blended_result: EvaluateSceneResult;
+    let distance = evaluate_particle(position, particle_index);
+    let h = clamp(.5 + .5 * (distance - result.distance) / 3., .0, 1.);
+    blended_result.distance = Sdf::smooth_union(result.distance, distance, 3.);
+    blended_result.colour = mix(particle_colour(particle_index), result.colour, h);
+    return blended_result;
+}
+
+fn particle_colour(particle_index: u32) -> vec3<f32> {
+    let material_index = min(particles[particle_index].material, Common::MAX_MATERIALS - 1u);
+    return materials[material_index].colour;
+}
+
 fn evaluate_particle(position: vec3<f32>, particle_index: u32) -> f32 {
     let particle = particles[particle_index];
     let relative_position = position - particle.position;
-    return sphere(relative_position, Common::PARTICLE_RADIUS);
+    return Sdf::sphere(relative_position, particle.radius);
+}
+
+// Combines the colliders in the same order as the simulation. Each takes the colour of the surface
+// nearest to `position`, so the surface that a collider carves out keeps the colour of what it
+// carved into
+fn evaluate_colliders(position: vec3<f32>) -> EvaluateSceneResult {
+    var result: EvaluateSceneResult;
+    result.distance = Sdf::EMPTY_DISTANCE;
+    for (var i = 0u; i < uniforms.collider_count; i++) {
+        let collider = colliders[i];
+        let distance = Sdf::collider_distance(collider, position);
+        let combined_distance = Sdf::combine_collider(collider, result.distance, distance);
+        if (abs(combined_distance - distance) < abs(combined_distance - result.distance)) {
+            result.colour = collider.colour;
+        }
+        result.distance = combined_distance;
+    }
+    return result;
 }
 
+// Particles are binned into every cell that they overlap, so only the cell containing `position` is
+// evaluated
 fn evaluate_grid(position: vec3<f32>) -> EvaluateSceneResult {
     var result: EvaluateSceneResult; 
-    let bounds_min = vec3<i32>(bounds.min_x, bounds.min_y, bounds.min_z);
-    let bounds_max = vec3<i32>(bounds.max_x, bounds.max_y, bounds.max_z);
-    let grid_position = Common::world_position_to_grid_position(position, bounds);
-    let bounded_grid_position = clamp(grid_position, vec3<i32>(bounds_min), vec3<i32>(bounds_max));
-    let grid_index = Common::grid_position_to_grid_index(bounded_grid_position);
-    let particles_length = grid[grid_index].particles_length;
+    let grid_position = Common::world_position_to_grid_position(position, bounds, parameters);
+    let bounded_grid_position = clamp(grid_position, vec3<i32>(0), vec3<i32>(parameters.grid_size) - 1);
+    let grid_index = Common::grid_position_to_grid_index(bounded_grid_position, parameters);
+    let particles_length = min(grid[grid_index].particles_length, parameters.max_particles_per_grid_cell);
     result.distance = MAX_DISTANCE;
     for (var i = 0u; i < particles_length; i++) {
-        result.distance = smooth_union(result.distance, evaluate_cell_particle(position, grid_index, i), 3.);
+        let particle_index = grid_particles[Common::grid_particle_index(grid_index, i, parameters)];
+        result = smooth_union_particle(result, position, particle_index);
     }
     return result;
 }
 
-fn evaluate_cell_particle(position: vec3<f32>, grid_index: i32, cell_particle_index: u32) -> f32 {
-    let particle_index = grid[grid_index].particles[cell_particle_index];
-    return evaluate_particle(position, particle_index);
-}
-
 // fn occlusion(position: vec3<f32>, direction: vec3<f32>) -> f32 {
 //     // return position.y;
 //     let start: vec3<f32> = position + (normal(position) * .01);
@@ -307,43 +361,3 @@ fn sphere_uv(position: vec3<f32>) -> vec2<f32> {
     let v = phi / PI;
     return vec2<f32>(u, v);
 }
-
-// https://iquilezles.org/articles/distfunctions/
-
-fn sphere(position: vec3<f32>, radius: f32) -> f32 {
-    return length(position) - radius;
-}
-
-fn sphere_relative(position: vec3<f32>, translation: vec3<f32>, radius: f32) -> f32 {
-    return length(position - translation) - radius;
-}
-
-fn cube(position: vec3<f32>, extent: vec3<f32>) -> f32 {
-    let q = abs(position) - extent;
-    return length(max(q, vec3<f32>(.0))) + min(max(q.x, max(q.y, q.z)), .0);
-}
-
-fn plane_y_infinite(position: vec3<f32>) -> f32 {
-    return position.y;
-}
-
-fn sharp_union(a: f32, b: f32) -> f32 { return min(a, b); }
-
-fn sharp_subtraction(a: f32, b: f32) -> f32 { return max(-a, b); }
-
-fn sharp_intersection(a: f32, b: f32) -> f32 { return max(a, b); }
-
-fn smooth_union(a: f32, b: f32, k: f32) -> f32 {
-    let h = clamp(.5 + .5 * (b - a) / k, .0, 1.);
-    return mix(b, a, h) - k * h * (1. - h);
-}
-
-fn smooth_subtraction(a: f32, b: f32, k: f32) -> f32 {
-    let h = clamp(.5 - .5 * (b + a) / k, .0, 1.);
-    return mix(b, -a, h) + k * h * (1. - h);
-}
-
-fn smooth_intersection(a: f32, b: f32, k: f32) -> f32 {
-    let h = clamp(.5 - .5 * (b - a) / k, .0, 1.);
-    return mix(b, a, h) + k * h * (1. - h);
-}
\ No newline at end of file
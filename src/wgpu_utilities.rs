@@ -1,6 +1,17 @@
+use crate::error::SolError;
 use encase::{internal::WriteInto, ShaderType, UniformBuffer};
-use wgpu::{Buffer, Queue};
+use futures::FutureExt;
+use std::error::Error;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use wgpu::{
+    Adapter, Buffer, CommandBuffer, ErrorFilter, Limits, Queue, Surface, SurfaceConfiguration,
+    SurfaceError, SurfaceTexture,
+};
+use wgpu_core::device::DeviceError;
 
+/// Convenience methods for writing `encase` encoded data through a [`Queue`].
 pub trait QueueUtilities<T: ShaderType + WriteInto> {
     fn write_encased_uniform_buffer(&self, buffer: &Buffer, data: T);
 }
@@ -12,3 +23,135 @@ impl<T: ShaderType + WriteInto> QueueUtilities<T> for Queue {
         self.write_buffer(buffer, 0, &encased_uniform_buffer.into_inner());
     }
 }
+
+/// A [`wgpu::Device`] that remembers whether it has been lost, which `wgpu` offers no way to ask,
+/// and turns the errors that it raises into [`SolError`]s.
+///
+/// Dereferences to the [`wgpu::Device`], so it can be used wherever one is expected.
+pub struct Device {
+    device: wgpu::Device,
+    // Shared with the uncaptured error handler of `device`
+    lost: Arc<AtomicBool>,
+}
+
+impl Device {
+    /// Wraps `device`, printing the errors that it raises outside of [`Self::capture_errors`]
+    /// instead of panicking, see [`Self::report_error`].
+    pub fn new(device: wgpu::Device) -> Self {
+        let lost = Arc::new(AtomicBool::new(false));
+        let handler_lost = lost.clone();
+        device.on_uncaptured_error(Box::new(move |error| report_error(&handler_lost, error)));
+        Device { device, lost }
+    }
+
+    /// Runs `f` and returns the first error it raised, rather than passing the error to the
+    /// uncaptured error handler.
+    ///
+    /// Errors raised because the device has been lost are returned as [`SolError::DeviceLost`].
+    pub fn capture_errors<T>(&self, f: impl FnOnce() -> T) -> Result<T, SolError> {
+        self.push_error_scope(ErrorFilter::OutOfMemory);
+        self.push_error_scope(ErrorFilter::Validation);
+        let value = f();
+        // Native error scopes resolve immediately, so there is no need for an executor, which would
+        // also prevent this from being called from async code
+        let validation_error = self.pop_error_scope().now_or_never().flatten();
+        let out_of_memory_error = self.pop_error_scope().now_or_never().flatten();
+        match out_of_memory_error.or(validation_error) {
+            Some(error) if is_caused_by_device_loss(&error) => {
+                self.lost.store(true, Ordering::Relaxed);
+                Err(SolError::DeviceLost)
+            }
+            Some(error) => Err(error.into()),
+            None => Ok(value),
+        }
+    }
+
+    /// Handles `error` as if the device had raised it outside of [`Self::capture_errors`]: prints
+    /// it, and records it for [`Self::is_lost`] if it was caused by the loss of the device.
+    pub fn report_error(&self, error: wgpu::Error) {
+        report_error(&self.lost, error);
+    }
+
+    /// Whether the device has raised an error caused by its loss, after which it and everything
+    /// created from it have to be recreated.
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+impl Deref for Device {
+    type Target = wgpu::Device;
+
+    fn deref(&self) -> &wgpu::Device {
+        &self.device
+    }
+}
+
+fn report_error(lost: &AtomicBool, error: wgpu::Error) {
+    if is_caused_by_device_loss(&error) {
+        lost.store(true, Ordering::Relaxed);
+    }
+    eprintln!("Uncaptured error: {}", SolError::from(error));
+}
+
+/// Whether `error` was raised because the device has been lost, which `wgpu` reports as a
+/// validation error caused by a [`DeviceError::Lost`].
+fn is_caused_by_device_loss(error: &wgpu::Error) -> bool {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if let Some(DeviceError::Lost) = error.downcast_ref::<DeviceError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// Submits `command_buffer` to the `queue` of `device`, unless `device` is known to have been lost.
+///
+/// `wgpu` treats a failed submission as fatal, so the loss of the device has to be noticed through
+/// the errors it raised before, see [`Device::is_lost`].
+pub fn submit(
+    device: &Device,
+    queue: &Queue,
+    command_buffer: CommandBuffer,
+) -> Result<(), SolError> {
+    if device.is_lost() {
+        return Err(SolError::DeviceLost);
+    }
+    queue.submit(Some(command_buffer));
+    Ok(())
+}
+
+/// Acquires the next texture of `surface`, or `None` when the frame should be skipped.
+///
+/// A lost or outdated surface is reconfigured with `surface_configuration` so that the next frame
+/// can acquire a texture again.
+pub fn current_texture(
+    surface: &Surface,
+    device: &Device,
+    surface_configuration: &SurfaceConfiguration,
+) -> Result<Option<SurfaceTexture>, SolError> {
+    match surface.get_current_texture() {
+        Ok(surface_texture) => Ok(Some(surface_texture)),
+        Err(SurfaceError::Lost | SurfaceError::Outdated) => {
+            surface.configure(device, surface_configuration);
+            Ok(None)
+        }
+        Err(SurfaceError::Timeout) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// The limits requested from `adapter`: the downlevel defaults, raised to whatever the adapter
+/// supports for resolution and storage buffers so that large particle counts and many bound buffers
+/// are available.
+pub fn required_limits(adapter: &Adapter) -> Limits {
+    let adapter_limits = adapter.limits();
+    Limits {
+        max_storage_buffers_per_shader_stage: adapter_limits.max_storage_buffers_per_shader_stage,
+        max_storage_buffer_binding_size: adapter_limits.max_storage_buffer_binding_size,
+        max_buffer_size: adapter_limits.max_buffer_size,
+        ..Limits::downlevel_defaults().using_resolution(adapter_limits)
+    }
+}
@@ -0,0 +1,1594 @@
+//! Runs the shaders and the CPU reference from the same initial state on the fallback adapter and
+//! checks that they agree.
+
+use bytemuck::Zeroable;
+use futures::executor::block_on;
+use glam::{Quat, UVec3, Vec3};
+use rand::Rng;
+use sol::common::{MassCell, DEFAULT_PARTICLE_MASS, DEFAULT_PARTICLE_RADIUS};
+use sol::debug::read_buffer;
+use sol::headless::HeadlessRunner;
+use sol::partition::MASS_FIXED_POINT_SCALE;
+use sol::reference::{Scene, Settings};
+use sol::sdf::{OPERATION_INTERSECTION, OPERATION_SUBTRACTION};
+use sol::simulation::{NeighbourSearch, Phase, DEFAULT_SUBSTEPS};
+use sol::spawn::{
+    assign_material, cloth_constraints, mix_coarse_grains, random_particles, seeded_rng,
+    soft_body_constraints, Lattice, RigidBody, Shape,
+};
+use sol::{
+    reference, Bounds, BoundsMode, Cluster, Collider, Configuration, Container, DistanceConstraint,
+    ForceField, GravityModel, GridCell, Material, Particle, RadialGravity, SelfGravity,
+};
+
+const TOLERANCE: f32 = 1e-3;
+const DELTA_TIME: f32 = 1.0 / 60.0;
+const GRAVITY: Vec3 = Vec3::new(0.0, -9.8, 0.0);
+
+/// Particles on a lattice that is spaced widely enough that they never touch.
+fn lattice_particles(size: u32, spacing: f32) -> Vec<Particle> {
+    let mut particles = Vec::new();
+    for x in 0..size {
+        for y in 0..size {
+            for z in 0..size {
+                let position = Vec3::new(x as f32, y as f32, z as f32) * spacing
+                    - Vec3::splat(size as f32 * spacing * 0.5);
+                particles.push(Particle::new(
+                    position,
+                    DEFAULT_PARTICLE_RADIUS,
+                    DEFAULT_PARTICLE_MASS,
+                ));
+            }
+        }
+    }
+    particles
+}
+
+/// Index of the pebble material in [`materials`]
+const PEBBLE: u32 = 1;
+/// Index of the fluid material in [`materials`]
+const WATER: u32 = 2;
+
+/// The default material, a denser, bouncier and less frictional pebble and a fluid.
+fn materials() -> Vec<Material> {
+    let sand = Material::default();
+    let pebble = Material {
+        static_friction: 0.4,
+        kinetic_friction: 0.3,
+        restitution: 0.05,
+        density: sand.density * 2.0,
+        ..sand
+    };
+    vec![sand, pebble, Material::fluid()]
+}
+
+fn runner(particles: &[Particle]) -> HeadlessRunner {
+    let configuration = Configuration {
+        grid_size: UVec3::splat(8),
+        max_particles_per_grid_cell: 64,
+        ..Configuration::with_particles(particles.len() as u32)
+    };
+    let mut runner = block_on(HeadlessRunner::new(configuration, true)).unwrap();
+    runner
+        .resources
+        .set_materials(&runner.queue, &materials())
+        .unwrap();
+    runner.populate(particles).unwrap();
+    runner
+}
+
+#[test]
+fn bounds_match_reference() {
+    let particles = lattice_particles(4, 3.3);
+    let mut runner = runner(&particles);
+
+    runner
+        .pipeline
+        .bounds_partition
+        .calculate_bounds(&runner.device, &runner.queue, &runner.resources)
+        .unwrap();
+    let bounds = read_buffer::<Bounds>(
+        &runner.device,
+        &runner.queue,
+        runner.resources.bounds_buffer(),
+    )
+    .unwrap();
+
+    assert_eq!(bounds, reference::calculate_bounds(&particles));
+}
+
+fn read_bounds(runner: &HeadlessRunner) -> Bounds {
+    read_buffer::<Bounds>(
+        &runner.device,
+        &runner.queue,
+        runner.resources.bounds_buffer(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn fixed_bounds_ignore_the_particles() {
+    let particles = lattice_particles(4, 3.3);
+    let mut runner = runner(&particles);
+    let fixed_bounds = Bounds {
+        min_x: -2,
+        min_y: -3,
+        min_z: -4,
+        max_x: 20,
+        max_y: 30,
+        max_z: 40,
+    };
+    let mode = BoundsMode::Fixed(fixed_bounds);
+    runner.pipeline.bounds_partition.set_mode(mode);
+
+    runner
+        .pipeline
+        .bounds_partition
+        .calculate_bounds(&runner.device, &runner.queue, &runner.resources)
+        .unwrap();
+
+    assert_eq!(read_bounds(&runner), fixed_bounds);
+    assert_eq!(
+        reference::update_bounds(&Bounds::zeroed(), &particles, mode),
+        fixed_bounds
+    );
+}
+
+#[test]
+fn bounds_with_margin_and_hysteresis_match_reference() {
+    // Drifting steadily along x, so that the bounds have to follow them
+    let mut particles = lattice_particles(4, 3.3);
+    for particle in &mut particles {
+        particle.old_position -= Vec3::X * 0.1;
+    }
+    let mut runner = runner(&particles);
+    let mode = BoundsMode::DynamicWithMargin {
+        margin: 2,
+        hysteresis: 3,
+    };
+    runner.pipeline.bounds_partition.set_mode(mode);
+
+    let mut bounds = read_bounds(&runner);
+    let mut changes = 0;
+    let frames = 32;
+    for frame in 0..frames {
+        let particles = runner.particles().unwrap();
+        let expected_bounds = reference::update_bounds(&bounds, &particles, mode);
+        runner.step(DELTA_TIME, Vec3::ZERO).unwrap();
+
+        let gpu_bounds = read_bounds(&runner);
+        assert_eq!(gpu_bounds, expected_bounds, "frame {frame}");
+        if gpu_bounds != bounds {
+            changes += 1;
+        }
+        bounds = gpu_bounds;
+    }
+    // The bounds only move once the particles cross them or leave them far behind
+    assert!(
+        changes > 1 && changes < frames / 2,
+        "the bounds changed {changes} times in {frames} frames"
+    );
+}
+
+/// A loose cloud of particles, a quarter of which are coarse pebbles that span several grid cells.
+fn mixed_particles(seed: u64) -> Vec<Particle> {
+    let mut rng = seeded_rng(seed);
+    // Loose enough for the pebbles to separate from whatever they were spawned overlapping within a
+    // few steps
+    let mut particles = random_particles(&mut rng, 128, 10.0);
+    mix_coarse_grains(
+        &mut rng,
+        &mut particles,
+        0.25,
+        1.6,
+        PEBBLE,
+        &materials()[PEBBLE as usize],
+    );
+    particles
+}
+
+fn check_grid(mut runner: HeadlessRunner, particles: &[Particle]) {
+    let configuration = *runner.resources.configuration();
+
+    runner
+        .pipeline
+        .bounds_partition
+        .calculate_bounds(&runner.device, &runner.queue, &runner.resources)
+        .unwrap();
+    runner
+        .pipeline
+        .grid_partition
+        .build_grid(&runner.device, &runner.queue, &runner.resources)
+        .unwrap();
+    let grid = reference::Grid {
+        cells: read_buffer::<Vec<GridCell>>(
+            &runner.device,
+            &runner.queue,
+            runner.resources.grid_buffer(),
+        )
+        .unwrap(),
+        particles: read_buffer::<Vec<u32>>(
+            &runner.device,
+            &runner.queue,
+            runner.resources.grid_particles_buffer(),
+        )
+        .unwrap(),
+    };
+
+    let bounds = reference::calculate_bounds(particles);
+    let expected_grid = reference::build_grid(particles, &bounds, &configuration);
+    for grid_index in 0..configuration.grid_cell_count() as usize {
+        assert_eq!(
+            grid.cells[grid_index], expected_grid.cells[grid_index],
+            "cell {grid_index} has a different length"
+        );
+        assert_eq!(
+            grid.cell_particles(grid_index, &configuration),
+            expected_grid.cell_particles(grid_index, &configuration),
+            "cell {grid_index} contains different particles"
+        );
+    }
+}
+
+#[test]
+fn grid_matches_reference() {
+    let particles = lattice_particles(4, 3.3);
+    check_grid(runner(&particles), &particles);
+}
+
+#[test]
+fn grid_of_mixed_grains_matches_reference() {
+    let particles = mixed_particles(2);
+    check_grid(runner(&particles), &particles);
+}
+
+/// A clump of particles in one cell among a cloud of particles that spread the bounds, with a grid
+/// that the clump overflows.
+fn overflowing_runner() -> (HeadlessRunner, Vec<Particle>) {
+    let mut rng = seeded_rng(12);
+    let mut particles = random_particles(&mut rng, 64, 12.0);
+    particles.extend((0..48).map(|_| {
+        let offset = Vec3::new(rng.gen(), rng.gen(), rng.gen()) - 0.5;
+        Particle::new(
+            Vec3::new(3.0, -2.0, 1.0) + offset,
+            DEFAULT_PARTICLE_RADIUS,
+            rng.gen_range(0.5..2.0),
+        )
+    }));
+    let configuration = Configuration {
+        grid_size: UVec3::splat(8),
+        max_particles_per_grid_cell: 8,
+        ..Configuration::with_particles(particles.len() as u32)
+    };
+    let mut runner = block_on(HeadlessRunner::new(configuration, true)).unwrap();
+    runner.populate(&particles).unwrap();
+
+    let bounds = reference::calculate_bounds(&particles);
+    let grid = reference::build_grid(&particles, &bounds, &configuration);
+    assert!(grid
+        .cells
+        .iter()
+        .any(|cell| cell.particles_length > 4 * configuration.max_particles_per_grid_cell));
+    (runner, particles)
+}
+
+#[test]
+fn overflowing_grid_cells_keep_the_lowest_particle_indices() {
+    let (runner, particles) = overflowing_runner();
+    check_grid(runner, &particles);
+}
+
+#[test]
+fn simulate_matches_reference_without_contacts() {
+    let mut particles = lattice_particles(4, 3.3);
+    let mut runner = runner(&particles);
+    let configuration = *runner.resources.configuration();
+
+    for frame in 0..16 {
+        runner.step(DELTA_TIME, GRAVITY).unwrap();
+
+        let bounds = reference::calculate_bounds(&particles);
+        reference::simulate(
+            &mut particles,
+            &mut [],
+            &mut [],
+            &Scene {
+                materials: &materials(),
+                configuration,
+                ..Default::default()
+            },
+            &bounds,
+            DELTA_TIME,
+            GRAVITY,
+        );
+
+        let divergence = reference::divergence(&runner.particles().unwrap(), &particles);
+        assert!(
+            divergence.exceeding(TOLERANCE).is_empty(),
+            "frame {frame}: particles {:?} diverged by up to {} (mean {})",
+            divergence.exceeding(TOLERANCE),
+            divergence.max(),
+            divergence.mean(),
+        );
+    }
+}
+
+/// The constraints and clusters that join particles into bodies, and the static colliders,
+/// container and force fields around them.
+#[derive(Default)]
+struct Bodies {
+    constraints: Vec<DistanceConstraint>,
+    clusters: Vec<Cluster>,
+    rest_offsets: Vec<Vec3>,
+    colliders: Vec<Collider>,
+    container: Option<Container>,
+    force_fields: Vec<ForceField>,
+}
+
+impl Bodies {
+    fn add_rigid_body(&mut self, particles: &mut Vec<Particle>, rigid_body: RigidBody) {
+        particles.extend(rigid_body.particles);
+        self.clusters.push(rigid_body.cluster);
+        self.rest_offsets.extend(rigid_body.rest_offsets);
+    }
+
+    fn upload(&self, runner: &mut HeadlessRunner) {
+        let resources = &mut runner.resources;
+        resources
+            .set_constraints(&runner.device, &runner.queue, &self.constraints)
+            .unwrap();
+        resources
+            .set_clusters(
+                &runner.device,
+                &runner.queue,
+                &self.clusters,
+                &self.rest_offsets,
+            )
+            .unwrap();
+        resources
+            .set_colliders(&runner.queue, &self.colliders)
+            .unwrap();
+        resources.set_container(&runner.queue, self.container);
+        runner
+            .pipeline
+            .simulation
+            .set_force_fields(&self.force_fields)
+            .unwrap();
+    }
+}
+
+/// Steps `particles`, some of which touch, with `settings`.
+fn check_contacts(particles: Vec<Particle>, settings: Settings) {
+    check_bodies(particles, Bodies::default(), settings);
+}
+
+/// Steps `particles`, some of which touch and some of which are joined into `bodies`, with
+/// `settings`.
+fn check_bodies(mut particles: Vec<Particle>, bodies: Bodies, settings: Settings) {
+    let mut runner = runner(&particles);
+    bodies.upload(&mut runner);
+    let Bodies {
+        mut constraints,
+        mut clusters,
+        rest_offsets,
+        colliders,
+        container,
+        force_fields,
+    } = bodies;
+    let configuration = *runner.resources.configuration();
+    let simulation = &mut runner.pipeline.simulation;
+    simulation.set_neighbour_search(settings.neighbour_search);
+    simulation.set_substeps(settings.substeps);
+    simulation.set_iterations(settings.iterations);
+    simulation.set_relaxation(settings.relaxation);
+    simulation.set_phase(settings.phase);
+    simulation.set_smoothing_length(settings.smoothing_length);
+    simulation.set_self_gravity(settings.self_gravity);
+    simulation.set_gravity_model(settings.gravity_model);
+    let materials = materials();
+    let scene = Scene {
+        materials: &materials,
+        colliders: &colliders,
+        container: container.as_ref(),
+        force_fields: &force_fields,
+        rest_offsets: &rest_offsets,
+        configuration,
+        settings,
+    };
+
+    for frame in 0..4 {
+        runner.step(DELTA_TIME, GRAVITY).unwrap();
+
+        let bounds = reference::calculate_bounds(&particles);
+        reference::simulate(
+            &mut particles,
+            &mut constraints,
+            &mut clusters,
+            &scene,
+            &bounds,
+            DELTA_TIME,
+            GRAVITY,
+        );
+
+        let divergence = reference::divergence(&runner.particles().unwrap(), &particles);
+        assert!(
+            divergence.exceeding(TOLERANCE).is_empty(),
+            "frame {frame}: particles {:?} diverged by up to {} (mean {})",
+            divergence.exceeding(TOLERANCE),
+            divergence.max(),
+            divergence.mean(),
+        );
+        if !constraints.is_empty() {
+            let gpu_constraints = read_buffer::<Vec<DistanceConstraint>>(
+                &runner.device,
+                &runner.queue,
+                runner.resources.constraint_buffer(),
+            )
+            .unwrap();
+            assert_eq!(&gpu_constraints[..constraints.len()], &constraints[..]);
+        }
+        if !clusters.is_empty() {
+            let gpu_clusters = read_buffer::<Vec<Cluster>>(
+                &runner.device,
+                &runner.queue,
+                runner.resources.cluster_buffer(),
+            )
+            .unwrap();
+            for (gpu_cluster, cluster) in gpu_clusters.iter().zip(&clusters) {
+                assert!(
+                    gpu_cluster.centre.distance(cluster.centre) < TOLERANCE
+                        && gpu_cluster.rotation.distance(cluster.rotation) < TOLERANCE,
+                    "frame {frame}: cluster {gpu_cluster:?} diverged from {cluster:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn grid_search_matches_reference_with_contacts() {
+    check_contacts(
+        random_particles(&mut seeded_rng(0), 128, 6.0),
+        Settings::default(),
+    );
+}
+
+#[test]
+fn grid_search_matches_reference_with_mixed_grains() {
+    check_contacts(mixed_particles(0), Settings::default());
+}
+
+#[test]
+fn brute_force_search_matches_reference_with_contacts() {
+    check_contacts(
+        random_particles(&mut seeded_rng(0), 128, 6.0),
+        Settings {
+            neighbour_search: NeighbourSearch::BruteForce,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn fluid_matches_reference() {
+    check_contacts(
+        random_particles(&mut seeded_rng(0), 128, 6.0),
+        Settings {
+            phase: Phase::Fluid,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn fluid_mixed_with_grains_matches_reference() {
+    let mut particles = random_particles(&mut seeded_rng(2), 128, 6.0);
+    for chunk in particles.chunks_mut(2) {
+        assign_material(&mut chunk[..1], WATER, &Material::fluid());
+    }
+    check_contacts(particles.clone(), Settings::default());
+    check_contacts(
+        particles,
+        Settings {
+            neighbour_search: NeighbourSearch::BruteForce,
+            ..Default::default()
+        },
+    );
+}
+
+/// Loose sand with a sheet of cloth above it and a soft blob within it, some of whose constraints
+/// break.
+fn constrained_particles() -> (Vec<Particle>, Vec<DistanceConstraint>) {
+    let mut particles = random_particles(&mut seeded_rng(3), 64, 6.0);
+    let mut constraints = Vec::new();
+
+    let cloth = Lattice {
+        origin: Vec3::new(-4.0, 8.0, -4.0),
+        size: UVec3::new(6, 1, 6),
+        spacing: 2.0 * DEFAULT_PARTICLE_RADIUS,
+    };
+    constraints.extend(cloth_constraints(&cloth, particles.len() as u32, 1.0));
+    particles.extend(cloth.particles(DEFAULT_PARTICLE_RADIUS, DEFAULT_PARTICLE_MASS));
+
+    let blob = Lattice {
+        origin: Vec3::splat(-2.0),
+        size: UVec3::splat(3),
+        spacing: 2.0 * DEFAULT_PARTICLE_RADIUS,
+    };
+    let blob_constraints = soft_body_constraints(&blob, particles.len() as u32, 0.5);
+    constraints.extend(
+        blob_constraints
+            .into_iter()
+            .map(|constraint| DistanceConstraint {
+                break_strain: 0.05,
+                ..constraint
+            }),
+    );
+    particles.extend(blob.particles(DEFAULT_PARTICLE_RADIUS, DEFAULT_PARTICLE_MASS));
+    (particles, constraints)
+}
+
+#[test]
+fn constraints_match_reference() {
+    let (particles, constraints) = constrained_particles();
+    let bodies = Bodies {
+        constraints,
+        ..Default::default()
+    };
+    check_bodies(particles, bodies, Settings::default());
+}
+
+/// A spinning box and a sphere falling into loose sand.
+fn clustered_particles() -> (Vec<Particle>, Bodies) {
+    let mut particles = random_particles(&mut seeded_rng(4), 64, 6.0);
+    let mut bodies = Bodies::default();
+
+    let crate_shape = Shape::Box {
+        half_size: Vec3::new(2.4, 1.6, 1.6),
+    };
+    let centre = Vec3::new(0.0, 8.0, 0.0);
+    let mut crate_particles =
+        crate_shape.voxelize(centre, DEFAULT_PARTICLE_RADIUS, DEFAULT_PARTICLE_MASS);
+    for particle in &mut crate_particles {
+        particle.old_position -= Vec3::Z.cross(particle.position - centre) * 0.02;
+    }
+    let rigid_body = RigidBody::new(crate_particles, particles.len() as u32, 1.0);
+    bodies.add_rigid_body(&mut particles, rigid_body);
+
+    let rock_shape = Shape::Sphere { radius: 2.0 };
+    let rock_particles = rock_shape.voxelize(
+        Vec3::new(4.0, 4.0, 0.0),
+        DEFAULT_PARTICLE_RADIUS,
+        DEFAULT_PARTICLE_MASS,
+    );
+    let rigid_body = RigidBody::new(rock_particles, particles.len() as u32, 0.5);
+    bodies.add_rigid_body(&mut particles, rigid_body);
+    (particles, bodies)
+}
+
+#[test]
+fn clusters_match_reference() {
+    let (particles, bodies) = clustered_particles();
+    check_bodies(particles, bodies, Settings::default());
+}
+
+/// Kills every fifth particle, as a sink would, leaving the dead slots among the live particles.
+fn kill_some(particles: &mut [Particle]) {
+    for particle in particles.iter_mut().step_by(5) {
+        particle.alive = 0;
+    }
+}
+
+#[test]
+fn dead_particles_match_reference() {
+    let brute_force_fluid = Settings {
+        neighbour_search: NeighbourSearch::BruteForce,
+        phase: Phase::Fluid,
+        ..Default::default()
+    };
+    for settings in [Settings::default(), brute_force_fluid] {
+        let (mut particles, constraints) = constrained_particles();
+        kill_some(&mut particles);
+        let bodies = Bodies {
+            constraints,
+            ..Default::default()
+        };
+        check_bodies(particles, bodies, settings);
+    }
+
+    let (mut particles, bodies) = clustered_particles();
+    kill_some(&mut particles);
+    check_bodies(particles, bodies, Settings::default());
+}
+
+/// A tilted slab and a ground plane, with a tunnel carved through a ball on top, combined in every
+/// way that colliders can be.
+fn colliders() -> Vec<Collider> {
+    vec![
+        Collider::cuboid(Vec3::new(0.0, -4.0, 0.0), Vec3::new(5.0, 1.0, 5.0))
+            .rotated(Quat::from_rotation_z(0.3)),
+        Collider::plane(Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.1, 1.0, 0.0)),
+        Collider {
+            smoothing: 1.0,
+            ..Collider::sphere(Vec3::new(3.0, 2.0, 0.0), 2.5)
+        },
+        Collider {
+            operation: OPERATION_SUBTRACTION,
+            smoothing: 0.5,
+            ..Collider::capsule(Vec3::new(3.0, 2.0, 0.0), 2.0, 1.0)
+                .rotated(Quat::from_rotation_x(std::f32::consts::FRAC_PI_2))
+        },
+        Collider {
+            operation: OPERATION_INTERSECTION,
+            ..Collider::cuboid(Vec3::ZERO, Vec3::splat(5.5))
+        },
+    ]
+}
+
+#[test]
+fn colliders_match_reference() {
+    let bodies = Bodies {
+        colliders: colliders(),
+        ..Default::default()
+    };
+    check_bodies(
+        random_particles(&mut seeded_rng(6), 128, 6.0),
+        bodies,
+        Settings::default(),
+    );
+}
+
+/// One of each kind of force field, overlapping so that some particles feel several.
+fn force_fields() -> Vec<ForceField> {
+    vec![
+        ForceField::attractor(Vec3::new(2.0, 0.0, 0.0), 5.0, 30.0),
+        ForceField {
+            falloff: 2.0,
+            ..ForceField::attractor(Vec3::new(-3.0, 2.0, 0.0), 4.0, -20.0)
+        },
+        ForceField::vortex(Vec3::ZERO, Vec3::new(0.2, 1.0, 0.0), 6.0, 15.0),
+        ForceField::wind(
+            Vec3::new(0.0, 3.0, 0.0),
+            Vec3::new(6.0, 2.0, 6.0),
+            Vec3::new(8.0, 2.0, 0.0),
+            4.0,
+        )
+        .rotated(Quat::from_rotation_y(0.5)),
+        ForceField::drag(Vec3::new(0.0, -3.0, 0.0), Vec3::new(6.0, 1.0, 6.0), 200.0),
+    ]
+}
+
+#[test]
+fn force_fields_match_reference() {
+    check_bodies(
+        random_particles(&mut seeded_rng(9), 128, 6.0),
+        Bodies {
+            force_fields: force_fields(),
+            ..Default::default()
+        },
+        Settings::default(),
+    );
+}
+
+/// Sums the mass grid of the particles that `runner` was populated with, checks it against the
+/// reference, and checks that its top level holds every particle.
+fn check_mass_grid(mut runner: HeadlessRunner, particles: &[Particle]) {
+    let configuration = *runner.resources.configuration();
+    runner
+        .pipeline
+        .bounds_partition
+        .calculate_bounds(&runner.device, &runner.queue, &runner.resources)
+        .unwrap();
+    runner
+        .pipeline
+        .mass_partition
+        .sum_masses(&runner.device, &runner.queue, &runner.resources)
+        .unwrap();
+    let mass_grid = read_buffer::<Vec<MassCell>>(
+        &runner.device,
+        &runner.queue,
+        runner.resources.mass_grid_buffer(),
+    )
+    .unwrap();
+
+    let bounds = reference::calculate_bounds(particles);
+    let expected_mass_grid = reference::build_mass_grid(particles, &bounds, &configuration);
+    assert_eq!(mass_grid.len(), expected_mass_grid.len());
+    for (index, (cell, expected_cell)) in mass_grid.iter().zip(&expected_mass_grid).enumerate() {
+        assert!(
+            (cell.mass - expected_cell.mass).abs() < TOLERANCE
+                && cell.centre.distance(expected_cell.centre) < TOLERANCE,
+            "mass cell {index} {cell:?} differs from {expected_cell:?}"
+        );
+    }
+    // Every level holds every particle, to within the rounding of their masses to fixed point
+    let total_mass: f32 = particles.iter().map(|particle| particle.mass).sum();
+    let top_cells = &mass_grid[mass_grid.len()
+        - configuration
+            .mass_grid_sizes()
+            .last()
+            .unwrap()
+            .element_product() as usize..];
+    let top_mass: f32 = top_cells.iter().map(|cell| cell.mass).sum();
+    assert!(
+        (top_mass - total_mass).abs() <= particles.len() as f32 / MASS_FIXED_POINT_SCALE,
+        "the top level holds {top_mass} of {total_mass}"
+    );
+}
+
+#[test]
+fn mass_grid_matches_reference() {
+    let particles = mixed_particles(3);
+    check_mass_grid(runner(&particles), &particles);
+}
+
+#[test]
+fn mass_grid_counts_the_particles_that_overflow_the_grid() {
+    let (runner, particles) = overflowing_runner();
+    check_mass_grid(runner, &particles);
+}
+
+#[test]
+fn self_gravity_matches_reference() {
+    check_contacts(
+        random_particles(&mut seeded_rng(10), 128, 6.0),
+        Settings {
+            self_gravity: Some(SelfGravity::new(0.5)),
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn self_gravity_approximates_summing_every_pair() {
+    let particles = random_particles(&mut seeded_rng(11), 512, 24.0);
+    let configuration = Configuration {
+        grid_size: UVec3::splat(16),
+        max_particles_per_grid_cell: 64,
+        ..Configuration::with_particles(particles.len() as u32)
+    };
+    let self_gravity = SelfGravity::new(1.0);
+    let bounds = reference::calculate_bounds(&particles);
+    let approximate =
+        reference::approximate_self_gravity(&self_gravity, &particles, &bounds, &configuration);
+
+    let mut error = 0.0;
+    let mut magnitude = 0.0;
+    for (particle_index, approximate) in approximate.iter().enumerate() {
+        let exact = reference::direct_self_gravity(&self_gravity, particle_index, &particles);
+        error += approximate.distance(exact);
+        magnitude += exact.length();
+    }
+    assert!(
+        error < magnitude * 0.02,
+        "the approximation is off by {} of the exact pull",
+        error / magnitude
+    );
+}
+
+/// The ratio of the longest to the shortest spread of `particles` along the axes, 1 for a ball.
+fn elongation(particles: &[Particle]) -> f32 {
+    let centre = particles
+        .iter()
+        .map(|particle| particle.position)
+        .sum::<Vec3>()
+        / particles.len() as f32;
+    let spread = particles
+        .iter()
+        .map(|particle| (particle.position - centre).powf(2.0))
+        .sum::<Vec3>()
+        .powf(0.5);
+    spread.max_element() / spread.min_element()
+}
+
+#[test]
+fn self_gravity_pulls_a_rod_of_grains_into_a_ball() {
+    // Scattered a little, but never overlapping
+    let mut rng = seeded_rng(12);
+    let mut particles = Vec::new();
+    for x in 0..16 {
+        for y in 0..4 {
+            for z in 0..4 {
+                let position = (Vec3::new(x as f32 - 7.5, y as f32 - 1.5, z as f32 - 1.5)
+                    + Vec3::new(
+                        rng.gen_range(-0.2..0.2),
+                        rng.gen_range(-0.2..0.2),
+                        rng.gen_range(-0.2..0.2),
+                    ))
+                    * 3.0;
+                particles.push(Particle::new(position, 0.8, 1.0));
+            }
+        }
+    }
+    assert!(elongation(&particles) > 3.5);
+
+    let configuration = Configuration {
+        grid_size: UVec3::splat(16),
+        max_particles_per_grid_cell: 64,
+        ..Configuration::with_particles(particles.len() as u32)
+    };
+    let mut runner = block_on(HeadlessRunner::new(configuration, true)).unwrap();
+    // Without friction to hold up its ends
+    let slippery = Material {
+        static_friction: 0.0,
+        kinetic_friction: 0.0,
+        ..Material::default()
+    };
+    runner
+        .resources
+        .set_materials(&runner.queue, &[slippery])
+        .unwrap();
+    runner.populate(&particles).unwrap();
+    runner
+        .pipeline
+        .simulation
+        .set_self_gravity(Some(SelfGravity::new(2.0)));
+    runner.run(420, DELTA_TIME, Vec3::ZERO).unwrap();
+
+    let elongation = elongation(&runner.particles().unwrap());
+    assert!(
+        elongation < 1.15,
+        "the clump is still {elongation} times longer than it is wide"
+    );
+}
+
+#[test]
+fn radial_gravity_matches_reference() {
+    // Centred off the particles, so that some are within its radius and some beyond
+    let centre = Vec3::new(2.0, -1.0, 0.0);
+    for gravity_model in [
+        GravityModel::Radial(RadialGravity::inverse_square(centre, 3.0, 20.0)),
+        GravityModel::UniformAndRadial(RadialGravity::constant(centre, 3.0, 10.0)),
+    ] {
+        check_contacts(
+            random_particles(&mut seeded_rng(13), 128, 6.0),
+            Settings {
+                gravity_model,
+                ..Default::default()
+            },
+        );
+    }
+}
+
+#[test]
+fn radial_gravity_weakens_beyond_its_radius_and_towards_its_centre() {
+    let bounds = Bounds {
+        min_x: -16,
+        min_y: -16,
+        min_z: -16,
+        max_x: 16,
+        max_y: 16,
+        max_z: 16,
+    };
+    // Half the radius, the radius and twice the radius from the centre, too far apart to touch
+    let fall = |radial_gravity: RadialGravity| {
+        let starts = [
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 4.0, 0.0),
+            Vec3::new(0.0, 0.0, -8.0),
+        ];
+        let mut particles = starts.map(|start| Particle::new(start, 0.8, 1.0));
+        reference::simulate(
+            &mut particles,
+            &mut [],
+            &mut [],
+            &Scene {
+                materials: &materials(),
+                configuration: Configuration::with_particles(starts.len() as u32),
+                settings: Settings {
+                    gravity_model: GravityModel::Radial(radial_gravity),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            &bounds,
+            DELTA_TIME,
+            // Ignored by radial gravity alone
+            GRAVITY,
+        );
+        let falls = starts.map(|start| start.length());
+        let mut distances = [0.0; 3];
+        for (index, particle) in particles.iter().enumerate() {
+            // Straight towards the centre
+            assert!(particle.position.normalize().dot(starts[index].normalize()) > 0.9999);
+            distances[index] = falls[index] - particle.position.length();
+        }
+        distances.map(|distance| distance / distances[1])
+    };
+
+    let inverse_square = fall(RadialGravity::inverse_square(Vec3::ZERO, 4.0, 10.0));
+    let constant = fall(RadialGravity::constant(Vec3::ZERO, 4.0, 10.0));
+    for (falls, expected) in [
+        (inverse_square, [0.5, 1.0, 0.25]),
+        (constant, [0.5, 1.0, 1.0]),
+    ] {
+        for (fall, expected) in falls.iter().zip(expected) {
+            assert!(
+                (fall - expected).abs() < 0.01,
+                "fell {falls:?} as far as at the radius, rather than {expected}"
+            );
+        }
+    }
+}
+
+#[test]
+fn sand_settles_onto_a_spherical_core() {
+    let core_radius = 4.0;
+    // Centred on the core, without the grains inside it
+    let particles: Vec<Particle> = lattice_particles(6, 2.2)
+        .into_iter()
+        .map(|particle| Particle::new(particle.position + 1.1, particle.radius, particle.mass))
+        .filter(|particle| particle.position.length() > core_radius + particle.radius)
+        .collect();
+    let mut runner = runner(&particles);
+    runner
+        .resources
+        .set_colliders(&runner.queue, &[Collider::sphere(Vec3::ZERO, core_radius)])
+        .unwrap();
+    runner
+        .pipeline
+        .simulation
+        .set_gravity_model(GravityModel::Radial(RadialGravity::inverse_square(
+            Vec3::ZERO,
+            core_radius,
+            GRAVITY.length(),
+        )));
+    // The uniform gravity of each step is ignored, so nothing falls off the core
+    runner.run(240, DELTA_TIME, GRAVITY).unwrap();
+    let particles = runner.particles().unwrap();
+
+    // Heaped on the core all around it, rather than fallen to one side of it
+    let centre = particles
+        .iter()
+        .map(|particle| particle.position)
+        .sum::<Vec3>()
+        / particles.len() as f32;
+    assert!(centre.length() < 0.5, "the grains settled around {centre}");
+    for particle in &particles {
+        let distance = particle.position.length();
+        assert!(
+            distance > core_radius + particle.radius - 0.05 && distance < core_radius + 5.0,
+            "a grain is {distance} from the centre of a core of {core_radius}"
+        );
+        let speed = (particle.position - particle.old_position).length() / DELTA_TIME;
+        assert!(speed < 0.5, "a grain is still moving at {speed}");
+    }
+}
+
+#[test]
+fn rotated_container_matches_reference() {
+    // Tilted so that the floor cuts through the bottom of the particles, which are pushed up and
+    // along it
+    let container = Container::oriented(
+        Vec3::new(0.0, 1.5, 0.0),
+        Vec3::new(6.0, 5.0, 6.0),
+        Quat::from_rotation_z(0.3) * Quat::from_rotation_x(0.2),
+    );
+    check_bodies(
+        random_particles(&mut seeded_rng(8), 128, 4.0),
+        Bodies {
+            container: Some(container),
+            ..Default::default()
+        },
+        Settings::default(),
+    );
+}
+
+#[test]
+fn odd_numbers_of_dispatches_per_step_match_reference() {
+    // Each step predicts once and solves twice, so the bounds and grid of consecutive steps are
+    // built from alternating particle buffers
+    check_contacts(
+        random_particles(&mut seeded_rng(0), 128, 6.0),
+        Settings {
+            substeps: 1,
+            iterations: 2,
+            ..Default::default()
+        },
+    );
+}
+
+fn check_grid_search_against_brute_force(mut particles: Vec<Particle>) {
+    let configuration = Configuration {
+        grid_size: UVec3::splat(8),
+        max_particles_per_grid_cell: 64,
+        ..Configuration::with_particles(particles.len() as u32)
+    };
+    let bounds = reference::calculate_bounds(&particles);
+
+    // Particles can be spawned overlapping deeply enough to be pushed further than the grid margin
+    // in one step, so let them separate first, then stop them so that they fly apart no further
+    for _ in 0..8 {
+        reference::simulate(
+            &mut particles,
+            &mut [],
+            &mut [],
+            &Scene {
+                materials: &materials(),
+                configuration,
+                settings: Settings {
+                    neighbour_search: NeighbourSearch::BruteForce,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            &bounds,
+            DELTA_TIME,
+            Vec3::ZERO,
+        );
+    }
+    for particle in &mut particles {
+        particle.old_position = particle.position;
+    }
+
+    let mut grid_particles = particles.clone();
+    let mut brute_force_particles = particles;
+    for (particles, neighbour_search) in [
+        (&mut grid_particles, NeighbourSearch::Grid),
+        (&mut brute_force_particles, NeighbourSearch::BruteForce),
+    ] {
+        reference::simulate(
+            particles,
+            &mut [],
+            &mut [],
+            &Scene {
+                materials: &materials(),
+                configuration,
+                settings: Settings {
+                    neighbour_search,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            &bounds,
+            DELTA_TIME,
+            GRAVITY,
+        );
+    }
+
+    // Contacts are summed in a different order, so the results only agree up to rounding
+    let divergence = reference::divergence(&grid_particles, &brute_force_particles);
+    assert!(
+        divergence.max() < TOLERANCE,
+        "diverged by up to {}",
+        divergence.max()
+    );
+}
+
+#[test]
+fn grid_search_finds_the_same_contacts_as_brute_force() {
+    check_grid_search_against_brute_force(random_particles(&mut seeded_rng(1), 128, 6.0));
+}
+
+#[test]
+fn grid_search_finds_the_same_contacts_between_mixed_grains_as_brute_force() {
+    check_grid_search_against_brute_force(mixed_particles(1));
+}
+
+#[test]
+fn heavier_particles_are_pushed_less() {
+    let light = Particle::new(Vec3::new(-0.5, 0.0, 0.0), 0.8, 1.0);
+    let heavy = Particle::new(Vec3::new(0.5, 0.0, 0.0), 0.8, 3.0);
+    let mut particles = vec![light, heavy];
+    let bounds = Bounds {
+        min_x: -4,
+        min_y: -4,
+        min_z: -4,
+        max_x: 4,
+        max_y: 4,
+        max_z: 4,
+    };
+    reference::simulate(
+        &mut particles,
+        &mut [],
+        &mut [],
+        &Scene {
+            materials: &materials(),
+            configuration: Configuration::with_particles(2),
+            settings: Settings {
+                substeps: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        &bounds,
+        DELTA_TIME,
+        Vec3::ZERO,
+    );
+
+    // Without gravity, particles at rest only move to resolve their contact
+    let light_displacement = particles[0].position.distance(light.position);
+    let heavy_displacement = particles[1].position.distance(heavy.position);
+    assert!(particles[0].position.x < light.position.x);
+    assert!(particles[1].position.x > heavy.position.x);
+    assert!(
+        (light_displacement - heavy_displacement * 3.0).abs() < 1e-4,
+        "light particle moved {light_displacement}, heavy particle moved {heavy_displacement}"
+    );
+}
+
+#[test]
+fn only_drag_slows_falling_particles() {
+    let sand = Particle::new(Vec3::new(-4.0, 0.0, 0.0), 0.8, 1.0);
+    let pebble = Particle {
+        material: PEBBLE,
+        ..Particle::new(Vec3::ZERO, 0.8, 1.0)
+    };
+    let feather = Particle {
+        material: 3,
+        ..Particle::new(Vec3::new(4.0, 0.0, 0.0), 0.8, 1.0)
+    };
+    let mut particles = vec![sand, pebble, feather];
+    let mut materials = materials();
+    materials.push(Material {
+        drag: 0.01,
+        ..Material::default()
+    });
+    let bounds = Bounds {
+        min_x: -8,
+        min_y: -64,
+        min_z: -4,
+        max_x: 8,
+        max_y: 4,
+        max_z: 4,
+    };
+    for _ in 0..16 {
+        reference::simulate(
+            &mut particles,
+            &mut [],
+            &mut [],
+            &Scene {
+                materials: &materials,
+                configuration: Configuration::with_particles(3),
+                ..Default::default()
+            },
+            &bounds,
+            DELTA_TIME,
+            GRAVITY,
+        );
+    }
+
+    // Friction only acts through contacts, so without drag every material falls alike
+    assert_eq!(particles[0].position.y, particles[1].position.y);
+    assert!(particles[2].position.y > particles[0].position.y);
+}
+
+/// Steps `particles`, which are far enough apart to never touch, for `steps` steps without gravity,
+/// so that only `force_fields` move them.
+fn drift(particles: &mut [Particle], force_fields: &[ForceField], steps: u32) {
+    let bounds = Bounds {
+        min_x: -16,
+        min_y: -16,
+        min_z: -16,
+        max_x: 16,
+        max_y: 16,
+        max_z: 16,
+    };
+    for _ in 0..steps {
+        reference::simulate(
+            particles,
+            &mut [],
+            &mut [],
+            &Scene {
+                materials: &materials(),
+                force_fields,
+                configuration: Configuration::with_particles(particles.len() as u32),
+                ..Default::default()
+            },
+            &bounds,
+            DELTA_TIME,
+            Vec3::ZERO,
+        );
+    }
+}
+
+#[test]
+fn attractors_pull_and_vortices_turn() {
+    let start = Vec3::new(3.0, 0.0, 0.0);
+    let mut attracted = [Particle::new(start, 0.8, 1.0)];
+    drift(
+        &mut attracted,
+        &[ForceField::attractor(Vec3::ZERO, 5.0, 10.0)],
+        8,
+    );
+    assert!(attracted[0].position.x < start.x);
+    assert_eq!(attracted[0].position.y, 0.0);
+    assert_eq!(attracted[0].position.z, 0.0);
+
+    let mut repelled = [Particle::new(start, 0.8, 1.0)];
+    drift(
+        &mut repelled,
+        &[ForceField::attractor(Vec3::ZERO, 5.0, -10.0)],
+        8,
+    );
+    assert!(repelled[0].position.x > start.x);
+
+    // Anticlockwise looking down the y axis turns +x towards -z
+    let mut turned = [Particle::new(start, 0.8, 1.0)];
+    drift(
+        &mut turned,
+        &[ForceField::vortex(Vec3::ZERO, Vec3::Y, 5.0, 10.0)],
+        8,
+    );
+    assert!(turned[0].position.z < 0.0);
+    assert_eq!(turned[0].position.y, 0.0);
+
+    // Beyond the radius, nothing moves
+    let mut outside = [Particle::new(Vec3::new(6.0, 0.0, 0.0), 0.8, 1.0)];
+    drift(
+        &mut outside,
+        &[ForceField::attractor(Vec3::ZERO, 5.0, 10.0)],
+        8,
+    );
+    assert_eq!(outside[0].position, Vec3::new(6.0, 0.0, 0.0));
+}
+
+#[test]
+fn wind_carries_particles_up_to_its_speed_and_drag_stops_them() {
+    let wind_velocity = Vec3::new(2.0, 0.0, 1.0);
+    let wind = ForceField::wind(Vec3::ZERO, Vec3::splat(8.0), wind_velocity, 10.0);
+    let mut particles = [
+        Particle::new(Vec3::ZERO, 0.8, 1.0),
+        Particle::new(Vec3::new(12.0, 0.0, 0.0), 0.8, 1.0),
+    ];
+    drift(&mut particles, &[wind], 60);
+    let substep_delta_time = DELTA_TIME / DEFAULT_SUBSTEPS as f32;
+    let velocity = (particles[0].position - particles[0].old_position) / substep_delta_time;
+    assert!(velocity.distance(wind_velocity) < 1e-3, "{velocity}");
+    assert_eq!(particles[1].position, Vec3::new(12.0, 0.0, 0.0));
+
+    let drag = ForceField::drag(Vec3::ZERO, Vec3::splat(8.0), 10.0);
+    drift(&mut particles, &[drag], 60);
+    let velocity = (particles[0].position - particles[0].old_position) / substep_delta_time;
+    assert!(velocity.length() < 1e-3, "{velocity}");
+}
+
+/// Pours a column of grains of `material` onto the floor of a container and returns them once they
+/// have settled.
+fn pour_pile(material: u32) -> Vec<Particle> {
+    let mut rng = seeded_rng(0);
+    let mut particles = Vec::new();
+    for layer in 0..24 {
+        for x in 0..3 {
+            for z in 0..3 {
+                let position = Vec3::new(x as f32 - 1.0, 0.0, z as f32 - 1.0) * 1.7
+                    + Vec3::new(
+                        rng.gen_range(-0.1..0.1),
+                        1.0 + layer as f32 * 1.7,
+                        rng.gen_range(-0.1..0.1),
+                    );
+                particles.push(Particle {
+                    material,
+                    ..Particle::new(position, 0.8, 1.0)
+                });
+            }
+        }
+    }
+
+    let configuration = Configuration {
+        grid_size: UVec3::splat(16),
+        max_particles_per_grid_cell: 64,
+        ..Configuration::with_particles(particles.len() as u32)
+    };
+    let mut runner = block_on(HeadlessRunner::new(configuration, true)).unwrap();
+    runner
+        .resources
+        .set_materials(&runner.queue, &materials())
+        .unwrap();
+    // With room to spread out across the floor
+    runner.resources.set_container(
+        &runner.queue,
+        Some(Container::aabb(
+            Vec3::new(-16.8, -0.8, -16.8),
+            Vec3::new(16.8, 48.0, 16.8),
+        )),
+    );
+    runner.populate(&particles).unwrap();
+    runner.run(480, DELTA_TIME, GRAVITY).unwrap();
+    runner.particles().unwrap()
+}
+
+/// The slope of a pile from its peak to where nine in ten of its grains lie within, in degrees.
+fn pile_angle(particles: &[Particle]) -> f32 {
+    let height = particles
+        .iter()
+        .map(|particle| particle.position.y)
+        .fold(0.0, f32::max);
+    let mut distances: Vec<f32> = particles
+        .iter()
+        .map(|particle| Vec3::new(particle.position.x, 0.0, particle.position.z).length())
+        .collect();
+    distances.sort_by(f32::total_cmp);
+    let radius = distances[distances.len() * 9 / 10];
+    (height / radius).atan().to_degrees()
+}
+
+#[test]
+fn grains_stick_to_slopes_below_their_angle_of_repose() {
+    let angle_of_repose = Material::default().static_friction.atan();
+    let slide = |slope: f32| {
+        let mut runner = runner(&[Particle::new(Vec3::ZERO, 0.8, 1.0)]);
+        // Resting on the floor, with room to slide far down the slope
+        runner.resources.set_container(
+            &runner.queue,
+            Some(Container::aabb(
+                Vec3::new(-4.0, -0.8, -4.0),
+                Vec3::new(24.0, 4.0, 4.0),
+            )),
+        );
+        // Tilting gravity is the same as tilting the floor
+        let gravity = Vec3::new(slope.sin(), -slope.cos(), 0.0) * GRAVITY.length();
+        runner.run(60, DELTA_TIME, gravity).unwrap();
+        runner.particles().unwrap()[0].position.x
+    };
+
+    let shallow = slide(angle_of_repose - 2f32.to_radians());
+    let steep = slide(angle_of_repose + 2f32.to_radians());
+    assert!(
+        shallow.abs() < 1e-3,
+        "slid {shallow} down a shallower slope"
+    );
+    assert!(steep > 0.1, "slid only {steep} down a steeper slope");
+}
+
+#[test]
+fn more_frictional_grains_pour_steeper_piles() {
+    let sand = pour_pile(0);
+    let pebbles = pour_pile(PEBBLE);
+
+    for pile in [&sand, &pebbles] {
+        for (index, particle) in pile.iter().enumerate() {
+            for neighbour in &pile[index + 1..] {
+                let penetration = particle.radius + neighbour.radius
+                    - particle.position.distance(neighbour.position);
+                assert!(
+                    penetration < 0.05,
+                    "particles interpenetrate by {penetration}"
+                );
+            }
+        }
+    }
+    let sand_angle = pile_angle(&sand);
+    let pebble_angle = pile_angle(&pebbles);
+    assert!(
+        sand_angle > pebble_angle + 10.0,
+        "sand piled at {sand_angle} degrees, pebbles at {pebble_angle}"
+    );
+    // The grains cannot roll, so they stack at least about as steeply as they can rest on a slope
+    let materials = materials();
+    for (angle, material) in [
+        (sand_angle, &materials[0]),
+        (pebble_angle, &materials[PEBBLE as usize]),
+    ] {
+        let angle_of_repose = material.static_friction.atan().to_degrees();
+        assert!(
+            angle > angle_of_repose * 0.75,
+            "piled at {angle} degrees, below the angle of repose {angle_of_repose}"
+        );
+    }
+}
+
+#[test]
+fn piles_come_to_rest_without_interpenetrating() {
+    let particles = random_particles(&mut seeded_rng(3), 256, 6.0);
+    let mut runner = runner(&particles);
+    runner.resources.set_container(
+        &runner.queue,
+        Some(Container::aabb(Vec3::splat(-6.8), Vec3::splat(6.8))),
+    );
+    runner.run(480, DELTA_TIME, GRAVITY).unwrap();
+    let particles = runner.particles().unwrap();
+
+    let jitter = particles
+        .iter()
+        .map(|particle| particle.position.distance(particle.old_position))
+        .fold(0.0, f32::max);
+    assert!(jitter < 0.01, "particles still move {jitter} per substep");
+    for (index, particle) in particles.iter().enumerate() {
+        for neighbour in &particles[index + 1..] {
+            let penetration =
+                particle.radius + neighbour.radius - particle.position.distance(neighbour.position);
+            assert!(
+                penetration < 0.05,
+                "particles interpenetrate by {penetration}"
+            );
+        }
+    }
+}
+
+#[test]
+fn fluid_column_holds_its_height_under_pressure() {
+    let mut particles = lattice_particles(6, 2.0 * DEFAULT_PARTICLE_RADIUS);
+    assign_material(&mut particles, WATER, &Material::fluid());
+    let height = |particles: &[Particle]| {
+        let (min, max) = particles
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), particle| {
+                (min.min(particle.position.y), max.max(particle.position.y))
+            });
+        max - min
+    };
+    let initial_height = height(&particles);
+    let mut runner = runner(&particles);
+    // Just wide enough to hold the column
+    runner.resources.set_container(
+        &runner.queue,
+        Some(Container::aabb(Vec3::splat(-5.6), Vec3::splat(4.0))),
+    );
+    runner.run(240, DELTA_TIME, GRAVITY).unwrap();
+    let particles = runner.particles().unwrap();
+
+    // Without pressure the particles would pile up at the bottom of the container. The particles
+    // along the walls are missing the neighbours beyond them, so the column still compresses
+    // somewhat
+    let final_height = height(&particles);
+    assert!(
+        final_height > initial_height * 0.6,
+        "the column sank from {initial_height} to {final_height}"
+    );
+    for (index, particle) in particles.iter().enumerate() {
+        for neighbour in &particles[index + 1..] {
+            let distance = particle.position.distance(neighbour.position);
+            assert!(distance > particle.radius, "particles are {distance} apart");
+        }
+    }
+}
+
+#[test]
+fn piles_rest_on_colliders_without_sinking_into_them() {
+    let mut particles = random_particles(&mut seeded_rng(7), 128, 4.0);
+    for particle in &mut particles {
+        particle.position.y += 12.0;
+        particle.old_position = particle.position;
+    }
+    let centre = Vec3::new(0.0, 4.0, 0.0);
+    let radius = 4.0;
+    let mut runner = runner(&particles);
+    runner
+        .resources
+        .set_colliders(&runner.queue, &[Collider::sphere(centre, radius)])
+        .unwrap();
+    // With room to slide off the ball
+    runner.resources.set_container(
+        &runner.queue,
+        Some(Container::aabb(
+            Vec3::new(-12.8, -0.8, -12.8),
+            Vec3::new(12.8, 24.0, 12.8),
+        )),
+    );
+    runner.run(240, DELTA_TIME, GRAVITY).unwrap();
+    let particles = runner.particles().unwrap();
+
+    let mut resting_on_ball = 0;
+    for particle in &particles {
+        let distance = particle.position.distance(centre) - radius;
+        assert!(
+            distance > particle.radius - 0.05,
+            "particle {particle:?} sank {} into the ball",
+            particle.radius - distance
+        );
+        if distance < particle.radius + 0.05 && particle.position.y > centre.y {
+            resting_on_ball += 1;
+        }
+    }
+    // Sand sticks to the top of the ball rather than all of it sliding off
+    assert!(resting_on_ball > 0, "no particle rests on the ball");
+}
+
+#[test]
+fn soft_bodies_keep_their_shape() {
+    let blob = Lattice {
+        origin: Vec3::ZERO,
+        size: UVec3::splat(4),
+        spacing: 2.0 * DEFAULT_PARTICLE_RADIUS,
+    };
+    let particles = blob.particles(DEFAULT_PARTICLE_RADIUS, DEFAULT_PARTICLE_MASS);
+    let constraints = soft_body_constraints(&blob, 0, 1.0);
+    let mut runner = runner(&particles);
+    runner
+        .resources
+        .set_constraints(&runner.device, &runner.queue, &constraints)
+        .unwrap();
+    // Spin the blob so that it has to hold itself together
+    let mut spinning_particles = particles.clone();
+    for particle in &mut spinning_particles {
+        particle.old_position -= Vec3::Y.cross(particle.position - Vec3::splat(2.4)) * 0.05;
+    }
+    runner.populate(&spinning_particles).unwrap();
+    runner.run(120, DELTA_TIME, Vec3::ZERO).unwrap();
+    let particles = runner.particles().unwrap();
+
+    for constraint in &constraints {
+        let length = particles[constraint.a as usize]
+            .position
+            .distance(particles[constraint.b as usize].position);
+        let strain = (length - constraint.rest_length).abs() / constraint.rest_length;
+        assert!(strain < 0.05, "constraint {constraint:?} is {length} long");
+    }
+}
+
+#[test]
+fn overstretched_constraints_break() {
+    let a = Particle::new(Vec3::new(-0.8, 0.0, 0.0), 0.8, 1.0);
+    let b = Particle::new(Vec3::new(0.8, 0.0, 0.0), 0.8, 1.0);
+    // Flying apart far faster than the constraint can hold them together
+    let particles = [
+        Particle {
+            old_position: a.position + Vec3::X,
+            ..a
+        },
+        Particle {
+            old_position: b.position - Vec3::X,
+            ..b
+        },
+    ];
+    let constraints = [DistanceConstraint {
+        break_strain: 0.5,
+        ..DistanceConstraint::new(0, 1, 1.6, 0.1)
+    }];
+    let mut runner = runner(&particles);
+    runner
+        .resources
+        .set_constraints(&runner.device, &runner.queue, &constraints)
+        .unwrap();
+    runner.run(4, DELTA_TIME, Vec3::ZERO).unwrap();
+
+    let constraints = read_buffer::<Vec<DistanceConstraint>>(
+        &runner.device,
+        &runner.queue,
+        runner.resources.constraint_buffer(),
+    )
+    .unwrap();
+    assert_eq!(constraints[0].broken, 1);
+}
+
+#[test]
+fn rigid_bodies_keep_their_shape_while_tumbling() {
+    let mut particles = random_particles(&mut seeded_rng(5), 256, 8.0);
+    let mut bodies = Bodies::default();
+    let centre = Vec3::new(0.0, 10.0, 0.0);
+    let mut crate_particles = Shape::Box {
+        half_size: Vec3::splat(2.4),
+    }
+    .voxelize(centre, DEFAULT_PARTICLE_RADIUS, DEFAULT_PARTICLE_MASS);
+    // Thrown sideways with a spin, so that it lands on an edge and tips over
+    for particle in &mut crate_particles {
+        particle.old_position -=
+            Vec3::X * 0.05 + Vec3::new(1.0, 0.0, 1.0).cross(particle.position - centre) * 0.03;
+    }
+    let first_index = particles.len();
+    let rigid_body = RigidBody::new(crate_particles, first_index as u32, 1.0);
+    let rest_positions = rigid_body.particles.clone();
+    bodies.add_rigid_body(&mut particles, rigid_body);
+
+    // With room above the floor for the crate to fall onto the other particles
+    bodies.container = Some(Container::aabb(
+        Vec3::splat(-8.8),
+        Vec3::new(8.8, 16.0, 8.8),
+    ));
+
+    let mut runner = runner(&particles);
+    bodies.upload(&mut runner);
+    runner.run(120, DELTA_TIME, GRAVITY).unwrap();
+    let particles = runner.particles().unwrap();
+    let members = &particles[first_index..];
+
+    for (a, rest_a) in members.iter().zip(&rest_positions) {
+        for (b, rest_b) in members.iter().zip(&rest_positions) {
+            let distance = a.position.distance(b.position);
+            let rest_distance = rest_a.position.distance(rest_b.position);
+            assert!(
+                (distance - rest_distance).abs() < 0.05,
+                "members {rest_distance} apart at rest are {distance} apart"
+            );
+        }
+    }
+    let clusters = read_buffer::<Vec<Cluster>>(
+        &runner.device,
+        &runner.queue,
+        runner.resources.cluster_buffer(),
+    )
+    .unwrap();
+    let angle = Quat::from_vec4(clusters[0].rotation).angle_between(Quat::IDENTITY);
+    assert!(angle > 0.1, "the crate only turned by {angle} radians");
+}
@@ -0,0 +1,295 @@
+//! Checks that emitters fill particle slots and that sinks free them, on the fallback adapter.
+
+use futures::executor::block_on;
+use glam::{UVec3, Vec3};
+use sol::common::{DEFAULT_PARTICLE_MASS, DEFAULT_PARTICLE_RADIUS};
+use sol::headless::{write_particles, HeadlessRunner};
+use sol::sdf::OPERATION_SUBTRACTION;
+use sol::{Collider, Configuration, Container, Emitter, Material, Particle, Sink};
+
+const DELTA_TIME: f32 = 1.0 / 60.0;
+const GRAVITY: Vec3 = Vec3::new(0.0, -9.8, 0.0);
+
+/// A runner with room for `particle_capacity` particles that starts without any.
+fn empty_runner(particle_capacity: u32) -> HeadlessRunner {
+    let configuration = Configuration {
+        grid_size: UVec3::splat(8),
+        max_particles_per_grid_cell: 64,
+        ..Configuration::with_particles(particle_capacity)
+    };
+    let mut runner = block_on(HeadlessRunner::new(configuration, true)).unwrap();
+    runner
+        .resources
+        .set_materials(&runner.queue, &[Material::default(), Material::fluid()])
+        .unwrap();
+    runner.populate(&[]).unwrap();
+    runner
+}
+
+#[test]
+fn emitters_fill_empty_slots() {
+    let mut runner = empty_runner(64);
+    let centre = Vec3::new(0.0, 4.0, 0.0);
+    let half_size = Vec3::splat(6.0);
+    let emitter = Emitter::cuboid(centre, half_size, 60.0).made_of(1, &Material::fluid());
+    runner.pipeline.emission.set_emitters(&[emitter]).unwrap();
+
+    runner.run(10, DELTA_TIME, Vec3::ZERO).unwrap();
+
+    let counters = runner.particle_counters().unwrap();
+    assert_eq!(counters.particle_count, 10);
+    assert_eq!(counters.alive_count, 10);
+    assert_eq!(counters.emitted_count, 10);
+    let particles = runner.particles().unwrap();
+    for particle in &particles[..10] {
+        assert!(particle.is_alive());
+        assert_eq!(particle.material, 1);
+        assert_eq!(particle.mass, emitter.mass);
+        // Emitted at rest and without gravity, so only contacts move them
+        let offset = (particle.position - centre).abs();
+        assert!(
+            offset.cmple(half_size + DEFAULT_PARTICLE_RADIUS).all(),
+            "{particle:?} was emitted outside of the emitter"
+        );
+    }
+    assert!(particles[10..].iter().all(|particle| !particle.is_alive()));
+}
+
+#[test]
+fn emitters_stop_once_every_slot_is_full() {
+    let mut runner = empty_runner(32);
+    let emitter = Emitter::cuboid(Vec3::ZERO, Vec3::splat(8.0), 600.0);
+    runner.pipeline.emission.set_emitters(&[emitter]).unwrap();
+
+    runner.run(10, DELTA_TIME, Vec3::ZERO).unwrap();
+
+    let counters = runner.particle_counters().unwrap();
+    assert_eq!(counters.particle_count, 32);
+    assert_eq!(counters.alive_count, 32);
+    assert_eq!(counters.free_count, 0);
+    assert_eq!(runner.resources.configuration().particle_capacity, 32);
+}
+
+#[test]
+fn emitters_keep_fractions_of_a_particle_between_steps() {
+    let mut runner = empty_runner(64);
+    // A quarter of a particle per step, spread wide enough that none are dropped for overlapping
+    let emitter = Emitter::cuboid(Vec3::ZERO, Vec3::splat(8.0), 15.0);
+    runner.pipeline.emission.set_emitters(&[emitter]).unwrap();
+
+    runner.run(12, DELTA_TIME, Vec3::ZERO).unwrap();
+
+    assert_eq!(runner.particle_counters().unwrap().emitted_count, 3);
+}
+
+/// A row of particles at rest on the ground, the first half of which are inside `sink`.
+fn particles_beside_a_sink() -> (Vec<Particle>, Sink) {
+    let particles: Vec<Particle> = (0..16)
+        .map(|i| {
+            Particle::new(
+                Vec3::new(i as f32 * 2.0 - 15.0, DEFAULT_PARTICLE_RADIUS, 0.0),
+                DEFAULT_PARTICLE_RADIUS,
+                DEFAULT_PARTICLE_MASS,
+            )
+        })
+        .collect();
+    let sink = Sink::cuboid(Vec3::new(-8.0, 0.0, 0.0), Vec3::new(8.0, 4.0, 4.0));
+    (particles, sink)
+}
+
+#[test]
+fn sinks_delete_the_particles_inside_them() {
+    let (particles, sink) = particles_beside_a_sink();
+    let mut runner = empty_runner(32);
+    runner.populate(&particles).unwrap();
+    runner.pipeline.emission.set_sinks(&[sink]).unwrap();
+
+    runner.run(1, DELTA_TIME, Vec3::ZERO).unwrap();
+
+    let counters = runner.particle_counters().unwrap();
+    assert_eq!(counters.alive_count, 8);
+    assert_eq!(counters.free_count, 8);
+    assert_eq!(counters.particle_count, 16);
+    let particles = runner.particles().unwrap();
+    for (index, particle) in particles[..16].iter().enumerate() {
+        assert_eq!(particle.is_alive(), index >= 8, "particle {index}");
+    }
+}
+
+#[test]
+fn slots_freed_at_the_end_are_no_longer_in_use() {
+    let (mut particles, sink) = particles_beside_a_sink();
+    particles.reverse();
+    let mut runner = empty_runner(32);
+    runner.populate(&particles).unwrap();
+    runner.pipeline.emission.set_sinks(&[sink]).unwrap();
+
+    runner.run(1, DELTA_TIME, Vec3::ZERO).unwrap();
+
+    let counters = runner.particle_counters().unwrap();
+    assert_eq!(counters.alive_count, 8);
+    assert_eq!(counters.particle_count, 8);
+    assert_eq!(counters.free_count, 0);
+}
+
+#[test]
+fn emitters_reuse_the_slots_that_sinks_free() {
+    let (particles, sink) = particles_beside_a_sink();
+    let mut runner = empty_runner(32);
+    runner.populate(&particles).unwrap();
+    runner.pipeline.emission.set_sinks(&[sink]).unwrap();
+    runner.run(1, DELTA_TIME, Vec3::ZERO).unwrap();
+
+    let emitter = Emitter::cuboid(Vec3::new(0.0, 8.0, 0.0), Vec3::splat(4.0), 60.0)
+        .made_of(1, &Material::fluid());
+    runner.pipeline.emission.set_emitters(&[emitter]).unwrap();
+    runner.pipeline.emission.set_sinks(&[]).unwrap();
+    runner.run(8, DELTA_TIME, Vec3::ZERO).unwrap();
+
+    let counters = runner.particle_counters().unwrap();
+    assert_eq!(counters.alive_count, 16);
+    assert_eq!(counters.free_count, 0);
+    assert_eq!(counters.particle_count, 16);
+    let particles = runner.particles().unwrap();
+    assert!(particles[..8].iter().all(|particle| particle.material == 1));
+    assert!(particles[8..16]
+        .iter()
+        .all(|particle| particle.material == 0));
+}
+
+#[test]
+fn emitters_forget_their_particles_once_sinks_delete_them() {
+    let mut runner = empty_runner(32);
+    // Close enough for a particle of either emitter to block the other
+    let near = Vec3::new(0.0, 4.0, 0.0);
+    let far = near + Vec3::new(1.2, 0.0, 0.0);
+    runner
+        .pipeline
+        .emission
+        .set_emitters(&[
+            Emitter::point(far, 60.0),
+            Emitter::point(near, 60.0),
+            // Keeps the slots of the others in use once they are freed
+            Emitter::point(Vec3::new(8.0, 4.0, 0.0), 60.0),
+        ])
+        .unwrap();
+    let emit = |runner: &mut HeadlessRunner| {
+        runner
+            .pipeline
+            .emission
+            .emit(
+                &runner.device,
+                &runner.queue,
+                &runner.resources,
+                DELTA_TIME,
+                1,
+            )
+            .unwrap();
+    };
+    emit(&mut runner);
+
+    // The first emitter's next particle reuses the slot of the second emitter's deleted particle,
+    // which must not block the second emitter as if it were its own
+    runner
+        .pipeline
+        .emission
+        .set_sinks(&[Sink::sphere(near, 0.5), Sink::sphere(far, 0.5)])
+        .unwrap();
+    emit(&mut runner);
+
+    let counters = runner.particle_counters().unwrap();
+    assert_eq!(counters.emitted_count, 5);
+    assert_eq!(counters.alive_count, 3);
+}
+
+#[test]
+fn written_particles_record_their_material_and_whether_they_are_alive() {
+    let (particles, sink) = particles_beside_a_sink();
+    let mut runner = empty_runner(32);
+    runner.populate(&particles).unwrap();
+    runner.pipeline.emission.set_sinks(&[sink]).unwrap();
+    let emitter = Emitter::cuboid(Vec3::new(8.0, 8.0, 0.0), Vec3::splat(4.0), 60.0)
+        .made_of(1, &Material::fluid());
+    runner.pipeline.emission.set_emitters(&[emitter]).unwrap();
+    runner.run(4, DELTA_TIME, Vec3::ZERO).unwrap();
+    let particles = runner.particles().unwrap();
+
+    let mut csv = Vec::new();
+    write_particles(&mut csv, &particles).unwrap();
+
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    let columns: Vec<&str> = lines.next().unwrap().split(',').collect();
+    let column = |name| columns.iter().position(|&column| column == name).unwrap();
+    let (position_x, material, alive) = (column("position_x"), column("material"), column("alive"));
+    let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+    assert_eq!(rows.len(), particles.len());
+    for (row, particle) in rows.iter().zip(&particles) {
+        assert_eq!(row.len(), columns.len());
+        assert_eq!(row[position_x].parse::<f32>().unwrap(), particle.position.x);
+        assert_eq!(row[material].parse::<u32>().unwrap(), particle.material);
+        assert_eq!(row[alive] == "1", particle.is_alive());
+    }
+    let alive_rows = |material_index: &str| {
+        rows.iter()
+            .filter(|row| row[alive] == "1" && row[material] == material_index)
+            .count()
+    };
+    // The sink deleted half of the row, and the emitter has filled some of the freed slots with
+    // fluid
+    assert_eq!(alive_rows("0"), 8);
+    assert_eq!(alive_rows("1"), 4);
+    assert!(rows[..8]
+        .iter()
+        .any(|row| row[alive] == "1" && row[material] == "1"));
+}
+
+#[test]
+fn sand_poured_into_a_hopper_drains_through_the_hole() {
+    let mut runner = empty_runner(512);
+    runner.resources.set_container(
+        &runner.queue,
+        Some(Container::aabb(Vec3::splat(-8.0), Vec3::splat(8.0))),
+    );
+    runner
+        .resources
+        .set_colliders(
+            &runner.queue,
+            &[
+                Collider::cuboid(Vec3::ZERO, Vec3::new(8.0, 1.0, 8.0)),
+                Collider {
+                    operation: OPERATION_SUBTRACTION,
+                    ..Collider::capsule(Vec3::ZERO, 2.0, 4.0)
+                },
+            ],
+        )
+        .unwrap();
+    let emitter = Emitter {
+        velocity: Vec3::new(0.0, -10.0, 0.0),
+        ..Emitter::disc(Vec3::new(0.0, 6.0, 0.0), 2.5, 12.0)
+    };
+    runner.pipeline.emission.set_emitters(&[emitter]).unwrap();
+    runner
+        .pipeline
+        .emission
+        .set_sinks(&[Sink::cuboid(
+            Vec3::new(0.0, -8.0, 0.0),
+            Vec3::new(8.0, 2.0, 8.0),
+        )])
+        .unwrap();
+
+    runner.run(120, DELTA_TIME, GRAVITY).unwrap();
+    let halfway = runner.particle_counters().unwrap();
+    runner.run(120, DELTA_TIME, GRAVITY).unwrap();
+    let end = runner.particle_counters().unwrap();
+
+    assert_eq!(end.emitted_count, 48);
+    assert!(end.alive_count < end.emitted_count / 2, "{end:?}");
+    // As much drains as is poured in
+    assert!(
+        end.alive_count <= halfway.alive_count + 4,
+        "{halfway:?} then {end:?}"
+    );
+    // Slots are reused rather than the particles growing to fill the capacity
+    assert!(end.particle_count < end.emitted_count / 2, "{end:?}");
+}
@@ -0,0 +1,188 @@
+//! Checks that failures are returned as [`SolError`]s rather than panicking.
+
+use futures::executor::block_on;
+use glam::Vec3;
+use sol::common::{MAX_CONSTRAINTS_PER_PARTICLE, MAX_MATERIALS};
+use sol::debug::read_buffer;
+use sol::emission::{Emitter, Sink, MAX_EMITTERS, MAX_SINKS};
+use sol::headless::HeadlessRunner;
+use sol::profiling::profile;
+use sol::sdf::MAX_COLLIDERS;
+use sol::simulation::MAX_FORCE_FIELDS;
+use sol::spawn::{random_particles, seeded_rng};
+use sol::wgpu_utilities::submit;
+use sol::{
+    Cluster, Collider, Configuration, DistanceConstraint, ForceField, Material, Particle,
+    Resources, SolError,
+};
+use wgpu::{BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Features};
+use wgpu_core::device::DeviceError;
+
+fn runner() -> HeadlessRunner {
+    block_on(HeadlessRunner::new(Configuration::with_particles(16), true)).unwrap()
+}
+
+#[test]
+fn reading_an_uncopyable_buffer_is_a_validation_error() {
+    let runner = runner();
+    let buffer = runner.device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: 16,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+    let result = read_buffer::<Vec<u32>>(&runner.device, &runner.queue, &buffer);
+    assert!(matches!(result, Err(SolError::Validation(_))), "{result:?}");
+}
+
+#[test]
+fn profiling_without_timestamp_queries_is_reported() {
+    let runner = runner();
+    if runner.device.features().contains(Features::TIMESTAMP_QUERY) {
+        return;
+    }
+    let result = block_on(profile(&runner.device, &runner.queue, |_| {}));
+    assert!(matches!(
+        result,
+        Err(SolError::MissingFeatures(Features::TIMESTAMP_QUERY))
+    ));
+}
+
+#[test]
+fn recovering_from_a_lost_device_restores_the_populated_particles() {
+    let mut runner = runner();
+    let particles: Vec<Particle> = random_particles(&mut seeded_rng(0), 16, 4.0);
+    runner.populate(&particles).unwrap();
+    runner.pipeline.simulation.set_substeps(3);
+    let materials = [
+        Material::default(),
+        Material {
+            restitution: 0.5,
+            ..Default::default()
+        },
+    ];
+    runner
+        .resources
+        .set_materials(&runner.queue, &materials)
+        .unwrap();
+
+    runner.step(1.0 / 60.0, Vec3::NEG_Y).unwrap();
+
+    // `wgpu` cannot lose a device on demand, so the device is handed the error that it raises
+    // once it has been lost, as its uncaptured error handler would be
+    runner.device.report_error(wgpu::Error::Validation {
+        source: Box::new(DeviceError::Lost),
+        description: DeviceError::Lost.to_string(),
+    });
+    assert!(runner.device.is_lost());
+    let command_buffer = runner
+        .device
+        .create_command_encoder(&CommandEncoderDescriptor { label: None })
+        .finish();
+    let result = submit(&runner.device, &runner.queue, command_buffer);
+    assert!(matches!(result, Err(SolError::DeviceLost)), "{result:?}");
+    let result = runner.step(1.0 / 60.0, Vec3::NEG_Y);
+    assert!(matches!(result, Err(SolError::DeviceLost)), "{result:?}");
+
+    block_on(runner.recover()).unwrap();
+    assert!(!runner.device.is_lost());
+
+    assert_eq!(runner.pipeline.simulation.substeps(), 3);
+    assert_eq!(runner.resources.materials(), materials);
+    assert_eq!(runner.particles().unwrap(), particles);
+}
+
+fn assert_invalid<T>(result: Result<T, SolError>) {
+    let error = result.err();
+    assert!(
+        matches!(error, Some(SolError::InvalidConfiguration(_))),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn more_particles_than_the_capacity_are_rejected() {
+    let mut runner = runner();
+    let configuration = Configuration {
+        particle_count: 32,
+        ..*runner.resources.configuration()
+    };
+    assert_invalid(Resources::new(&runner.device, &runner.queue, configuration));
+    assert_invalid(
+        runner
+            .resources
+            .configure(&runner.device, &runner.queue, configuration),
+    );
+    assert_invalid(runner.resources.set_particle_count(&runner.queue, 17));
+    assert_invalid(runner.populate(&random_particles(&mut seeded_rng(0), 17, 4.0)));
+    assert_eq!(runner.resources.configuration().particle_count, 16);
+}
+
+#[test]
+fn too_many_materials_or_colliders_are_rejected() {
+    let mut runner = runner();
+    let materials = vec![Material::default(); MAX_MATERIALS as usize + 1];
+    assert_invalid(runner.resources.set_materials(&runner.queue, &materials));
+    let colliders = vec![Collider::sphere(Vec3::ZERO, 1.0); MAX_COLLIDERS as usize + 1];
+    assert_invalid(runner.resources.set_colliders(&runner.queue, &colliders));
+    assert!(runner.resources.colliders().is_empty());
+}
+
+#[test]
+fn invalid_constraints_are_rejected() {
+    let mut runner = runner();
+    let mut set_constraints = |constraints: &[DistanceConstraint]| {
+        runner
+            .resources
+            .set_constraints(&runner.device, &runner.queue, constraints)
+    };
+    assert_invalid(set_constraints(&[DistanceConstraint::new(3, 3, 1.0, 1.0)]));
+    assert_invalid(set_constraints(&[DistanceConstraint::new(0, 16, 1.0, 1.0)]));
+    let crowded: Vec<DistanceConstraint> = (1..=MAX_CONSTRAINTS_PER_PARTICLE + 1)
+        .map(|other| DistanceConstraint::new(0, other, 1.0, 1.0))
+        .collect();
+    assert_invalid(set_constraints(&crowded));
+}
+
+#[test]
+fn invalid_clusters_are_rejected() {
+    let mut runner = runner();
+    let members = vec![Particle::new(Vec3::ZERO, 0.5, 1.0); 4];
+    let cluster = Cluster::new(&members, 0, 1.0);
+    let rest_offsets = cluster.rest_offsets(&members);
+    let mut set_clusters = |clusters: &[Cluster], rest_offsets: &[Vec3]| {
+        runner
+            .resources
+            .set_clusters(&runner.device, &runner.queue, clusters, rest_offsets)
+    };
+    assert_invalid(set_clusters(&[cluster], &rest_offsets[1..]));
+    let beyond = Cluster::new(&members, 14, 1.0);
+    assert_invalid(set_clusters(&[beyond], &rest_offsets));
+    let overlapping = Cluster::new(&members, 2, 1.0);
+    assert_invalid(set_clusters(
+        &[cluster, overlapping],
+        &[rest_offsets.clone(), rest_offsets.clone()].concat(),
+    ));
+}
+
+#[test]
+fn too_many_emitters_or_sinks_are_rejected() {
+    let mut runner = runner();
+    let emission = &mut runner.pipeline.emission;
+    let emitters = vec![Emitter::point(Vec3::ZERO, 1.0); MAX_EMITTERS as usize + 1];
+    assert_invalid(emission.set_emitters(&emitters));
+    assert!(emission.emitters().is_empty());
+    let sinks = vec![Sink::sphere(Vec3::ZERO, 1.0); MAX_SINKS as usize + 1];
+    assert_invalid(emission.set_sinks(&sinks));
+    assert!(emission.sinks().is_empty());
+}
+
+#[test]
+fn too_many_force_fields_are_rejected() {
+    let mut runner = runner();
+    let simulation = &mut runner.pipeline.simulation;
+    let force_fields =
+        vec![ForceField::drag(Vec3::ZERO, Vec3::ONE, 1.0); MAX_FORCE_FIELDS as usize + 1];
+    assert_invalid(simulation.set_force_fields(&force_fields));
+    assert!(simulation.force_fields().is_empty());
+}
@@ -0,0 +1,60 @@
+//! Checks how [`FixedTimestep`] and [`Transport`] turn elapsed time into steps.
+
+use sol::{FixedTimestep, Transport};
+
+const STEP: f32 = 0.25;
+
+#[test]
+fn carries_partial_steps_over() {
+    let mut timestep = FixedTimestep::new(STEP, 4);
+    assert_eq!(timestep.advance(0.125), 0);
+    assert_eq!(timestep.alpha(), 0.5);
+    assert_eq!(timestep.advance(0.125), 1);
+    assert_eq!(timestep.advance(0.5), 2);
+    assert_eq!(timestep.alpha(), 0.0);
+}
+
+#[test]
+fn caps_catch_up_steps() {
+    let mut timestep = FixedTimestep::new(STEP, 4);
+    assert_eq!(timestep.advance(10.0), 4);
+    // The backlog beyond the cap is dropped rather than run over the following frames
+    assert_eq!(timestep.advance(0.0), 0);
+    assert_eq!(timestep.alpha(), 0.0);
+}
+
+#[test]
+fn ignores_negative_time() {
+    let mut timestep = FixedTimestep::new(STEP, 4);
+    assert_eq!(timestep.advance(-1.0), 0);
+    assert_eq!(timestep.advance(STEP), 1);
+}
+
+#[test]
+fn transport_pauses_without_accumulating() {
+    let mut timestep = FixedTimestep::new(STEP, 4);
+    let mut transport = Transport::default();
+    transport.set_paused(true);
+    assert_eq!(transport.advance(&mut timestep, 1.0), 0);
+    transport.set_paused(false);
+    assert_eq!(transport.advance(&mut timestep, 0.0), 0);
+}
+
+#[test]
+fn transport_steps_once_while_paused() {
+    let mut timestep = FixedTimestep::new(STEP, 4);
+    let mut transport = Transport::default();
+    transport.set_paused(true);
+    transport.step_once();
+    assert_eq!(transport.advance(&mut timestep, 1.0), 1);
+    assert_eq!(transport.advance(&mut timestep, 1.0), 0);
+}
+
+#[test]
+fn transport_scales_time() {
+    let mut timestep = FixedTimestep::new(STEP, 4);
+    let mut transport = Transport::default();
+    transport.set_time_scale(0.5);
+    assert_eq!(transport.advance(&mut timestep, STEP), 0);
+    assert_eq!(transport.advance(&mut timestep, STEP), 1);
+}